@@ -0,0 +1,117 @@
+use crate::audit::{self, AuditAction, AuditOutcome};
+use crate::keys::NetworkKeyIds;
+use crate::{history, memory, with_state, BitcoinWalletError, VaultId, VaultWalletState};
+use candid::{CandidType, Decode, Principal};
+pub use canister_common::access::*;
+use ic_cdk::api;
+use ic_cdk::bitcoin_canister::Network;
+use serde::Deserialize;
+
+#[derive(Default, CandidType, Deserialize)]
+pub struct InitArgs {
+    pub vault_manager: Option<Principal>,
+    /// Bitcoin network the wallet operates on; testnet when omitted.
+    pub network: Option<Network>,
+    /// Per-network key id allowlists overriding the management canister defaults. On upgrade,
+    /// key ids to add to the existing allowlists.
+    pub key_ids: Option<Vec<NetworkKeyIds>>,
+    /// ckBTC minter used to pay heirs that name an ICRC-1 account.
+    pub ckbtc_minter: Option<Principal>,
+    /// Guardian manager consulted when guardians vote to cancel a scheduled execution.
+    pub guardian_mgr: Option<Principal>,
+    /// Delay between `schedule_inheritance` and the broadcast; zero allows immediate execution.
+    pub grace_period_secs: Option<u64>,
+}
+
+impl AccessState for VaultWalletState {
+    fn access(&self) -> &AccessControl {
+        &self.access
+    }
+
+    fn access_mut(&mut self) -> &mut AccessControl {
+        &mut self.access
+    }
+
+    fn vault_manager_mut(&mut self) -> &mut Option<Principal> {
+        &mut self.vault_manager
+    }
+
+    fn log_rotation(&mut self, rotation: ManagerRotation) {
+        crate::memory::append_rotation(rotation);
+    }
+}
+
+impl From<AccessError> for BitcoinWalletError {
+    fn from(value: AccessError) -> Self {
+        match value {
+            AccessError::ManagerNotConfigured => BitcoinWalletError::ManagerNotConfigured,
+            AccessError::ManagerRoleReserved => BitcoinWalletError::ManagerRoleReserved,
+            AccessError::Paused => BitcoinWalletError::Paused,
+            AccessError::Unauthorized(caller) => BitcoinWalletError::Unauthorized(caller),
+        }
+    }
+}
+
+fn reject(err: AccessError) -> String {
+    BitcoinWalletError::from(err).into()
+}
+
+pub fn guard_admin() -> Result<(), String> {
+    let caller = api::msg_caller();
+    with_state(|state| ensure_admin(state, caller)).map_err(reject)
+}
+
+pub fn guard_auditor() -> Result<(), String> {
+    let caller = api::msg_caller();
+    with_state(|state| ensure_auditor(state, caller)).map_err(reject)
+}
+
+pub fn guard_pauser() -> Result<(), String> {
+    let caller = api::msg_caller();
+    with_state(|state| ensure_pauser(state, caller)).map_err(reject)
+}
+
+/// Rejections of principals holding a role are recorded in the audit log: guards reject
+/// without trapping, so the entry is committed even though the call itself fails. Anonymous
+/// and unknown callers are not logged, since anyone could grow the log that way.
+pub fn guard_vault_manager() -> Result<(), String> {
+    let caller = api::msg_caller();
+    with_state(|state| {
+        ensure_not_paused(state)?;
+        ensure_vault_manager(state, caller)
+    })
+    .map_err(|err| {
+        let reason = reject(err);
+        if with_state(|state| is_admin(state, caller) || holds_any_role(state, caller)) {
+            audit::record(None, AuditAction::AccessDenied, AuditOutcome::Failure(reason.clone()), None);
+        }
+        reason
+    })
+}
+
+pub fn guard_not_paused() -> Result<(), String> {
+    with_state(ensure_not_paused).map_err(reject)
+}
+
+/// Reads of one vault: its owner, auditors and the vault manager.
+pub fn guard_vault_reader() -> Result<(), String> {
+    ensure_vault_reader(false)
+}
+
+/// Reads of one vault's history: as `guard_vault_reader`, and heirs the vault has paid in ckBTC.
+pub fn guard_vault_history_reader() -> Result<(), String> {
+    ensure_vault_reader(true)
+}
+
+/// Guards cannot see a method's arguments, so the vault id is decoded from the first one.
+fn ensure_vault_reader(paid_heirs: bool) -> Result<(), String> {
+    let caller = api::msg_caller();
+    with_state(ensure_not_paused).map_err(reject)?;
+    let vault_id = Decode!(&api::msg_arg_data(), VaultId).map_err(|err| err.to_string())?;
+    let wallet = memory::wallet(vault_id).ok_or(BitcoinWalletError::VaultNotFound(vault_id))?;
+    let is_heir = || paid_heirs && history::paid_heirs(&memory::vault_history(vault_id)).any(|heir| heir == caller);
+    if wallet.owner != Some(caller) && !with_state(|state| can_audit(state, caller)) && !is_heir() {
+        return Err(BitcoinWalletError::Unauthorized(caller).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,183 @@
+use crate::{memory, VaultId};
+use candid::{CandidType, Principal};
+use canister_common::candid_storable;
+use ic_cdk::api;
+use serde::{Deserialize, Serialize};
+
+/// Upper bound on entries returned by a single `audit_log` page.
+pub const MAX_PAGE_SIZE: u64 = 100;
+
+#[derive(Clone, Copy, Debug, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub enum AuditAction {
+    AddressGenerated,
+    ExecutionAttempted,
+    ExecutionFailed,
+    ExecutionBroadcast,
+    ManagerChanged,
+    KeyMigrated,
+    LedgerRegistered,
+    TokensTransferred,
+    TranchePaid,
+    InheritanceScheduled,
+    CancelVoteCast,
+    InheritanceCancelled,
+    PolicyUpdated,
+    HeirsConfigured,
+    AccessDenied,
+    OwnerChanged,
+    CkbtcMinted,
+    VestingCancelled,
+    /// A payout kept failing and stopped retrying.
+    RetriesExhausted,
+    /// An execution waits on the timer for deposits to confirm.
+    ExecutionDeferred,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub enum AuditOutcome {
+    Success,
+    Failure(String),
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    #[serde(rename = "vaultId")]
+    pub vault_id: Option<VaultId>,
+    pub action: AuditAction,
+    pub caller: Principal,
+    pub timestamp: u64,
+    pub outcome: AuditOutcome,
+    /// Address, transaction id, new key or new manager, depending on `action`.
+    pub detail: Option<String>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct AuditQuery {
+    /// Restricts the page to one vault; `None` pages through the whole log.
+    #[serde(rename = "vaultId")]
+    pub vault_id: Option<VaultId>,
+    pub offset: u64,
+    pub limit: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    pub total: u64,
+}
+
+/// Appends an event for the current message's caller.
+pub fn record(vault_id: Option<VaultId>, action: AuditAction, outcome: AuditOutcome, detail: Option<String>) {
+    append(api::msg_caller(), vault_id, action, outcome, detail);
+}
+
+/// Records the outcome of work the canister started itself, such as a timer payout. The
+/// canister is logged as the caller.
+pub fn record_system<T, E: ToString>(
+    vault_id: VaultId,
+    action: AuditAction,
+    result: &Result<T, E>,
+    detail: impl FnOnce(&T) -> String,
+) {
+    let (outcome, detail) = match result {
+        Ok(value) => (AuditOutcome::Success, Some(detail(value))),
+        Err(err) => (AuditOutcome::Failure(err.to_string()), None),
+    };
+    let action = if result.is_ok() { action } else { failure_action(action) };
+    append(api::canister_self(), Some(vault_id), action, outcome, detail);
+}
+
+fn append(
+    caller: Principal,
+    vault_id: Option<VaultId>,
+    action: AuditAction,
+    outcome: AuditOutcome,
+    detail: Option<String>,
+) {
+    memory::append_audit(AuditEntry {
+        seq: 0,
+        vault_id,
+        action,
+        caller,
+        timestamp: api::time(),
+        outcome,
+        detail,
+    });
+}
+
+/// Records `action` for a successful vault operation, or its failure counterpart with the
+/// error message.
+pub fn record_result<T, E: ToString>(
+    vault_id: VaultId,
+    action: AuditAction,
+    result: &Result<T, E>,
+    detail: impl FnOnce(&T) -> String,
+) {
+    match result {
+        Ok(value) => record(Some(vault_id), action, AuditOutcome::Success, Some(detail(value))),
+        Err(err) => record(Some(vault_id), failure_action(action), AuditOutcome::Failure(err.to_string()), None),
+    }
+}
+
+fn failure_action(action: AuditAction) -> AuditAction {
+    match action {
+        AuditAction::ExecutionBroadcast => AuditAction::ExecutionFailed,
+        other => other,
+    }
+}
+
+pub fn page(query: &AuditQuery) -> AuditPage {
+    let limit = query.limit.min(MAX_PAGE_SIZE);
+    match query.vault_id {
+        Some(vault_id) => AuditPage {
+            entries: memory::vault_audit_range(vault_id, query.offset, limit),
+            total: memory::vault_audit_len(vault_id),
+        },
+        None => AuditPage {
+            entries: memory::audit_range(query.offset, limit),
+            total: memory::audit_len(),
+        },
+    }
+}
+
+candid_storable!(AuditEntry, "audit entry");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(vault_id: Option<VaultId>, action: AuditAction) -> AuditEntry {
+        AuditEntry {
+            seq: 0,
+            vault_id,
+            action,
+            caller: Principal::from_slice(&[4]),
+            timestamp: 1,
+            outcome: AuditOutcome::Success,
+            detail: None,
+        }
+    }
+
+    #[test]
+    fn pages_follow_vault_index() {
+        memory::append_audit(entry(Some(1), AuditAction::AddressGenerated));
+        memory::append_audit(entry(Some(2), AuditAction::AddressGenerated));
+        memory::append_audit(entry(None, AuditAction::ManagerChanged));
+        memory::append_audit(entry(Some(1), AuditAction::ExecutionAttempted));
+        memory::append_audit(entry(Some(1), AuditAction::ExecutionFailed));
+
+        let first = page(&AuditQuery { vault_id: Some(1), offset: 0, limit: 2 });
+        assert_eq!(first.total, 3);
+        assert_eq!(first.entries.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![0, 3]);
+
+        let second = page(&AuditQuery { vault_id: Some(1), offset: 2, limit: 2 });
+        assert_eq!(second.entries.len(), 1);
+        assert_eq!(second.entries[0].action, AuditAction::ExecutionFailed);
+
+        let all = page(&AuditQuery { vault_id: None, offset: 1, limit: 1_000 });
+        assert_eq!(all.total, 5);
+        assert_eq!(all.entries.len(), 4);
+        assert_eq!(all.entries[1].action, AuditAction::ManagerChanged);
+    }
+}
@@ -0,0 +1,452 @@
+//! Seams between the wallet logic and the system APIs it spends through: the Bitcoin canister,
+//! threshold ECDSA, `guardian_mgr` and ICRC-1 ledgers. Endpoints pass the IC implementations;
+//! tests pass the in-memory ones in [`mock`].
+
+use crate::icrc::{Account, LedgerTransfer, TokenPayoutPlan, TransferFailure};
+use crate::{guardians, icrc, metrics, BitcoinWalletError, VaultId};
+use candid::{Nat, Principal};
+use ic_cdk::api;
+use ic_cdk::bitcoin_canister::{
+    bitcoin_get_current_fee_percentiles, bitcoin_get_utxos, bitcoin_send_transaction, cost_get_current_fee_percentiles,
+    cost_get_utxos, cost_send_transaction, GetCurrentFeePercentilesRequest, GetUtxosRequest, GetUtxosResponse, Network,
+    SendTransactionRequest, UtxosFilter,
+};
+use ic_cdk::management_canister::{
+    cost_sign_with_ecdsa, ecdsa_public_key, sign_with_ecdsa, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgs,
+    SignWithEcdsaArgs,
+};
+
+pub trait BitcoinBackend {
+    async fn get_utxos(
+        &self,
+        network: Network,
+        address: &str,
+        min_confirmations: u32,
+    ) -> Result<GetUtxosResponse, BitcoinWalletError>;
+
+    /// Fee percentiles in millisatoshi per byte, as reported by the Bitcoin canister.
+    async fn fee_percentiles(&self, network: Network) -> Result<Vec<u64>, BitcoinWalletError>;
+
+    async fn send_transaction(&self, network: Network, transaction: Vec<u8>) -> Result<(), BitcoinWalletError>;
+
+    /// Cycles attached to each call above.
+    fn get_utxos_cost(&self, network: Network) -> u128;
+
+    fn fee_percentiles_cost(&self, network: Network) -> u128;
+
+    fn send_transaction_cost(&self, network: Network, transaction_bytes: usize) -> u128;
+
+    /// Cycles the canister can still attach to calls.
+    fn cycles_available(&self) -> u128;
+}
+
+pub trait Signer {
+    /// SEC1-compressed public key for `derivation_path` under `key_id`.
+    async fn public_key(&self, key_id: &str, derivation_path: &[Vec<u8>]) -> Result<Vec<u8>, BitcoinWalletError>;
+
+    /// 64-byte compact ECDSA signature over `message_hash`.
+    async fn sign(
+        &self,
+        key_id: &str,
+        derivation_path: &[Vec<u8>],
+        message_hash: &[u8; 32],
+    ) -> Result<Vec<u8>, BitcoinWalletError>;
+
+    /// Cycles attached to each signature under `key_id`.
+    fn sign_cost(&self, key_id: &str) -> Result<u128, BitcoinWalletError>;
+}
+
+pub trait GuardianApi {
+    /// Fails unless enough guardians have released `vault_id`.
+    async fn ensure_threshold_met(&self, vault_id: VaultId) -> Result<(), BitcoinWalletError>;
+}
+
+pub trait Ledgers {
+    async fn fee(&self, ledger: Principal) -> Result<Nat, BitcoinWalletError>;
+
+    async fn balance_of(&self, ledger: Principal, account: &Account) -> Result<Nat, BitcoinWalletError>;
+
+    /// Number of blocks the ledger has recorded.
+    async fn log_length(&self, ledger: Principal) -> Result<Nat, BitcoinWalletError>;
+
+    /// Transfers out of `from` recorded from block `start` on.
+    async fn transfers_from(&self, ledger: Principal, from: &Account, start: Nat)
+        -> Result<Vec<LedgerTransfer>, String>;
+
+    /// Sends planned transfer `index` out of the vault subaccount, returning its block index.
+    async fn transfer(
+        &self,
+        ledger: Principal,
+        vault_id: VaultId,
+        plan: &TokenPayoutPlan,
+        index: usize,
+    ) -> Result<Nat, TransferFailure>;
+}
+
+/// The management canister's Bitcoin API.
+pub struct IcBitcoin;
+
+/// Threshold ECDSA keys held by the subnet for this canister.
+pub struct IcSigner;
+
+/// The configured `guardian_mgr` canister.
+pub struct IcGuardians;
+
+/// ICRC-1 ledger canisters, addressed by principal.
+pub struct IcLedgers;
+
+fn ecdsa_key_id(key_id: &str) -> EcdsaKeyId {
+    EcdsaKeyId {
+        curve: EcdsaCurve::Secp256k1,
+        name: key_id.to_string(),
+    }
+}
+
+impl BitcoinBackend for IcBitcoin {
+    async fn get_utxos(
+        &self,
+        network: Network,
+        address: &str,
+        min_confirmations: u32,
+    ) -> Result<GetUtxosResponse, BitcoinWalletError> {
+        bitcoin_get_utxos(&GetUtxosRequest {
+            network,
+            address: address.to_string(),
+            filter: Some(UtxosFilter::MinConfirmations(min_confirmations)),
+        })
+        .await
+        .inspect_err(|err| metrics::record_bitcoin_error("bitcoin_get_utxos", err))
+        .map_err(|err| BitcoinWalletError::Network(format!("bitcoin_get_utxos failed: {err:?}")))
+    }
+
+    async fn fee_percentiles(&self, network: Network) -> Result<Vec<u64>, BitcoinWalletError> {
+        bitcoin_get_current_fee_percentiles(&GetCurrentFeePercentilesRequest { network })
+            .await
+            .inspect_err(|err| metrics::record_bitcoin_error("bitcoin_get_current_fee_percentiles", err))
+            .map_err(|err| BitcoinWalletError::Network(format!("fee percentiles failed: {err:?}")))
+    }
+
+    async fn send_transaction(&self, network: Network, transaction: Vec<u8>) -> Result<(), BitcoinWalletError> {
+        bitcoin_send_transaction(&SendTransactionRequest { network, transaction })
+            .await
+            .inspect_err(|err| metrics::record_bitcoin_error("bitcoin_send_transaction", err))
+            .map_err(|err| BitcoinWalletError::Network(format!("bitcoin_send_transaction failed: {err:?}")))
+    }
+
+    fn get_utxos_cost(&self, network: Network) -> u128 {
+        cost_get_utxos(&GetUtxosRequest {
+            network,
+            address: String::new(),
+            filter: None,
+        })
+    }
+
+    fn fee_percentiles_cost(&self, network: Network) -> u128 {
+        cost_get_current_fee_percentiles(&GetCurrentFeePercentilesRequest { network })
+    }
+
+    fn send_transaction_cost(&self, network: Network, transaction_bytes: usize) -> u128 {
+        cost_send_transaction(&SendTransactionRequest {
+            network,
+            transaction: vec![0; transaction_bytes],
+        })
+    }
+
+    fn cycles_available(&self) -> u128 {
+        api::canister_liquid_cycle_balance()
+    }
+}
+
+impl Signer for IcSigner {
+    async fn public_key(&self, key_id: &str, derivation_path: &[Vec<u8>]) -> Result<Vec<u8>, BitcoinWalletError> {
+        ecdsa_public_key(&EcdsaPublicKeyArgs {
+            canister_id: Some(api::canister_self()),
+            derivation_path: derivation_path.to_vec(),
+            key_id: ecdsa_key_id(key_id),
+        })
+        .await
+        .map(|response| response.public_key)
+        .map_err(|err| BitcoinWalletError::Crypto(format!("ecdsa_public_key failed: {err:?}")))
+    }
+
+    async fn sign(
+        &self,
+        key_id: &str,
+        derivation_path: &[Vec<u8>],
+        message_hash: &[u8; 32],
+    ) -> Result<Vec<u8>, BitcoinWalletError> {
+        let result = sign_with_ecdsa(&SignWithEcdsaArgs {
+            message_hash: message_hash.to_vec(),
+            derivation_path: derivation_path.to_vec(),
+            key_id: ecdsa_key_id(key_id),
+        })
+        .await;
+        metrics::record_signature(result.is_ok());
+        result
+            .map(|response| response.signature)
+            .map_err(|err| BitcoinWalletError::Crypto(format!("sign_with_ecdsa failed: {err:?}")))
+    }
+
+    fn sign_cost(&self, key_id: &str) -> Result<u128, BitcoinWalletError> {
+        cost_sign_with_ecdsa(&SignWithEcdsaArgs {
+            message_hash: Vec::new(),
+            derivation_path: Vec::new(),
+            key_id: ecdsa_key_id(key_id),
+        })
+        .map_err(|err| BitcoinWalletError::Crypto(format!("sign_with_ecdsa cost unavailable: {err:?}")))
+    }
+}
+
+impl GuardianApi for IcGuardians {
+    async fn ensure_threshold_met(&self, vault_id: VaultId) -> Result<(), BitcoinWalletError> {
+        guardians::ensure_threshold_met(vault_id).await
+    }
+}
+
+impl Ledgers for IcLedgers {
+    async fn fee(&self, ledger: Principal) -> Result<Nat, BitcoinWalletError> {
+        icrc::fee(ledger).await
+    }
+
+    async fn balance_of(&self, ledger: Principal, account: &Account) -> Result<Nat, BitcoinWalletError> {
+        icrc::balance_of(ledger, account).await
+    }
+
+    async fn log_length(&self, ledger: Principal) -> Result<Nat, BitcoinWalletError> {
+        icrc::log_length(ledger).await
+    }
+
+    async fn transfers_from(
+        &self,
+        ledger: Principal,
+        from: &Account,
+        start: Nat,
+    ) -> Result<Vec<LedgerTransfer>, String> {
+        icrc::transfers_from(ledger, from, start).await
+    }
+
+    async fn transfer(
+        &self,
+        ledger: Principal,
+        vault_id: VaultId,
+        plan: &TokenPayoutPlan,
+        index: usize,
+    ) -> Result<Nat, TransferFailure> {
+        icrc::transfer(ledger, vault_id, plan, index).await
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use bitcoin::secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+    use ic_cdk::bitcoin_canister::{Outpoint, Utxo};
+    use sha2::{Digest, Sha256};
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+
+    pub const GET_UTXOS_COST: u128 = 1_000;
+    pub const FEE_PERCENTILES_COST: u128 = 100;
+    pub const SEND_COST_PER_BYTE: u128 = 10;
+    pub const SIGN_COST: u128 = 5_000;
+
+    /// A fixed UTXO set per address, filtered by confirmations against `tip_height`, and a
+    /// record of every broadcast transaction.
+    #[derive(Default)]
+    pub struct MockBitcoin {
+        pub utxos: Vec<(String, Utxo)>,
+        pub fee_percentiles: Vec<u64>,
+        pub tip_height: u32,
+        pub cycles: u128,
+        pub sent: RefCell<Vec<Vec<u8>>>,
+    }
+
+    impl MockBitcoin {
+        /// Funds `address` with one UTXO per value, with deterministic outpoints.
+        pub fn fund(&mut self, address: &str, values: &[u64]) {
+            for value in values {
+                let seed = self.utxos.len() as u8;
+                self.utxos.push((
+                    address.to_string(),
+                    Utxo {
+                        outpoint: Outpoint {
+                            txid: vec![seed + 1; 32],
+                            vout: u32::from(seed),
+                        },
+                        value: *value,
+                        height: 100,
+                    },
+                ));
+            }
+        }
+    }
+
+    impl BitcoinBackend for MockBitcoin {
+        async fn get_utxos(
+            &self,
+            _network: Network,
+            address: &str,
+            min_confirmations: u32,
+        ) -> Result<GetUtxosResponse, BitcoinWalletError> {
+            let confirmations = |utxo: &Utxo| (self.tip_height + 1).saturating_sub(utxo.height);
+            Ok(GetUtxosResponse {
+                utxos: self
+                    .utxos
+                    .iter()
+                    .filter(|(owner, utxo)| owner == address && confirmations(utxo) >= min_confirmations)
+                    .map(|(_, utxo)| utxo.clone())
+                    .collect(),
+                tip_block_hash: vec![0; 32],
+                tip_height: self.tip_height,
+                next_page: None,
+            })
+        }
+
+        async fn fee_percentiles(&self, _network: Network) -> Result<Vec<u64>, BitcoinWalletError> {
+            Ok(self.fee_percentiles.clone())
+        }
+
+        async fn send_transaction(&self, _network: Network, transaction: Vec<u8>) -> Result<(), BitcoinWalletError> {
+            self.sent.borrow_mut().push(transaction);
+            Ok(())
+        }
+
+        fn get_utxos_cost(&self, _network: Network) -> u128 {
+            GET_UTXOS_COST
+        }
+
+        fn fee_percentiles_cost(&self, _network: Network) -> u128 {
+            FEE_PERCENTILES_COST
+        }
+
+        fn send_transaction_cost(&self, _network: Network, transaction_bytes: usize) -> u128 {
+            SEND_COST_PER_BYTE * transaction_bytes as u128
+        }
+
+        fn cycles_available(&self) -> u128 {
+            self.cycles
+        }
+    }
+
+    /// Real secp256k1 keys derived from a seed, the key id and the derivation path.
+    pub struct MockSigner {
+        pub seed: [u8; 32],
+    }
+
+    impl MockSigner {
+        pub fn secret_key(&self, key_id: &str, derivation_path: &[Vec<u8>]) -> SecretKey {
+            let mut hasher = Sha256::new();
+            hasher.update(self.seed);
+            hasher.update(key_id.as_bytes());
+            for segment in derivation_path {
+                hasher.update(segment);
+            }
+            SecretKey::from_slice(&hasher.finalize()).expect("hash is a valid secret key")
+        }
+    }
+
+    impl Signer for MockSigner {
+        async fn public_key(&self, key_id: &str, derivation_path: &[Vec<u8>]) -> Result<Vec<u8>, BitcoinWalletError> {
+            let secret = self.secret_key(key_id, derivation_path);
+            Ok(PublicKey::from_secret_key(&Secp256k1::new(), &secret).serialize().to_vec())
+        }
+
+        async fn sign(
+            &self,
+            key_id: &str,
+            derivation_path: &[Vec<u8>],
+            message_hash: &[u8; 32],
+        ) -> Result<Vec<u8>, BitcoinWalletError> {
+            let secret = self.secret_key(key_id, derivation_path);
+            let message = Message::from_digest(*message_hash);
+            Ok(Secp256k1::new().sign_ecdsa(&message, &secret).serialize_compact().to_vec())
+        }
+
+        fn sign_cost(&self, _key_id: &str) -> Result<u128, BitcoinWalletError> {
+            Ok(SIGN_COST)
+        }
+    }
+
+    /// Answers the threshold check with `submitted` shares and whether that met the threshold.
+    pub struct MockGuardians {
+        pub submitted: u64,
+        pub threshold_met: bool,
+    }
+
+    impl GuardianApi for MockGuardians {
+        async fn ensure_threshold_met(&self, vault_id: VaultId) -> Result<(), BitcoinWalletError> {
+            if !self.threshold_met {
+                return Err(BitcoinWalletError::GuardianThresholdNotMet {
+                    vault_id,
+                    submitted: self.submitted,
+                });
+            }
+            Ok(())
+        }
+    }
+
+    /// Ledgers with a fixed fee and balance each, recording every call and transfer. Each
+    /// transfer out of a vault also lands in `blocks`; transfers created before
+    /// `reject_before` are refused as `TooOld`.
+    #[derive(Default)]
+    pub struct MockLedgers {
+        pub fee: u64,
+        pub balances: BTreeMap<Principal, u64>,
+        pub reject_before: u64,
+        pub calls: RefCell<Vec<&'static str>>,
+        pub transfers: RefCell<Vec<(Principal, Account, Nat)>>,
+        pub blocks: RefCell<Vec<LedgerTransfer>>,
+    }
+
+    impl Ledgers for MockLedgers {
+        async fn fee(&self, _ledger: Principal) -> Result<Nat, BitcoinWalletError> {
+            self.calls.borrow_mut().push("icrc1_fee");
+            Ok(Nat::from(self.fee))
+        }
+
+        async fn balance_of(&self, ledger: Principal, _account: &Account) -> Result<Nat, BitcoinWalletError> {
+            self.calls.borrow_mut().push("icrc1_balance_of");
+            Ok(Nat::from(self.balances.get(&ledger).copied().unwrap_or_default()))
+        }
+
+        async fn log_length(&self, _ledger: Principal) -> Result<Nat, BitcoinWalletError> {
+            self.calls.borrow_mut().push("get_transactions");
+            Ok(Nat::from(self.blocks.borrow().len()))
+        }
+
+        async fn transfers_from(
+            &self,
+            _ledger: Principal,
+            _from: &Account,
+            start: Nat,
+        ) -> Result<Vec<LedgerTransfer>, String> {
+            self.calls.borrow_mut().push("get_transactions");
+            let blocks = self.blocks.borrow();
+            Ok(blocks.iter().filter(|block| block.block_index >= start).cloned().collect())
+        }
+
+        async fn transfer(
+            &self,
+            ledger: Principal,
+            vault_id: VaultId,
+            plan: &TokenPayoutPlan,
+            index: usize,
+        ) -> Result<Nat, TransferFailure> {
+            self.calls.borrow_mut().push("icrc1_transfer");
+            if plan.created_at_time < self.reject_before {
+                return Err(TransferFailure::TooOld);
+            }
+            let planned = &plan.transfers[index];
+            self.transfers
+                .borrow_mut()
+                .push((ledger, planned.account.clone(), planned.amount.clone()));
+            let mut blocks = self.blocks.borrow_mut();
+            let block_index = blocks.last().map_or(Nat::from(0u64), |block| block.block_index.clone() + 1u64);
+            blocks.push(LedgerTransfer {
+                block_index: block_index.clone(),
+                memo: Some(icrc::transfer_memo(vault_id, index).to_vec()),
+            });
+            Ok(block_index)
+        }
+    }
+}
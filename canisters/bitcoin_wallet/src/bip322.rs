@@ -0,0 +1,152 @@
+use bitcoin::base64::{engine::general_purpose::STANDARD, Engine};
+use bitcoin::blockdata::opcodes::all::OP_RETURN;
+use bitcoin::consensus::Encodable;
+use bitcoin::hashes::Hash;
+use bitcoin::key::PublicKey;
+use bitcoin::script::{Builder, PushBytesBuf};
+use bitcoin::sighash::{EcdsaSighashType, SighashCache};
+use bitcoin::{
+    absolute::LockTime, transaction::Version, Amount, OutPoint, Script, ScriptBuf, Sequence, Transaction, TxIn,
+    TxOut, Txid, Witness,
+};
+use sha2::{Digest, Sha256};
+
+const MESSAGE_TAG: &[u8] = b"BIP0322-signed-message";
+
+/// Tagged hash committing to the signed message, as defined by BIP-322.
+pub fn message_hash(message: &[u8]) -> [u8; 32] {
+    let tag = Sha256::digest(MESSAGE_TAG);
+    let mut hasher = Sha256::new();
+    hasher.update(tag);
+    hasher.update(tag);
+    hasher.update(message);
+    hasher.finalize().into()
+}
+
+/// The virtual `to_spend` transaction whose only output is locked to the signing address.
+pub fn to_spend(script_pub_key: &Script, message: &[u8]) -> Transaction {
+    let push = PushBytesBuf::from(message_hash(message));
+    let script_sig = Builder::new().push_int(0).push_slice(push).into_script();
+    Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0xFFFF_FFFF),
+            script_sig,
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: script_pub_key.to_owned(),
+        }],
+    }
+}
+
+/// The virtual `to_sign` transaction spending `to_spend` into an `OP_RETURN` output.
+pub fn to_sign(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.txid(), 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        }],
+    }
+}
+
+/// Digest the P2WPKH key has to sign for a BIP-322 simple signature over `message`.
+pub fn p2wpkh_sighash(public_key: &PublicKey, message: &[u8]) -> Result<[u8; 32], String> {
+    let script_pub_key = ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash().ok_or("uncompressed public key")?);
+    let to_sign = to_sign(&to_spend(&script_pub_key, message));
+    SighashCache::new(&to_sign)
+        .p2wpkh_signature_hash(0, &script_pub_key, Amount::ZERO, EcdsaSighashType::All)
+        .map(|sighash| sighash.to_byte_array())
+        .map_err(|err| err.to_string())
+}
+
+/// Encodes the `to_sign` witness as a base64 BIP-322 simple signature.
+pub fn encode_simple_signature(signature: Vec<u8>, public_key: &PublicKey) -> String {
+    let mut witness = Witness::new();
+    witness.push(signature);
+    witness.push(public_key.to_bytes());
+    let mut bytes = Vec::new();
+    witness
+        .consensus_encode(&mut bytes)
+        .expect("in-memory writers do not fail");
+    STANDARD.encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::consensus::Decodable;
+    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::secp256k1::{ecdsa::Signature, Message, Secp256k1};
+    use bitcoin::{Address, Network, PrivateKey};
+    use std::str::FromStr;
+
+    const WIF: &str = "L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k";
+    const ADDRESS: &str = "bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l";
+
+    fn verify_simple(address: &Address, message: &[u8], signature: &str) -> bool {
+        let bytes = STANDARD.decode(signature).expect("base64");
+        let witness = Witness::consensus_decode(&mut bytes.as_slice()).expect("witness");
+        let (Some(sig), Some(key)) = (witness.nth(0), witness.nth(1)) else {
+            return false;
+        };
+        let public_key = PublicKey::from_slice(key).expect("public key");
+        if Address::p2wpkh(&public_key, Network::Bitcoin).unwrap() != *address {
+            return false;
+        }
+        let (hash_type, der) = sig.split_last().expect("signature");
+        assert_eq!(*hash_type, EcdsaSighashType::All as u8);
+        let digest = p2wpkh_sighash(&public_key, message).expect("sighash");
+        let signature = Signature::from_der(der).expect("der");
+        Secp256k1::verification_only()
+            .verify_ecdsa(&Message::from_digest(digest), &signature, &public_key.inner)
+            .is_ok()
+    }
+
+    #[test]
+    fn message_hash_matches_bip322_vectors() {
+        assert_eq!(
+            message_hash(b"").to_vec(),
+            Vec::<u8>::from_hex("c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1").unwrap()
+        );
+        assert_eq!(
+            message_hash(b"Hello World").to_vec(),
+            Vec::<u8>::from_hex("f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a").unwrap()
+        );
+    }
+
+    #[test]
+    fn signed_message_verifies_against_address() {
+        let address = Address::from_str(ADDRESS).unwrap().assume_checked();
+        let private_key = PrivateKey::from_wif(WIF).unwrap();
+        let secp = Secp256k1::new();
+        let public_key = private_key.public_key(&secp);
+
+        let digest = p2wpkh_sighash(&public_key, b"Hello World").unwrap();
+        let mut signature = secp
+            .sign_ecdsa(&Message::from_digest(digest), &private_key.inner)
+            .serialize_der()
+            .to_vec();
+        signature.push(EcdsaSighashType::All as u8);
+        let encoded = encode_simple_signature(signature, &public_key);
+
+        assert!(verify_simple(&address, b"Hello World", &encoded));
+        assert!(!verify_simple(&address, b"Hello World!", &encoded));
+        assert!(verify_simple(
+            &address,
+            b"Hello World",
+            "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI="
+        ));
+    }
+}
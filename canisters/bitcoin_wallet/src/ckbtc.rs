@@ -0,0 +1,240 @@
+use crate::grace::CONFIRMATION_POLL_NANOS;
+use crate::icrc::Account;
+use crate::BitcoinWalletError;
+use bitcoin::hashes::Hash;
+use bitcoin::Txid;
+use candid::{CandidType, Principal, Reserved};
+use ic_cdk::bitcoin_canister::Utxo;
+use ic_cdk::call::Call;
+use serde::{Deserialize, Serialize};
+
+/// Argument shared by the minter's `get_btc_address` and `update_balance`.
+#[derive(CandidType)]
+struct MinterAccountArg {
+    owner: Option<Principal>,
+    subaccount: Option<serde_bytes::ByteBuf>,
+}
+
+impl From<&Account> for MinterAccountArg {
+    fn from(account: &Account) -> Self {
+        MinterAccountArg {
+            owner: Some(account.owner),
+            subaccount: account.subaccount.clone(),
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum UtxoStatus {
+    ValueTooSmall(Reserved),
+    Tainted(Reserved),
+    Checked(Reserved),
+    Minted {
+        block_index: u64,
+        minted_amount: u64,
+        utxo: Utxo,
+    },
+}
+
+#[derive(CandidType, Deserialize)]
+enum UpdateBalanceError {
+    GenericError { error_code: u64, error_message: String },
+    TemporarilyUnavailable(String),
+    AlreadyProcessing,
+    NoNewUtxos(Reserved),
+}
+
+/// What the minter reported when notified of a deposit.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub enum DepositStatus {
+    /// ckBTC minted to the heir, in satoshis.
+    Minted(u64),
+    /// The deposit is not credited yet, usually because it lacks confirmations. The wallet
+    /// keeps a `PendingMint` and asks the minter again until it is.
+    Pending(String),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct CkbtcDeposit {
+    pub account: Account,
+    #[serde(rename = "depositAddress")]
+    pub deposit_address: String,
+    #[serde(rename = "amountSats")]
+    pub amount_sats: u64,
+    pub status: DepositStatus,
+}
+
+/// How long the timer keeps asking the minter to credit a deposit before giving up on it.
+pub const MAX_MINT_WAIT_NANOS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+
+/// A ckBTC payout broadcast to the minter's deposit address and not minted yet.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct PendingMint {
+    pub deposit: CkbtcDeposit,
+    /// Payout transaction that funded the deposit.
+    #[serde(rename = "txId")]
+    pub tx_id: String,
+    #[serde(rename = "recordedAt")]
+    pub recorded_at: u64,
+    #[serde(rename = "nextAttemptAt")]
+    pub next_attempt_at: u64,
+    pub attempts: u32,
+}
+
+impl PendingMint {
+    /// First attempt one poll interval after broadcast: the minter needs confirmations.
+    pub fn new(deposit: CkbtcDeposit, tx_id: String, now: u64) -> Self {
+        PendingMint {
+            deposit,
+            tx_id,
+            recorded_at: now,
+            next_attempt_at: now.saturating_add(CONFIRMATION_POLL_NANOS),
+            attempts: 0,
+        }
+    }
+
+    pub fn is_due(&self, now: u64) -> bool {
+        now >= self.next_attempt_at
+    }
+
+    pub fn expired(&self, now: u64) -> bool {
+        now >= self.recorded_at.saturating_add(MAX_MINT_WAIT_NANOS)
+    }
+
+    /// Records a failed attempt and schedules the next one.
+    pub fn defer(&mut self, reason: String, now: u64) {
+        self.attempts += 1;
+        self.deposit.status = DepositStatus::Pending(reason);
+        self.next_attempt_at = now.saturating_add(CONFIRMATION_POLL_NANOS);
+    }
+}
+
+/// The minter's Bitcoin deposit address for `account`.
+pub async fn deposit_address(minter: Principal, account: &Account) -> Result<String, BitcoinWalletError> {
+    Call::unbounded_wait(minter, "get_btc_address")
+        .with_arg(MinterAccountArg::from(account))
+        .await
+        .map_err(|err| BitcoinWalletError::Minter(format!("get_btc_address failed: {err}")))?
+        .candid::<String>()
+        .map_err(|err| BitcoinWalletError::Minter(format!("get_btc_address returned: {err}")))
+}
+
+/// A deposit UTXO the minter credited as ckBTC.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MintedUtxo {
+    /// Transaction that created the UTXO, in display order.
+    pub tx_id: String,
+    pub amount: u64,
+}
+
+/// Satoshis the minter credited from outputs of `tx_id`, if any.
+pub fn minted_from(minted: &[MintedUtxo], tx_id: &str) -> Option<u64> {
+    minted
+        .iter()
+        .filter(|utxo| utxo.tx_id == tx_id)
+        .map(|utxo| utxo.amount)
+        .reduce(|total, amount| total + amount)
+}
+
+/// Asks the minter to credit any deposits to `account`'s address. One call can mint deposits
+/// from several payouts, so the reply lists every UTXO minted; an error explains why nothing
+/// was.
+pub async fn notify_deposit(minter: Principal, account: &Account) -> Result<Vec<MintedUtxo>, String> {
+    let response = Call::unbounded_wait(minter, "update_balance")
+        .with_arg(MinterAccountArg::from(account))
+        .await
+        .map_err(|err| err.to_string())
+        .and_then(|response| {
+            response
+                .candid::<Result<Vec<UtxoStatus>, UpdateBalanceError>>()
+                .map_err(|err| err.to_string())
+        });
+    match response {
+        Ok(Ok(statuses)) => Ok(minted_utxos(&statuses)),
+        Ok(Err(err)) => Err(update_balance_error(&err)),
+        Err(err) => Err(format!("update_balance failed: {err}")),
+    }
+}
+
+fn minted_utxos(statuses: &[UtxoStatus]) -> Vec<MintedUtxo> {
+    statuses
+        .iter()
+        .filter_map(|status| match status {
+            UtxoStatus::Minted { minted_amount, utxo, .. } => Some(MintedUtxo {
+                tx_id: Txid::from_slice(&utxo.outpoint.txid).ok()?.to_string(),
+                amount: *minted_amount,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+fn update_balance_error(err: &UpdateBalanceError) -> String {
+    match err {
+        UpdateBalanceError::GenericError { error_code, error_message } => format!("error {error_code}: {error_message}"),
+        UpdateBalanceError::TemporarilyUnavailable(reason) => format!("minter unavailable: {reason}"),
+        UpdateBalanceError::AlreadyProcessing => "minter is already processing this account".into(),
+        UpdateBalanceError::NoNewUtxos(_) => "deposit not yet confirmed".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ic_cdk::bitcoin_canister::Outpoint;
+
+    fn minted(block_index: u64, txid: u8, vout: u32, minted_amount: u64) -> UtxoStatus {
+        UtxoStatus::Minted {
+            block_index,
+            minted_amount,
+            utxo: Utxo {
+                outpoint: Outpoint {
+                    txid: vec![txid; 32],
+                    vout,
+                },
+                value: minted_amount + 10,
+                height: 100,
+            },
+        }
+    }
+
+    #[test]
+    fn minted_amounts_are_matched_by_transaction() {
+        let statuses = vec![
+            UtxoStatus::Checked(Reserved),
+            minted(3, 1, 0, 40_000),
+            minted(4, 1, 1, 2_000),
+            minted(5, 2, 0, 9_000),
+        ];
+        let utxos = minted_utxos(&statuses);
+        let first = Txid::from_byte_array([1; 32]).to_string();
+        let second = Txid::from_byte_array([2; 32]).to_string();
+        assert_eq!(minted_from(&utxos, &first), Some(42_000));
+        assert_eq!(minted_from(&utxos, &second), Some(9_000));
+        assert_eq!(minted_from(&utxos, &"cd".repeat(32)), None);
+        assert!(minted_utxos(&[UtxoStatus::Checked(Reserved)]).is_empty());
+    }
+
+    #[test]
+    fn pending_mints_are_polled_until_they_expire() {
+        let deposit = CkbtcDeposit {
+            account: Account {
+                owner: Principal::anonymous(),
+                subaccount: None,
+            },
+            deposit_address: "tb1qminter".into(),
+            amount_sats: 50_000,
+            status: DepositStatus::Pending("awaiting confirmations".into()),
+        };
+        let mut mint = PendingMint::new(deposit, "ab".repeat(32), 100);
+        assert!(!mint.is_due(100));
+        assert!(mint.is_due(100 + CONFIRMATION_POLL_NANOS));
+
+        mint.defer("deposit not yet confirmed".into(), 100 + CONFIRMATION_POLL_NANOS);
+        assert_eq!(mint.attempts, 1);
+        assert_eq!(mint.next_attempt_at, 100 + 2 * CONFIRMATION_POLL_NANOS);
+        assert!(!mint.expired(100 + MAX_MINT_WAIT_NANOS - 1));
+        assert!(mint.expired(100 + MAX_MINT_WAIT_NANOS));
+    }
+}
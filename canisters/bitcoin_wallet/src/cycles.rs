@@ -0,0 +1,192 @@
+//! Cycles pricing and per-vault accounting for the system APIs a spend goes through.
+
+use crate::backend::{BitcoinBackend, Signer};
+use crate::{memory, BitcoinWalletError, VaultId, VaultWallet};
+use candid::CandidType;
+use ic_cdk::bitcoin_canister::{GetUtxosResponse, Network};
+use serde::Deserialize;
+use std::cell::Cell;
+
+#[derive(CandidType, Deserialize)]
+pub enum Operation {
+    Inheritance { inputs: u64, outputs: u64 },
+    KeyMigration { inputs: u64 },
+    SignMessage,
+}
+
+/// Upper bound on a signed P2WPKH transaction's serialized size, witnesses included.
+pub fn estimated_tx_bytes(inputs: usize, outputs: usize) -> usize {
+    12 + inputs * 150 + outputs * 43
+}
+
+/// Cycles `operation` attaches to system API calls when run against `wallet`.
+pub fn estimate<B: BitcoinBackend, S: Signer>(
+    backend: &B,
+    signer: &S,
+    wallet: &VaultWallet,
+    operation: &Operation,
+) -> Result<u128, BitcoinWalletError> {
+    let lookups = backend.get_utxos_cost(wallet.network) + backend.fee_percentiles_cost(wallet.network);
+    match *operation {
+        Operation::Inheritance { inputs, outputs } => {
+            Ok(lookups + signing_cost(backend, signer, wallet, inputs as usize, outputs as usize)?)
+        }
+        Operation::KeyMigration { inputs } => Ok(lookups + signing_cost(backend, signer, wallet, inputs as usize, 1)?),
+        Operation::SignMessage => signer.sign_cost(&wallet.key_id),
+    }
+}
+
+/// What a spend still costs once its UTXOs are known: one signature per input and the broadcast.
+pub fn signing_cost<B: BitcoinBackend, S: Signer>(
+    backend: &B,
+    signer: &S,
+    wallet: &VaultWallet,
+    inputs: usize,
+    outputs: usize,
+) -> Result<u128, BitcoinWalletError> {
+    let signatures = signer.sign_cost(&wallet.key_id)? * inputs as u128;
+    Ok(signatures + backend.send_transaction_cost(wallet.network, estimated_tx_bytes(inputs, outputs)))
+}
+
+/// Refuses to start work the canister cannot pay for, so a spend never stops halfway through signing.
+pub fn ensure_available(needed: u128, available: u128) -> Result<(), BitcoinWalletError> {
+    if available < needed {
+        return Err(BitcoinWalletError::InsufficientCycles { needed, available });
+    }
+    Ok(())
+}
+
+/// Sums the cycles attached on behalf of one vault and adds them to its running total when
+/// dropped, so calls made before a failure are still counted.
+pub struct CyclesMeter {
+    vault_id: VaultId,
+    spent: Cell<u128>,
+}
+
+impl CyclesMeter {
+    pub fn new(vault_id: VaultId) -> Self {
+        Self {
+            vault_id,
+            spent: Cell::new(0),
+        }
+    }
+
+    fn charge(&self, cycles: u128) {
+        self.spent.set(self.spent.get().saturating_add(cycles));
+    }
+}
+
+impl Drop for CyclesMeter {
+    fn drop(&mut self) {
+        if self.spent.get() > 0 {
+            memory::add_cycles_spent(self.vault_id, self.spent.get());
+        }
+    }
+}
+
+/// Charges every successful call through `inner` to `meter`.
+pub struct Metered<'a, T> {
+    inner: &'a T,
+    meter: &'a CyclesMeter,
+}
+
+impl<'a, T> Metered<'a, T> {
+    pub fn new(inner: &'a T, meter: &'a CyclesMeter) -> Self {
+        Self { inner, meter }
+    }
+}
+
+impl<T: BitcoinBackend> BitcoinBackend for Metered<'_, T> {
+    async fn get_utxos(
+        &self,
+        network: Network,
+        address: &str,
+        min_confirmations: u32,
+    ) -> Result<GetUtxosResponse, BitcoinWalletError> {
+        let response = self.inner.get_utxos(network, address, min_confirmations).await?;
+        self.meter.charge(self.inner.get_utxos_cost(network));
+        Ok(response)
+    }
+
+    async fn fee_percentiles(&self, network: Network) -> Result<Vec<u64>, BitcoinWalletError> {
+        let percentiles = self.inner.fee_percentiles(network).await?;
+        self.meter.charge(self.inner.fee_percentiles_cost(network));
+        Ok(percentiles)
+    }
+
+    async fn send_transaction(&self, network: Network, transaction: Vec<u8>) -> Result<(), BitcoinWalletError> {
+        let cost = self.inner.send_transaction_cost(network, transaction.len());
+        self.inner.send_transaction(network, transaction).await?;
+        self.meter.charge(cost);
+        Ok(())
+    }
+
+    fn get_utxos_cost(&self, network: Network) -> u128 {
+        self.inner.get_utxos_cost(network)
+    }
+
+    fn fee_percentiles_cost(&self, network: Network) -> u128 {
+        self.inner.fee_percentiles_cost(network)
+    }
+
+    fn send_transaction_cost(&self, network: Network, transaction_bytes: usize) -> u128 {
+        self.inner.send_transaction_cost(network, transaction_bytes)
+    }
+
+    fn cycles_available(&self) -> u128 {
+        self.inner.cycles_available()
+    }
+}
+
+impl<T: Signer> Signer for Metered<'_, T> {
+    async fn public_key(&self, key_id: &str, derivation_path: &[Vec<u8>]) -> Result<Vec<u8>, BitcoinWalletError> {
+        self.inner.public_key(key_id, derivation_path).await
+    }
+
+    async fn sign(
+        &self,
+        key_id: &str,
+        derivation_path: &[Vec<u8>],
+        message_hash: &[u8; 32],
+    ) -> Result<Vec<u8>, BitcoinWalletError> {
+        let signature = self.inner.sign(key_id, derivation_path, message_hash).await?;
+        self.meter.charge(self.inner.sign_cost(key_id)?);
+        Ok(signature)
+    }
+
+    fn sign_cost(&self, key_id: &str) -> Result<u128, BitcoinWalletError> {
+        self.inner.sign_cost(key_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::{MockBitcoin, MockSigner, FEE_PERCENTILES_COST, GET_UTXOS_COST, SIGN_COST};
+    use futures::executor::block_on;
+
+    #[test]
+    fn meter_charges_the_vault_on_drop() {
+        let backend = MockBitcoin::default();
+        let signer = MockSigner { seed: [1; 32] };
+        {
+            let meter = CyclesMeter::new(9);
+            let metered_backend = Metered::new(&backend, &meter);
+            let metered_signer = Metered::new(&signer, &meter);
+            block_on(metered_backend.get_utxos(Network::Regtest, "bcrt1q", 1)).unwrap();
+            block_on(metered_backend.fee_percentiles(Network::Regtest)).unwrap();
+            block_on(metered_signer.sign("key", &[], &[7; 32])).unwrap();
+        }
+        assert_eq!(memory::cycles_spent(9), GET_UTXOS_COST + FEE_PERCENTILES_COST + SIGN_COST);
+        assert_eq!(memory::cycles_spent(10), 0);
+    }
+
+    #[test]
+    fn shortfall_reports_both_amounts() {
+        assert!(ensure_available(10, 10).is_ok());
+        assert!(matches!(
+            ensure_available(11, 10),
+            Err(BitcoinWalletError::InsufficientCycles { needed: 11, available: 10 })
+        ));
+    }
+}
@@ -0,0 +1,100 @@
+use bitcoin::key::{PublicKey, XOnlyPublicKey};
+use bitcoin::Script;
+
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const GENERATORS: [u64; 5] = [0xf5dee51989, 0xa9fdca3312, 0x1bab10e32d, 0x3706b1677a, 0x644d626ffd];
+
+/// Builds the checksummed output descriptor watching `script_pub_key`.
+///
+/// Only single-key scripts are produced by the wallet, so P2WPKH maps to `wpkh(...)` and
+/// P2TR maps to a key-path-only `tr(...)`.
+pub fn descriptor_for_script(script_pub_key: &[u8], public_key: &[u8]) -> Option<String> {
+    let script = Script::from_bytes(script_pub_key);
+    let public_key = PublicKey::from_slice(public_key).ok()?;
+    let body = if script.is_p2wpkh() {
+        format!("wpkh({public_key})")
+    } else if script.is_p2tr() {
+        format!("tr({})", XOnlyPublicKey::from(public_key.inner))
+    } else {
+        return None;
+    };
+    with_checksum(&body)
+}
+
+/// Appends the BIP-380 descriptor checksum, returning `None` for characters outside the
+/// descriptor charset.
+pub fn with_checksum(descriptor: &str) -> Option<String> {
+    let checksum = checksum(descriptor)?;
+    Some(format!("{descriptor}#{checksum}"))
+}
+
+fn checksum(descriptor: &str) -> Option<String> {
+    let mut c = 1u64;
+    let mut class = 0u64;
+    let mut class_count = 0;
+    for ch in descriptor.chars() {
+        let position = INPUT_CHARSET.find(ch)? as u64;
+        c = polymod(c, position & 31);
+        class = class * 3 + (position >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = polymod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+    if class_count > 0 {
+        c = polymod(c, class);
+    }
+    for _ in 0..8 {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+    Some(
+        (0..8)
+            .map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char)
+            .collect(),
+    )
+}
+
+fn polymod(c: u64, value: u64) -> u64 {
+    let top = c >> 35;
+    let mut c = ((c & 0x7_ffff_ffff) << 5) ^ value;
+    for (index, generator) in GENERATORS.iter().enumerate() {
+        if top & (1 << index) != 0 {
+            c ^= generator;
+        }
+    }
+    c
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{Address, Network};
+    use std::str::FromStr;
+
+    const PUBKEY: &str = "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+
+    #[test]
+    fn checksum_matches_reference_vectors() {
+        assert_eq!(with_checksum("raw(deadbeef)").unwrap(), "raw(deadbeef)#89f8spxm");
+        assert_eq!(
+            with_checksum(&format!("wpkh({PUBKEY})")).unwrap(),
+            format!("wpkh({PUBKEY})#8zl0zxma")
+        );
+    }
+
+    #[test]
+    fn descriptor_follows_script_type() {
+        let public_key = PublicKey::from_str(PUBKEY).unwrap();
+        let p2wpkh = Address::p2wpkh(&public_key, Network::Testnet).unwrap();
+        let descriptor = descriptor_for_script(p2wpkh.script_pubkey().as_bytes(), &public_key.to_bytes()).unwrap();
+        assert!(descriptor.starts_with(&format!("wpkh({PUBKEY})#")));
+
+        let p2pkh = Address::p2pkh(&public_key, Network::Testnet);
+        assert!(descriptor_for_script(p2pkh.script_pubkey().as_bytes(), &public_key.to_bytes()).is_none());
+    }
+}
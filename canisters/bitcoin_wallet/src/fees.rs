@@ -0,0 +1,115 @@
+use crate::{estimate_fee_sat, memo, BitcoinWalletError, VaultId, FALLBACK_FEE_MSAT_PER_VBYTE};
+use candid::CandidType;
+use serde::Deserialize;
+
+/// Percentiles of recent transaction fees each tier pays.
+const LOW_PERCENTILE: usize = 25;
+const MEDIUM_PERCENTILE: usize = 50;
+const HIGH_PERCENTILE: usize = 90;
+
+/// Fee rates in sat/vB.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeeTiers {
+    pub low: u64,
+    pub medium: u64,
+    pub high: u64,
+}
+
+impl FeeTiers {
+    /// Reads the tiers off the Bitcoin canister's fee percentiles (millisatoshi per byte). Networks
+    /// without fee data, such as regtest, get the fallback rate for every tier.
+    pub fn from_percentiles(percentiles: &[u64]) -> Self {
+        let at = |percentile: usize| match percentiles.len() {
+            0 => FALLBACK_FEE_MSAT_PER_VBYTE / 1_000,
+            len => percentiles[(len * percentile / 100).min(len - 1)].div_ceil(1_000).max(1),
+        };
+        Self {
+            low: at(LOW_PERCENTILE),
+            medium: at(MEDIUM_PERCENTILE),
+            high: at(HIGH_PERCENTILE),
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct FeeTier {
+    #[serde(rename = "satPerVbyte")]
+    pub sat_per_vbyte: u64,
+    /// Fee for sending the whole vault to its configured heirs at this rate.
+    #[serde(rename = "sweepFeeSats")]
+    pub sweep_fee_sats: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct FeeEstimate {
+    #[serde(rename = "vaultId")]
+    pub vault_id: VaultId,
+    #[serde(rename = "balanceSats")]
+    pub balance_sats: u64,
+    /// Inputs the sweep was priced with; an empty vault is priced as one deposit.
+    pub inputs: u64,
+    /// Heir outputs; an OP_RETURN memo, when the vault adds one, is priced on top.
+    pub outputs: u64,
+    pub memo: bool,
+    pub low: FeeTier,
+    pub medium: FeeTier,
+    pub high: FeeTier,
+}
+
+impl FeeEstimate {
+    pub fn new(
+        vault_id: VaultId,
+        balance_sats: u64,
+        utxo_count: usize,
+        heir_count: usize,
+        memo: bool,
+        tiers: FeeTiers,
+    ) -> Result<Self, BitcoinWalletError> {
+        let inputs = utxo_count.max(1);
+        let tier = |sat_per_vbyte: u64| -> Result<FeeTier, BitcoinWalletError> {
+            Ok(FeeTier {
+                sat_per_vbyte,
+                sweep_fee_sats: estimate_fee_sat(sat_per_vbyte, inputs, heir_count)
+                    .zip(if memo { memo::fee_sat(sat_per_vbyte) } else { Some(0) })
+                    .and_then(|(fee, memo_fee)| fee.checked_add(memo_fee))
+                    .ok_or(BitcoinWalletError::FeeEstimationUnavailable)?,
+            })
+        };
+        Ok(Self {
+            vault_id,
+            balance_sats,
+            inputs: inputs as u64,
+            outputs: heir_count as u64,
+            memo,
+            low: tier(tiers.low)?,
+            medium: tier(tiers.medium)?,
+            high: tier(tiers.high)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiers_come_from_percentiles() {
+        let percentiles: Vec<u64> = (0..=100).map(|p| p * 1_000 + 1).collect();
+        let tiers = FeeTiers::from_percentiles(&percentiles);
+        assert_eq!(tiers, FeeTiers { low: 26, medium: 51, high: 91 });
+        assert_eq!(FeeTiers::from_percentiles(&[]).medium, FALLBACK_FEE_MSAT_PER_VBYTE / 1_000);
+        assert_eq!(FeeTiers::from_percentiles(&[0]).low, 1);
+    }
+
+    #[test]
+    fn sweep_is_priced_per_tier() {
+        let tiers = FeeTiers { low: 1, medium: 5, high: 20 };
+        let estimate = FeeEstimate::new(1, 0, 0, 2, false, tiers).unwrap();
+        assert_eq!(estimate.inputs, 1);
+        assert_eq!(estimate.low.sweep_fee_sats, 10 + 68 + 2 * 31);
+        assert_eq!(estimate.high.sweep_fee_sats, 20 * (10 + 68 + 2 * 31));
+
+        let with_memo = FeeEstimate::new(1, 0, 0, 2, true, tiers).unwrap();
+        assert_eq!(with_memo.high.sweep_fee_sats, 20 * (10 + 68 + 2 * 31 + memo::OUTPUT_VBYTES));
+    }
+}
@@ -0,0 +1,233 @@
+use crate::memo::InheritanceMemo;
+use crate::vesting::{retry_delay, MAX_RETRY_ATTEMPTS};
+use crate::ExecuteInheritanceArgs;
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// How often an execution waiting for deposits to confirm checks again; about one block.
+pub const CONFIRMATION_POLL_NANOS: u64 = 10 * 60 * NANOS_PER_SEC;
+/// How long past its execution time an execution waits for deposits before paying out without
+/// them, so a stream of fresh deposits cannot hold it back forever.
+pub const MAX_CONFIRMATION_WAIT_NANOS: u64 = 24 * 60 * 60 * NANOS_PER_SEC;
+
+/// Earliest time a payout requested at `now` may run: owners and guardians get the whole grace
+/// period to veto it.
+pub fn earliest_payout(now: u64, grace_period_secs: u64) -> u64 {
+    now.saturating_add(grace_period_secs.saturating_mul(NANOS_PER_SEC))
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub enum ExecutionStatus {
+    Scheduled,
+    Cancelled {
+        #[serde(rename = "cancelledBy")]
+        cancelled_by: Vec<Principal>,
+        #[serde(rename = "cancelledAt")]
+        cancelled_at: u64,
+    },
+    Executed {
+        #[serde(rename = "txId")]
+        tx_id: String,
+        #[serde(rename = "executedAt")]
+        executed_at: u64,
+        /// Commitment published in the payout's OP_RETURN output.
+        memo: Option<InheritanceMemo>,
+    },
+    /// Stopped on an error retrying cannot fix, such as a policy naming a fee bearer who is
+    /// not an heir.
+    Failed {
+        error: String,
+        #[serde(rename = "failedAt")]
+        failed_at: u64,
+    },
+}
+
+/// An inheritance execution waiting out its grace period. The vault owner can cancel it alone;
+/// guardians cancel it once a quorum of them has voted.
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct PendingExecution {
+    pub request: ExecuteInheritanceArgs,
+    #[serde(rename = "requestedBy")]
+    pub requested_by: Principal,
+    #[serde(rename = "requestedAt")]
+    pub requested_at: u64,
+    #[serde(rename = "executeAt")]
+    pub execute_at: u64,
+    #[serde(rename = "cancelVotes")]
+    pub cancel_votes: Vec<Principal>,
+    pub status: ExecutionStatus,
+    /// Set after a failed broadcast; the execution is retried from then on.
+    #[serde(rename = "retryAt")]
+    pub retry_at: Option<u64>,
+    #[serde(rename = "lastError")]
+    pub last_error: Option<String>,
+    /// Failed broadcasts so far; `None` until the first one.
+    pub attempts: Option<u32>,
+}
+
+impl PendingExecution {
+    pub fn new(request: ExecuteInheritanceArgs, requested_by: Principal, now: u64, grace_period_secs: u64) -> Self {
+        Self {
+            request,
+            requested_by,
+            requested_at: now,
+            execute_at: earliest_payout(now, grace_period_secs),
+            cancel_votes: Vec::new(),
+            status: ExecutionStatus::Scheduled,
+            retry_at: None,
+            last_error: None,
+            attempts: None,
+        }
+    }
+
+    pub fn is_scheduled(&self) -> bool {
+        self.status == ExecutionStatus::Scheduled
+    }
+
+    /// Cancellation is only possible before the execution falls due.
+    pub fn window_open(&self, now: u64) -> bool {
+        self.is_scheduled() && now < self.execute_at
+    }
+
+    pub fn next_run_at(&self) -> Option<u64> {
+        self.is_scheduled().then(|| self.retry_at.unwrap_or(self.execute_at))
+    }
+
+    pub fn is_due(&self, now: u64) -> bool {
+        self.next_run_at().is_some_and(|at| at <= now)
+    }
+
+    pub fn cancel(&mut self, cancelled_by: Vec<Principal>, now: u64) {
+        self.status = ExecutionStatus::Cancelled {
+            cancelled_by,
+            cancelled_at: now,
+        };
+    }
+
+    /// Counts `guardian`'s vote once and cancels when `threshold` guardians have voted.
+    pub fn vote_cancel(&mut self, guardian: Principal, threshold: u64, now: u64) {
+        if !self.cancel_votes.contains(&guardian) {
+            self.cancel_votes.push(guardian);
+        }
+        if self.cancel_votes.len() as u64 >= threshold.max(1) {
+            self.cancel(self.cancel_votes.clone(), now);
+        }
+    }
+
+    pub fn mark_executed(&mut self, tx_id: String, memo: Option<InheritanceMemo>, now: u64) {
+        self.status = ExecutionStatus::Executed {
+            tx_id,
+            executed_at: now,
+            memo,
+        };
+        self.retry_at = None;
+        self.last_error = None;
+    }
+
+    /// Records a failed broadcast and backs off. After `MAX_RETRY_ATTEMPTS` the execution
+    /// halts instead and this returns true.
+    pub fn mark_failed(&mut self, error: String, now: u64) -> bool {
+        let attempts = self.attempts.unwrap_or_default() + 1;
+        self.attempts = Some(attempts);
+        if attempts >= MAX_RETRY_ATTEMPTS {
+            self.mark_halted(format!("gave up after {attempts} attempts: {error}"), now);
+            return true;
+        }
+        self.retry_at = Some(now + retry_delay(attempts));
+        self.last_error = Some(error);
+        false
+    }
+
+    /// Stops retrying after an error retrying cannot fix.
+    pub fn mark_halted(&mut self, error: String, now: u64) {
+        self.status = ExecutionStatus::Failed {
+            error: error.clone(),
+            failed_at: now,
+        };
+        self.retry_at = None;
+        self.last_error = Some(error);
+    }
+
+    /// Whether the payout should still hold out for unconfirmed deposits at `now`.
+    pub fn waits_for_confirmations(&self, now: u64) -> bool {
+        self.request.wait_for_confirmations.unwrap_or_default()
+            && now < self.execute_at.saturating_add(MAX_CONFIRMATION_WAIT_NANOS)
+    }
+
+    pub fn defer(&mut self, reason: String, now: u64) {
+        self.retry_at = Some(now + CONFIRMATION_POLL_NANOS);
+        self.last_error = Some(reason);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending() -> PendingExecution {
+        let request = ExecuteInheritanceArgs {
+            vault_id: 1,
+            key_id: "test_key_1".into(),
+            heirs: vec![],
+            guardian_submissions: 0,
+            wait_for_confirmations: Some(true),
+        };
+        PendingExecution::new(request, Principal::anonymous(), 1_000, 60)
+    }
+
+    #[test]
+    fn grace_window_closes_at_execution_time() {
+        let pending = pending();
+        let execute_at = 1_000 + 60 * NANOS_PER_SEC;
+        assert_eq!(pending.execute_at, execute_at);
+        assert!(pending.window_open(execute_at - 1));
+        assert!(!pending.window_open(execute_at));
+        assert!(!pending.is_due(execute_at - 1));
+        assert!(pending.is_due(execute_at));
+    }
+
+    #[test]
+    fn confirmation_wait_is_bounded() {
+        let mut pending = pending();
+        let execute_at = pending.execute_at;
+        assert!(pending.waits_for_confirmations(execute_at));
+        assert!(!pending.waits_for_confirmations(execute_at + MAX_CONFIRMATION_WAIT_NANOS));
+
+        pending.defer("2 deposits unconfirmed".into(), execute_at);
+        assert!(!pending.is_due(execute_at + CONFIRMATION_POLL_NANOS - 1));
+        assert!(pending.is_due(execute_at + CONFIRMATION_POLL_NANOS));
+    }
+
+    #[test]
+    fn failing_executions_back_off_and_then_halt() {
+        let mut pending = pending();
+        assert!(!pending.mark_failed("no utxos".into(), 5));
+        assert_eq!(pending.retry_at, Some(5 + retry_delay(1)));
+        assert!(!pending.mark_failed("no utxos".into(), 6));
+        assert_eq!(pending.retry_at, Some(6 + 2 * retry_delay(1)));
+        while !pending.mark_failed("no utxos".into(), 7) {}
+        assert_eq!(pending.attempts, Some(MAX_RETRY_ATTEMPTS));
+        assert!(matches!(pending.status, ExecutionStatus::Failed { failed_at: 7, .. }));
+        assert_eq!(pending.next_run_at(), None);
+    }
+
+    #[test]
+    fn guardian_quorum_cancels() {
+        let mut pending = pending();
+        let guardian = Principal::from_slice(&[1]);
+        pending.vote_cancel(guardian, 2, 5);
+        pending.vote_cancel(guardian, 2, 6);
+        assert!(pending.is_scheduled());
+        pending.vote_cancel(Principal::from_slice(&[2]), 2, 7);
+        assert_eq!(
+            pending.status,
+            ExecutionStatus::Cancelled {
+                cancelled_by: vec![guardian, Principal::from_slice(&[2])],
+                cancelled_at: 7,
+            }
+        );
+        assert_eq!(pending.next_run_at(), None);
+    }
+}
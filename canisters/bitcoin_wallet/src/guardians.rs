@@ -0,0 +1,111 @@
+//! Calls into `guardian_mgr`, which owns guardian membership and share submissions.
+
+use crate::{with_state, BitcoinWalletError, VaultId};
+use candid::{CandidType, Principal};
+use ic_cdk::call::Call;
+use serde::Deserialize;
+
+/// Reply of `guardian_mgr::guardian_quorum`.
+#[derive(CandidType, Deserialize)]
+pub struct GuardianQuorum {
+    pub threshold: u64,
+    /// Guardians who have bound a principal; only they can vote.
+    pub guardians: Vec<Principal>,
+}
+
+impl GuardianQuorum {
+    /// Fails while fewer guardians have bound a principal than a veto needs votes, since the
+    /// guardians could then not cancel a payout during its grace period.
+    pub fn ensure_veto_reachable(&self, vault_id: VaultId) -> Result<(), BitcoinWalletError> {
+        let bound = self.guardians.len() as u64;
+        if bound < self.threshold.max(1) {
+            return Err(BitcoinWalletError::VetoQuorumUnreachable {
+                vault_id,
+                bound,
+                threshold: self.threshold,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Reply of `guardian_mgr::guardian_threshold_status`.
+#[derive(CandidType, Deserialize)]
+struct GuardianSubmissionResult {
+    submitted: u64,
+    #[serde(rename = "thresholdMet")]
+    threshold_met: bool,
+}
+
+fn guardian_mgr() -> Result<Principal, BitcoinWalletError> {
+    with_state(|state| state.guardian_mgr).ok_or(BitcoinWalletError::GuardianMgrNotConfigured)
+}
+
+pub async fn guardian_quorum(vault_id: VaultId) -> Result<GuardianQuorum, BitcoinWalletError> {
+    Call::unbounded_wait(guardian_mgr()?, "guardian_quorum")
+        .with_arg(vault_id)
+        .await
+        .map_err(|err| BitcoinWalletError::Guardian(format!("guardian_quorum failed: {err}")))?
+        .candid::<Option<GuardianQuorum>>()
+        .map_err(|err| BitcoinWalletError::Guardian(format!("guardian_quorum returned: {err}")))?
+        .ok_or_else(|| BitcoinWalletError::Guardian(format!("vault {vault_id} has no guardians")))
+}
+
+/// Asks `guardian_mgr` whether enough guardians have submitted their shares for `vault_id`.
+/// The vault manager's word is not enough: without a configured guardian manager, or when it
+/// cannot be reached, spending is refused.
+pub async fn ensure_threshold_met(vault_id: VaultId) -> Result<(), BitcoinWalletError> {
+    let status = Call::unbounded_wait(guardian_mgr()?, "guardian_threshold_status")
+        .with_arg(vault_id)
+        .await
+        .map_err(|err| BitcoinWalletError::Guardian(format!("guardian_threshold_status failed: {err}")))?
+        .candid::<GuardianSubmissionResult>()
+        .map_err(|err| BitcoinWalletError::Guardian(format!("guardian_threshold_status returned: {err}")))?;
+    check_threshold(vault_id, &status)
+}
+
+fn check_threshold(vault_id: VaultId, status: &GuardianSubmissionResult) -> Result<(), BitcoinWalletError> {
+    if !status.threshold_met {
+        return Err(BitcoinWalletError::GuardianThresholdNotMet {
+            vault_id,
+            submitted: status.submitted,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unmet_threshold_blocks_spending() {
+        let pending = GuardianSubmissionResult {
+            submitted: 1,
+            threshold_met: false,
+        };
+        assert!(matches!(
+            check_threshold(4, &pending),
+            Err(BitcoinWalletError::GuardianThresholdNotMet { vault_id: 4, submitted: 1 })
+        ));
+        let met = GuardianSubmissionResult {
+            submitted: 2,
+            threshold_met: true,
+        };
+        assert!(check_threshold(4, &met).is_ok());
+    }
+
+    #[test]
+    fn veto_needs_enough_bound_guardians() {
+        let mut quorum = GuardianQuorum {
+            threshold: 2,
+            guardians: vec![Principal::from_slice(&[1])],
+        };
+        assert!(matches!(
+            quorum.ensure_veto_reachable(4),
+            Err(BitcoinWalletError::VetoQuorumUnreachable { vault_id: 4, bound: 1, threshold: 2 })
+        ));
+        quorum.guardians.push(Principal::from_slice(&[2]));
+        assert!(quorum.ensure_veto_reachable(4).is_ok());
+    }
+}
@@ -0,0 +1,291 @@
+//! Per-vault record of Bitcoin moving in and out, exported for estate documentation.
+
+use crate::memo::InheritanceMemo;
+use crate::{icrc, memory, ManagedUtxo, VaultId};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub enum MovementKind {
+    Deposit,
+    /// Funds swept off the vault's old address by a key migration.
+    Withdrawal,
+    InheritancePayout,
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct Recipient {
+    pub address: String,
+    #[serde(rename = "amountSats")]
+    pub amount_sats: u64,
+    /// Set when the recipient is paid in ckBTC; `address` is then the minter's deposit address.
+    pub account: Option<icrc::Account>,
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct HistoryEntry {
+    pub kind: MovementKind,
+    pub txid: String,
+    /// Output index of a deposit.
+    pub vout: Option<u32>,
+    /// Deposited value, or the total paid to recipients.
+    #[serde(rename = "amountSats")]
+    pub amount_sats: u64,
+    #[serde(rename = "feeSats")]
+    pub fee_sats: Option<u64>,
+    /// Confirmation height of a deposit; the chain tip an outgoing transaction was built at.
+    pub height: u32,
+    /// IC time in nanoseconds at which the movement was recorded.
+    pub timestamp: u64,
+    pub recipients: Vec<Recipient>,
+    /// Commitment an inheritance payout published in its OP_RETURN output.
+    pub memo: Option<InheritanceMemo>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+/// Records every UTXO in `utxos` not already in the vault's history as a deposit. Outputs of
+/// the vault's own transactions (change, key migration sweeps) are not deposits.
+pub fn record_deposits(vault_id: VaultId, utxos: &[ManagedUtxo], now: u64) {
+    let history = memory::vault_history(vault_id);
+    let seen = |utxo: &ManagedUtxo| {
+        let txid = utxo.outpoint.txid.to_string();
+        history.iter().any(|entry| {
+            entry.txid == txid && (entry.kind != MovementKind::Deposit || entry.vout == Some(utxo.outpoint.vout))
+        })
+    };
+    for utxo in utxos.iter().filter(|utxo| !seen(utxo)) {
+        memory::append_history(
+            vault_id,
+            HistoryEntry {
+                kind: MovementKind::Deposit,
+                txid: utxo.outpoint.txid.to_string(),
+                vout: Some(utxo.outpoint.vout),
+                amount_sats: utxo.value,
+                fee_sats: None,
+                height: utxo.height,
+                timestamp: now,
+                recipients: Vec::new(),
+                memo: None,
+            },
+        );
+    }
+}
+
+/// Principals paid in ckBTC by one of the vault's inheritance payouts.
+pub fn paid_heirs(entries: &[HistoryEntry]) -> impl Iterator<Item = Principal> + '_ {
+    entries
+        .iter()
+        .filter(|entry| entry.kind == MovementKind::InheritancePayout)
+        .flat_map(|entry| entry.recipients.iter().filter_map(|r| r.account.as_ref().map(|a| a.owner)))
+}
+
+pub fn render(entries: &[HistoryEntry], format: &ExportFormat) -> String {
+    match format {
+        ExportFormat::Csv => render_csv(entries),
+        ExportFormat::Json => render_json(entries),
+    }
+}
+
+fn kind_label(kind: MovementKind) -> &'static str {
+    match kind {
+        MovementKind::Deposit => "deposit",
+        MovementKind::Withdrawal => "withdrawal",
+        MovementKind::InheritancePayout => "inheritance_payout",
+    }
+}
+
+/// One row per movement; recipients are joined as `address:amount` pairs separated by `;`. The
+/// memo's execution time stays in nanoseconds, as the commitment is computed over it.
+fn render_csv(entries: &[HistoryEntry]) -> String {
+    let mut out =
+        String::from("kind,txid,vout,amount_sats,fee_sats,height,timestamp,recipients,memo,memo_executed_at\n");
+    for entry in entries {
+        let recipients: Vec<String> =
+            entry.recipients.iter().map(|r| format!("{}:{}", r.address, r.amount_sats)).collect();
+        let _ = writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{},{}",
+            kind_label(entry.kind),
+            entry.txid,
+            entry.vout.map(|vout| vout.to_string()).unwrap_or_default(),
+            entry.amount_sats,
+            entry.fee_sats.map(|fee| fee.to_string()).unwrap_or_default(),
+            entry.height,
+            iso8601(entry.timestamp),
+            recipients.join(";"),
+            entry.memo.as_ref().map(|memo| memo.commitment.as_str()).unwrap_or_default(),
+            entry.memo.as_ref().map(|memo| memo.executed_at.to_string()).unwrap_or_default(),
+        );
+    }
+    out
+}
+
+fn render_json(entries: &[HistoryEntry]) -> String {
+    let optional = |value: Option<u64>| value.map_or("null".to_string(), |value| value.to_string());
+    let rows: Vec<String> = entries
+        .iter()
+        .map(|entry| {
+            let recipients: Vec<String> = entry
+                .recipients
+                .iter()
+                .map(|r| format!("{{\"address\":{},\"amountSats\":{}}}", json_string(&r.address), r.amount_sats))
+                .collect();
+            let memo = entry.memo.as_ref().map_or("null".to_string(), |memo| {
+                format!("{{\"commitment\":{},\"executedAt\":{}}}", json_string(&memo.commitment), memo.executed_at)
+            });
+            format!(
+                "{{\"kind\":\"{}\",\"txid\":{},\"vout\":{},\"amountSats\":{},\"feeSats\":{},\"height\":{},\
+                 \"timestamp\":\"{}\",\"recipients\":[{}],\"memo\":{}}}",
+                kind_label(entry.kind),
+                json_string(&entry.txid),
+                optional(entry.vout.map(u64::from)),
+                entry.amount_sats,
+                optional(entry.fee_sats),
+                entry.height,
+                iso8601(entry.timestamp),
+                recipients.join(","),
+                memo,
+            )
+        })
+        .collect();
+    format!("[{}]", rows.join(","))
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// UTC timestamp in ISO 8601, e.g. `2024-05-01T12:00:00Z`.
+fn iso8601(nanos: u64) -> String {
+    let secs = nanos / 1_000_000_000;
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payout() -> HistoryEntry {
+        HistoryEntry {
+            kind: MovementKind::InheritancePayout,
+            txid: "ab".repeat(32),
+            vout: None,
+            amount_sats: 99_584,
+            fee_sats: Some(416),
+            height: 120,
+            timestamp: 1_714_564_800_000_000_000,
+            recipients: vec![
+                Recipient {
+                    address: "bcrt1qheir".into(),
+                    amount_sats: 59_750,
+                    account: None,
+                },
+                Recipient {
+                    address: "bcrt1qminter".into(),
+                    amount_sats: 39_834,
+                    account: Some(icrc::Account {
+                        owner: Principal::from_slice(&[7]),
+                        subaccount: None,
+                    }),
+                },
+            ],
+            memo: Some(InheritanceMemo {
+                commitment: "cd".repeat(32),
+                executed_at: 1_714_564_799_000_000_123,
+            }),
+        }
+    }
+
+    #[test]
+    fn deposits_are_recorded_once_and_change_is_not_a_deposit() {
+        use bitcoin::hashes::Hash;
+        use bitcoin::{OutPoint, Txid};
+
+        let utxo = |seed: u8, vout: u32| ManagedUtxo {
+            outpoint: OutPoint::new(Txid::from_byte_array([seed; 32]), vout),
+            value: 10_000,
+            height: 100,
+        };
+        record_deposits(4, &[utxo(1, 0), utxo(1, 1)], 5);
+        record_deposits(4, &[utxo(1, 0), utxo(1, 1)], 6);
+        let mut change = payout();
+        change.txid = Txid::from_byte_array([2; 32]).to_string();
+        memory::append_history(4, change);
+        record_deposits(4, &[utxo(1, 1), utxo(2, 2)], 7);
+
+        let history = memory::vault_history(4);
+        let deposits: Vec<_> = history.iter().filter(|entry| entry.kind == MovementKind::Deposit).collect();
+        assert_eq!(deposits.len(), 2);
+        assert_eq!(deposits[1].vout, Some(1));
+        assert_eq!(history.len(), 3);
+        assert!(memory::vault_history(5).is_empty());
+    }
+
+    #[test]
+    fn csv_has_one_row_per_movement() {
+        let csv = render(&[payout()], &ExportFormat::Csv);
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("kind,txid,vout,amount_sats,fee_sats,height,timestamp,recipients,memo,memo_executed_at")
+        );
+        assert_eq!(
+            lines.next().unwrap(),
+            format!(
+                "inheritance_payout,{},,99584,416,120,2024-05-01T12:00:00Z,bcrt1qheir:59750;bcrt1qminter:39834,{},{}",
+                "ab".repeat(32),
+                "cd".repeat(32),
+                1_714_564_799_000_000_123u64
+            )
+        );
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn json_lists_recipients_and_paid_heirs() {
+        let entries = [payout()];
+        let json = render(&entries, &ExportFormat::Json);
+        assert!(json.starts_with("[{\"kind\":\"inheritance_payout\""));
+        assert!(json.contains("\"vout\":null,\"amountSats\":99584,\"feeSats\":416,\"height\":120"));
+        assert!(json.contains("{\"address\":\"bcrt1qheir\",\"amountSats\":59750}"));
+        let memo = format!("\"memo\":{{\"commitment\":\"{}\",\"executedAt\":1714564799000000123}}", "cd".repeat(32));
+        assert!(json.ends_with(&format!("{memo}}}]")));
+        assert_eq!(json_string("a\"b\n"), "\"a\\\"b\\u000a\"");
+        assert_eq!(paid_heirs(&entries).collect::<Vec<_>>(), vec![Principal::from_slice(&[7])]);
+    }
+}
@@ -0,0 +1,462 @@
+use crate::{BitcoinWalletError, VaultId, BASIS_POINTS};
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::call::Call;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// ICRC-1 account.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<serde_bytes::ByteBuf>,
+}
+
+impl Account {
+    pub fn is_valid(&self) -> bool {
+        self.subaccount.as_ref().is_none_or(|subaccount| subaccount.len() == 32)
+    }
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct TokenHeir {
+    pub account: Account,
+    #[serde(rename = "weightBps")]
+    pub weight_bps: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ExecuteTokenInheritanceArgs {
+    #[serde(rename = "vaultId")]
+    pub vault_id: VaultId,
+    pub heirs: Vec<TokenHeir>,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct TokenBalance {
+    pub ledger: Principal,
+    pub balance: Nat,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct TokenTransfer {
+    pub account: Account,
+    pub amount: Nat,
+    #[serde(rename = "blockIndex")]
+    pub block_index: Option<Nat>,
+    pub error: Option<String>,
+}
+
+/// Transfers made from one ledger. Failures are reported per heir rather than aborting the
+/// remaining ledgers, since completed transfers cannot be rolled back.
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct LedgerPayout {
+    pub ledger: Principal,
+    pub transfers: Vec<TokenTransfer>,
+    pub error: Option<String>,
+}
+
+/// Most blocks requested from a ledger or archive per `get_transactions` call.
+const MAX_BLOCKS_PER_CALL: u64 = 1_000;
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct PlannedTransfer {
+    pub account: Account,
+    pub amount: Nat,
+    pub block_index: Option<Nat>,
+}
+
+/// What one ledger owes each heir of a vault, fixed by the first payout attempt. Retries pay
+/// only the unpaid transfers, with the same memo and `created_at_time`, so the ledger
+/// deduplicates a transfer whose reply was lost. Ledgers only deduplicate for 24 hours; after
+/// that they answer `TooOld` and the plan is reissued once its blocks show what already landed.
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct TokenPayoutPlan {
+    pub fee: Nat,
+    pub transfers: Vec<PlannedTransfer>,
+    pub created_at_time: u64,
+    /// Ledger length when the plan was made: none of its transfers can land before this block.
+    pub first_block: Option<Nat>,
+}
+
+/// A transfer out of a vault subaccount, as recorded in a ledger block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LedgerTransfer {
+    pub block_index: Nat,
+    pub memo: Option<Vec<u8>>,
+}
+
+/// Why a ledger did not execute a transfer.
+#[derive(Debug, Error)]
+pub enum TransferFailure {
+    /// The plan's `created_at_time` left the ledger's deduplication window.
+    #[error("TooOld")]
+    TooOld,
+    #[error("{0}")]
+    Failed(String),
+}
+
+impl TokenPayoutPlan {
+    /// Splits `balance`, net of one transfer fee per heir, by weight.
+    pub fn new(balance: Nat, fee: Nat, heirs: &[TokenHeir], first_block: Nat, now: u64) -> Result<Self, String> {
+        let fees = fee.clone() * heirs.len();
+        if balance <= fees {
+            return Err(format!("balance {balance} does not cover {fees} in transfer fees"));
+        }
+        let transfers = heirs
+            .iter()
+            .zip(split_by_weight(&(balance - fees), heirs))
+            .map(|(heir, amount)| PlannedTransfer {
+                account: heir.account.clone(),
+                amount,
+                block_index: None,
+            })
+            .collect();
+        Ok(TokenPayoutPlan {
+            fee,
+            transfers,
+            created_at_time: now,
+            first_block: Some(first_block),
+        })
+    }
+
+    pub fn unpaid(&self) -> Vec<usize> {
+        (0..self.transfers.len())
+            .filter(|index| self.transfers[*index].block_index.is_none())
+            .collect()
+    }
+
+    pub fn mark_paid(&mut self, index: usize, block_index: Nat) {
+        self.transfers[index].block_index = Some(block_index);
+    }
+
+    pub fn is_settled(&self) -> bool {
+        self.transfers.iter().all(|transfer| transfer.block_index.is_some())
+    }
+
+    /// Marks each unpaid transfer whose memo appears among `landed`, the transfers out of the
+    /// vault subaccount the ledger recorded.
+    pub fn mark_landed(&mut self, vault_id: VaultId, landed: &[LedgerTransfer]) {
+        for index in self.unpaid() {
+            let memo = transfer_memo(vault_id, index);
+            if let Some(transfer) = landed.iter().find(|transfer| transfer.memo.as_deref() == Some(&memo[..])) {
+                self.mark_paid(index, transfer.block_index.clone());
+            }
+        }
+    }
+
+    /// Starts a new deduplication window. Only safe once `mark_landed` has seen every block
+    /// since `first_block`, or the ledger would execute a landed transfer a second time.
+    pub fn reissue(&mut self, now: u64) {
+        self.created_at_time = now;
+    }
+}
+
+/// Memo of the transfer to heir `index` of `vault_id`: both as big-endian u64s.
+pub fn transfer_memo(vault_id: VaultId, index: usize) -> [u8; 16] {
+    let mut memo = [0u8; 16];
+    memo[..8].copy_from_slice(&vault_id.to_be_bytes());
+    memo[8..].copy_from_slice(&(index as u64).to_be_bytes());
+    memo
+}
+
+#[derive(CandidType)]
+struct TransferArg {
+    from_subaccount: Option<serde_bytes::ByteBuf>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<serde_bytes::ByteBuf>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType)]
+struct GetTransactionsRequest {
+    start: Nat,
+    length: Nat,
+}
+
+#[derive(CandidType, Deserialize)]
+struct BlockTransfer {
+    from: Account,
+    memo: Option<serde_bytes::ByteBuf>,
+}
+
+/// The fields of a ledger transaction the wallet reads; the others are skipped.
+#[derive(CandidType, Deserialize)]
+struct LedgerTransaction {
+    transfer: Option<BlockTransfer>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct TransactionRange {
+    transactions: Vec<LedgerTransaction>,
+}
+
+candid::define_function!(ArchiveCallback : (GetTransactionsRequest) -> (TransactionRange) query);
+
+#[derive(CandidType, Deserialize)]
+struct ArchivedRange {
+    start: Nat,
+    length: Nat,
+    callback: ArchiveCallback,
+}
+
+#[derive(CandidType, Deserialize)]
+struct GetTransactionsResponse {
+    log_length: Nat,
+    first_index: Nat,
+    transactions: Vec<LedgerTransaction>,
+    archived_transactions: Vec<ArchivedRange>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    TemporarilyUnavailable,
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+}
+
+/// Subaccount holding a vault's tokens: the big-endian `VaultId` in the last eight bytes.
+pub fn vault_subaccount(vault_id: VaultId) -> [u8; 32] {
+    let mut subaccount = [0u8; 32];
+    subaccount[24..].copy_from_slice(&vault_id.to_be_bytes());
+    subaccount
+}
+
+pub fn vault_account(canister: Principal, vault_id: VaultId) -> Account {
+    Account {
+        owner: canister,
+        subaccount: Some(serde_bytes::ByteBuf::from(vault_subaccount(vault_id).to_vec())),
+    }
+}
+
+pub fn ensure_valid_heirs(heirs: &[TokenHeir]) -> Result<(), BitcoinWalletError> {
+    let total: u64 = heirs.iter().map(|heir| heir.weight_bps).sum();
+    if heirs.is_empty() || total != BASIS_POINTS || !heirs.iter().all(|heir| heir.account.is_valid()) {
+        return Err(BitcoinWalletError::InvalidHeirs);
+    }
+    Ok(())
+}
+
+/// Splits `total` by weight; the last heir receives the rounding remainder.
+pub fn split_by_weight(total: &Nat, heirs: &[TokenHeir]) -> Vec<Nat> {
+    let mut shares = Vec::with_capacity(heirs.len());
+    let mut assigned = Nat::from(0u64);
+    for (index, heir) in heirs.iter().enumerate() {
+        let share = if index == heirs.len() - 1 {
+            total.clone() - assigned.clone()
+        } else {
+            total.clone() * heir.weight_bps / BASIS_POINTS
+        };
+        assigned += share.clone();
+        shares.push(share);
+    }
+    shares
+}
+
+pub async fn balance_of(ledger: Principal, account: &Account) -> Result<Nat, BitcoinWalletError> {
+    Call::unbounded_wait(ledger, "icrc1_balance_of")
+        .with_arg(account)
+        .await
+        .map_err(|err| BitcoinWalletError::Ledger(format!("icrc1_balance_of failed: {err}")))?
+        .candid::<Nat>()
+        .map_err(|err| BitcoinWalletError::Ledger(format!("icrc1_balance_of returned: {err}")))
+}
+
+pub async fn fee(ledger: Principal) -> Result<Nat, BitcoinWalletError> {
+    Call::unbounded_wait(ledger, "icrc1_fee")
+        .await
+        .map_err(|err| BitcoinWalletError::Ledger(format!("icrc1_fee failed: {err}")))?
+        .candid::<Nat>()
+        .map_err(|err| BitcoinWalletError::Ledger(format!("icrc1_fee returned: {err}")))
+}
+
+async fn get_transactions(
+    canister: Principal,
+    method: &str,
+    start: Nat,
+    length: Nat,
+) -> Result<GetTransactionsResponse, BitcoinWalletError> {
+    Call::unbounded_wait(canister, method)
+        .with_arg(GetTransactionsRequest { start, length })
+        .await
+        .map_err(|err| BitcoinWalletError::Ledger(format!("{method} failed: {err}")))?
+        .candid::<GetTransactionsResponse>()
+        .map_err(|err| BitcoinWalletError::Ledger(format!("{method} returned: {err}")))
+}
+
+/// Number of blocks `ledger` has recorded.
+pub async fn log_length(ledger: Principal) -> Result<Nat, BitcoinWalletError> {
+    Ok(get_transactions(ledger, "get_transactions", Nat::from(0u64), Nat::from(0u64)).await?.log_length)
+}
+
+/// Transfers out of `from` recorded in `ledger` from block `start` on, including blocks the
+/// ledger has moved to its archives.
+pub async fn transfers_from(ledger: Principal, from: &Account, start: Nat) -> Result<Vec<LedgerTransfer>, String> {
+    let mut transfers = Vec::new();
+    let mut next = start;
+    loop {
+        let response = get_transactions(ledger, "get_transactions", next.clone(), Nat::from(MAX_BLOCKS_PER_CALL))
+            .await
+            .map_err(|err| err.to_string())?;
+        let mut end = next.clone();
+        for range in response.archived_transactions {
+            let archive = range.callback.0;
+            let archived = Call::unbounded_wait(archive.principal, &archive.method)
+                .with_arg(GetTransactionsRequest {
+                    start: range.start.clone(),
+                    length: range.length.clone(),
+                })
+                .await
+                .map_err(|err| format!("archive {} failed: {err}", archive.method))?
+                .candid::<TransactionRange>()
+                .map_err(|err| format!("archive {} returned: {err}", archive.method))?;
+            end = end.max(range.start.clone() + archived.transactions.len());
+            collect_transfers(&mut transfers, from, range.start, archived.transactions);
+        }
+        if !response.transactions.is_empty() {
+            end = end.max(response.first_index.clone() + response.transactions.len());
+            collect_transfers(&mut transfers, from, response.first_index, response.transactions);
+        }
+        if end <= next || end >= response.log_length {
+            return Ok(transfers);
+        }
+        next = end;
+    }
+}
+
+fn collect_transfers(transfers: &mut Vec<LedgerTransfer>, from: &Account, first: Nat, blocks: Vec<LedgerTransaction>) {
+    for (offset, block) in blocks.into_iter().enumerate() {
+        if let Some(transfer) = block.transfer.filter(|transfer| transfer.from == *from) {
+            transfers.push(LedgerTransfer {
+                block_index: first.clone() + offset,
+                memo: transfer.memo.map(|memo| memo.into_vec()),
+            });
+        }
+    }
+}
+
+/// Sends planned transfer `index` out of the vault subaccount, returning the ledger block
+/// index. A transfer the ledger already executed returns the block it landed in.
+pub async fn transfer(
+    ledger: Principal,
+    vault_id: VaultId,
+    plan: &TokenPayoutPlan,
+    index: usize,
+) -> Result<Nat, TransferFailure> {
+    let planned = &plan.transfers[index];
+    let arg = TransferArg {
+        from_subaccount: Some(serde_bytes::ByteBuf::from(vault_subaccount(vault_id).to_vec())),
+        to: planned.account.clone(),
+        amount: planned.amount.clone(),
+        fee: Some(plan.fee.clone()),
+        memo: Some(serde_bytes::ByteBuf::from(transfer_memo(vault_id, index).to_vec())),
+        created_at_time: Some(plan.created_at_time),
+    };
+    let result = Call::unbounded_wait(ledger, "icrc1_transfer")
+        .with_arg(arg)
+        .await
+        .map_err(|err| TransferFailure::Failed(format!("icrc1_transfer failed: {err}")))?
+        .candid::<Result<Nat, TransferError>>()
+        .map_err(|err| TransferFailure::Failed(format!("icrc1_transfer returned: {err}")))?;
+    match result {
+        Ok(block_index) | Err(TransferError::Duplicate { duplicate_of: block_index }) => Ok(block_index),
+        Err(TransferError::TooOld) => Err(TransferFailure::TooOld),
+        Err(err) => Err(TransferFailure::Failed(format!("{err:?}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heir(seed: u8, weight_bps: u64) -> TokenHeir {
+        TokenHeir {
+            account: Account {
+                owner: Principal::from_slice(&[seed]),
+                subaccount: None,
+            },
+            weight_bps,
+        }
+    }
+
+    #[test]
+    fn vault_subaccounts_encode_the_vault_id() {
+        let subaccount = vault_subaccount(0x0102);
+        assert!(subaccount[..30].iter().all(|byte| *byte == 0));
+        assert_eq!(&subaccount[30..], &[1, 2]);
+        assert_ne!(vault_subaccount(1), vault_subaccount(2));
+    }
+
+    #[test]
+    fn weighted_split_assigns_the_remainder_to_the_last_heir() {
+        let heirs = vec![heir(1, 3_333), heir(2, 3_333), heir(3, 3_334)];
+        assert!(ensure_valid_heirs(&heirs).is_ok());
+        let shares = split_by_weight(&Nat::from(1_000u64), &heirs);
+        assert_eq!(shares, vec![Nat::from(333u64), Nat::from(333u64), Nat::from(334u64)]);
+    }
+
+    #[test]
+    fn payout_plans_pay_each_heir_once() {
+        let heirs = vec![heir(1, 5_000), heir(2, 5_000)];
+        assert!(TokenPayoutPlan::new(Nat::from(20u64), Nat::from(10u64), &heirs, Nat::from(0u64), 0).is_err());
+
+        let mut plan = TokenPayoutPlan::new(Nat::from(1_020u64), Nat::from(10u64), &heirs, Nat::from(0u64), 0).unwrap();
+        let amounts: Vec<Nat> = plan.transfers.iter().map(|transfer| transfer.amount.clone()).collect();
+        assert_eq!(amounts, vec![Nat::from(500u64), Nat::from(500u64)]);
+        assert_eq!(plan.unpaid(), vec![0, 1]);
+
+        plan.mark_paid(1, Nat::from(7u64));
+        assert_eq!(plan.unpaid(), vec![0]);
+        assert!(!plan.is_settled());
+        plan.mark_paid(0, Nat::from(8u64));
+        assert!(plan.is_settled());
+    }
+
+    #[test]
+    fn landed_transfers_are_found_by_memo() {
+        let heirs = vec![heir(1, 5_000), heir(2, 5_000)];
+        let mut plan = TokenPayoutPlan::new(Nat::from(1_020u64), Nat::from(10u64), &heirs, Nat::from(9u64), 5).unwrap();
+        let landed = vec![
+            LedgerTransfer {
+                block_index: Nat::from(11u64),
+                memo: Some(transfer_memo(7, 1).to_vec()),
+            },
+            LedgerTransfer {
+                block_index: Nat::from(12u64),
+                memo: Some(transfer_memo(8, 0).to_vec()),
+            },
+            LedgerTransfer {
+                block_index: Nat::from(13u64),
+                memo: None,
+            },
+        ];
+        plan.mark_landed(7, &landed);
+        assert_eq!(plan.unpaid(), vec![0]);
+        assert_eq!(plan.transfers[1].block_index, Some(Nat::from(11u64)));
+        plan.reissue(50);
+        assert_eq!(plan.created_at_time, 50);
+    }
+
+    #[test]
+    fn transfer_memos_identify_the_vault_and_heir() {
+        let memo = transfer_memo(0x0102, 3);
+        assert_eq!(&memo[6..8], &[1, 2]);
+        assert_eq!(memo[15], 3);
+        assert_ne!(transfer_memo(1, 0), transfer_memo(1, 1));
+        assert_ne!(transfer_memo(1, 0), transfer_memo(2, 0));
+    }
+
+    #[test]
+    fn subaccounts_must_be_32_bytes() {
+        let mut invalid = heir(1, 10_000);
+        invalid.account.subaccount = Some(serde_bytes::ByteBuf::from(vec![0; 31]));
+        assert!(ensure_valid_heirs(&[invalid]).is_err());
+        assert!(ensure_valid_heirs(&[heir(1, 10_000)]).is_ok());
+    }
+}
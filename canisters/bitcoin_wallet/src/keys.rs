@@ -0,0 +1,137 @@
+use crate::BitcoinWalletError;
+use bitcoin::Network as BtcNetwork;
+use candid::CandidType;
+use ic_cdk::bitcoin_canister::Network;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Threshold ECDSA key ids accepted on one Bitcoin network.
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct NetworkKeyIds {
+    pub network: Network,
+    #[serde(rename = "keyIds")]
+    pub key_ids: Vec<String>,
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct KeyConfig {
+    pub network: Network,
+    pub allowlist: BTreeMap<Network, Vec<String>>,
+}
+
+/// The active network and the key ids callers may use on it.
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct KeyConfigView {
+    pub network: Network,
+    #[serde(rename = "keyIds")]
+    pub key_ids: Vec<String>,
+}
+
+impl Default for KeyConfig {
+    fn default() -> Self {
+        Self::new(Network::Testnet, None)
+    }
+}
+
+impl KeyConfig {
+    /// Builds the configuration from init arguments. Networks without an explicit entry fall
+    /// back to the key ids the management canister offers there.
+    pub fn new(network: Network, overrides: Option<Vec<NetworkKeyIds>>) -> Self {
+        let mut allowlist: BTreeMap<Network, Vec<String>> = [Network::Mainnet, Network::Testnet, Network::Regtest]
+            .into_iter()
+            .map(|network| (network, default_key_ids(network)))
+            .collect();
+        for entry in overrides.unwrap_or_default() {
+            allowlist.insert(entry.network, entry.key_ids);
+        }
+        Self { network, allowlist }
+    }
+
+    /// Adds key ids to the allowlist without dropping any, so vaults on existing keys keep
+    /// working while new ones can migrate onto the added ids.
+    pub fn extend(&mut self, entries: Vec<NetworkKeyIds>) {
+        for entry in entries {
+            let allowed = self.allowlist.entry(entry.network).or_default();
+            for key_id in entry.key_ids {
+                if !allowed.contains(&key_id) {
+                    allowed.push(key_id);
+                }
+            }
+        }
+    }
+
+    pub fn active_key_ids(&self) -> &[String] {
+        self.allowlist.get(&self.network).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Rejects key ids outside the allowlist before any threshold ECDSA call spends cycles.
+    pub fn ensure_allowed(&self, key_id: &str) -> Result<(), BitcoinWalletError> {
+        if self.active_key_ids().iter().any(|allowed| allowed == key_id) {
+            return Ok(());
+        }
+        Err(BitcoinWalletError::UnknownKeyId {
+            key_id: key_id.to_string(),
+            network: self.network,
+        })
+    }
+
+    pub fn view(&self) -> KeyConfigView {
+        KeyConfigView {
+            network: self.network,
+            key_ids: self.active_key_ids().to_vec(),
+        }
+    }
+}
+
+fn default_key_ids(network: Network) -> Vec<String> {
+    let key_id = match network {
+        Network::Mainnet => "key_1",
+        Network::Testnet => "test_key_1",
+        Network::Regtest => "dfx_test_key",
+    };
+    vec![key_id.to_string()]
+}
+
+pub fn bitcoin_network(network: Network) -> BtcNetwork {
+    match network {
+        Network::Mainnet => BtcNetwork::Bitcoin,
+        Network::Testnet => BtcNetwork::Testnet,
+        Network::Regtest => BtcNetwork::Regtest,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowlist_follows_active_network() {
+        let config = KeyConfig::new(Network::Regtest, None);
+        assert!(config.ensure_allowed("dfx_test_key").is_ok());
+        assert!(matches!(
+            config.ensure_allowed("key_1"),
+            Err(BitcoinWalletError::UnknownKeyId { network: Network::Regtest, .. })
+        ));
+
+        let config = KeyConfig::new(
+            Network::Mainnet,
+            Some(vec![NetworkKeyIds {
+                network: Network::Mainnet,
+                key_ids: vec!["key_1".into(), "key_2".into()],
+            }]),
+        );
+        assert!(config.ensure_allowed("key_2").is_ok());
+        assert!(config.ensure_allowed("test_key_1").is_err());
+        assert_eq!(config.view().key_ids.len(), 2);
+    }
+
+    #[test]
+    fn extending_keeps_existing_key_ids() {
+        let mut config = KeyConfig::new(Network::Mainnet, None);
+        config.extend(vec![NetworkKeyIds {
+            network: Network::Mainnet,
+            key_ids: vec!["key_2".into(), "key_1".into()],
+        }]);
+        assert_eq!(config.active_key_ids(), ["key_1".to_string(), "key_2".to_string()]);
+    }
+}
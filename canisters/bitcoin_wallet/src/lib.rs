@@ -1,41 +1,58 @@
 use bitcoin::consensus::Encodable;
-use bitcoin::hashes::Hash;
+use bitcoin::hashes::{sha256, Hash};
 use bitcoin::key::PublicKey;
 use bitcoin::secp256k1;
 use bitcoin::sighash::{EcdsaSighashType, SighashCache};
 use bitcoin::{
-    absolute::LockTime, transaction::Version, Address, Amount, Network as BtcNetwork, OutPoint, ScriptBuf, Sequence,
-    Transaction, TxIn, TxOut, Txid, Witness,
+    transaction::Version, Address, Amount, Network as BtcNetwork, OutPoint, ScriptBuf, Transaction, TxIn, TxOut, Txid,
+    Witness,
 };
-use candid::{CandidType, Principal};
+use candid::{CandidType, Nat, Principal};
 use ic_cdk::api::{self};
-use ic_cdk::bitcoin_canister::{
-    bitcoin_get_current_fee_percentiles, bitcoin_get_utxos, bitcoin_send_transaction, GetCurrentFeePercentilesRequest,
-    GetUtxosRequest, Network, SendTransactionRequest, Utxo, UtxosFilter,
-};
-use ic_cdk::management_canister::{
-    ecdsa_public_key, sign_with_ecdsa, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgs, SignWithEcdsaArgs,
-};
-use ic_cdk::storage;
-use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query, update};
+use ic_cdk::bitcoin_canister::{Network, Utxo};
+use ic_cdk_macros::{init, post_upgrade, query, update};
 use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::str::FromStr;
 use thiserror::Error;
 
+use access::{InitArgs, ManagerRotation, Role, RoleAssignment};
+use audit::{AuditAction, AuditOutcome, AuditPage, AuditQuery};
+use backend::{BitcoinBackend, GuardianApi, IcBitcoin, IcGuardians, IcLedgers, IcSigner, Ledgers, Signer};
+use cycles::{CyclesMeter, Metered};
+use memory::{mutate_state, with_state};
+use policy::{FeeBearer, TransactionPolicy};
+
+mod access;
+mod audit;
+mod backend;
+mod bip322;
+mod ckbtc;
+mod cycles;
+mod descriptor;
+mod fees;
+mod grace;
+mod guardians;
+mod history;
+mod icrc;
+mod keys;
+mod memo;
+mod memory;
+mod metrics;
+mod policy;
+mod reserves;
+mod timer;
+mod upgrade;
+mod vesting;
+
 type VaultId = u64;
 
 const BASIS_POINTS: u64 = 10_000;
 const MIN_CONFIRMATIONS: u32 = 1;
 const DUST_THRESHOLD: u64 = 546;
 const FALLBACK_FEE_MSAT_PER_VBYTE: u64 = 15_000; // 15 sat/vB
-const BTC_NETWORK: BtcNetwork = BtcNetwork::Testnet;
-const BTC_CANISTER_NETWORK: Network = Network::Testnet;
-
-thread_local! {
-    static STATE: RefCell<VaultWalletState> = RefCell::new(VaultWalletState::default());
-}
+const WASM_PAGE_SIZE: u64 = 65_536;
 
 #[cfg(target_arch = "wasm32")]
 mod wasm_rand_shim {
@@ -50,8 +67,12 @@ mod wasm_rand_shim {
 
 #[derive(Default, Clone, CandidType, Deserialize, Serialize)]
 struct VaultWalletState {
-    wallets: BTreeMap<VaultId, VaultWallet>,
     vault_manager: Option<Principal>,
+    access: access::AccessControl,
+    keys: keys::KeyConfig,
+    ckbtc_minter: Option<Principal>,
+    guardian_mgr: Option<Principal>,
+    grace_period_secs: u64,
 }
 
 #[derive(Clone, CandidType, Deserialize, Serialize)]
@@ -62,6 +83,51 @@ struct VaultWallet {
     script_pub_key: Vec<u8>,
     public_key: Vec<u8>,
     network: Network,
+    owner: Option<Principal>,
+    /// Keys the vault has migrated off, oldest first. Their addresses can still receive
+    /// deposits, so every later migration sweeps them onto the current key.
+    retired_keys: Option<Vec<RetiredKey>>,
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+struct RetiredKey {
+    key_id: String,
+    derivation_path: Vec<Vec<u8>>,
+    address: String,
+    script_pub_key: Vec<u8>,
+    public_key: Vec<u8>,
+    retired_at: u64,
+}
+
+impl VaultWallet {
+    fn retire(&self, retired_at: u64) -> RetiredKey {
+        RetiredKey {
+            key_id: self.key_id.clone(),
+            derivation_path: self.derivation_path.clone(),
+            address: self.address.clone(),
+            script_pub_key: self.script_pub_key.clone(),
+            public_key: self.public_key.clone(),
+            retired_at,
+        }
+    }
+
+    fn retired_keys(&self) -> &[RetiredKey] {
+        self.retired_keys.as_deref().unwrap_or_default()
+    }
+
+    /// The wallet as it was under `key`, for spending what is left at its address.
+    fn under_retired(&self, key: &RetiredKey) -> VaultWallet {
+        VaultWallet {
+            key_id: key.key_id.clone(),
+            derivation_path: key.derivation_path.clone(),
+            address: key.address.clone(),
+            script_pub_key: key.script_pub_key.clone(),
+            public_key: key.public_key.clone(),
+            network: self.network,
+            owner: self.owner,
+            retired_keys: None,
+        }
+    }
 }
 
 #[derive(CandidType, Deserialize)]
@@ -70,6 +136,7 @@ pub struct GenerateVaultAddressArgs {
     pub vault_id: VaultId,
     #[serde(rename = "keyId")]
     pub key_id: String,
+    pub owner: Option<Principal>,
 }
 
 #[derive(CandidType, Serialize, Deserialize)]
@@ -84,26 +151,100 @@ pub struct HeirRecord {
     pub address: String,
     #[serde(rename = "weightBps")]
     pub weight_bps: u64,
+    /// Pays the heir in ckBTC instead; `address` must then be empty.
+    pub account: Option<icrc::Account>,
 }
 
-#[derive(CandidType, Deserialize)]
+impl HeirRecord {
+    fn label(&self) -> String {
+        match &self.account {
+            Some(account) => account.owner.to_text(),
+            None => self.address.clone(),
+        }
+    }
+}
+
+/// Heirs the vault manager has configured for a vault, used to price its payout ahead of time.
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+struct HeirConfig {
+    heirs: Vec<HeirRecord>,
+    updated_at: u64,
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
 pub struct ExecuteInheritanceArgs {
     #[serde(rename = "vaultId")]
     pub vault_id: VaultId,
     #[serde(rename = "keyId")]
     pub key_id: String,
     pub heirs: Vec<HeirRecord>,
+    /// Informational only: the wallet asks `guardian_mgr` for the threshold status itself.
     #[serde(rename = "guardian_submissions")]
     pub guardian_submissions: u64,
+    /// Hold the payout until deposits below the vault's confirmation threshold confirm,
+    /// instead of leaving them behind.
+    #[serde(rename = "waitForConfirmations")]
+    pub wait_for_confirmations: Option<bool>,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct DescriptorResponse {
+    pub descriptor: String,
+    pub address: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct SignedMessageResponse {
+    pub address: String,
+    pub signature: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct VaultReserve {
+    #[serde(rename = "vaultId")]
+    pub vault_id: VaultId,
+    pub address: String,
+    #[serde(rename = "balanceSats")]
+    pub balance_sats: u64,
+    #[serde(rename = "utxoCount")]
+    pub utxo_count: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct ReservesReport {
+    pub challenge: String,
+    #[serde(rename = "totalSats")]
+    pub total_sats: u64,
+    pub vaults: Vec<VaultReserve>,
+    pub psbt: String,
+    #[serde(rename = "generatedAt")]
+    pub generated_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct KeyMigrationResponse {
+    pub address: String,
+    #[serde(rename = "keyId")]
+    pub key_id: String,
+    #[serde(rename = "previousAddress")]
+    pub previous_address: String,
+    /// One sweep per retired key that held confirmed UTXOs, oldest key first.
+    #[serde(rename = "txIds")]
+    pub tx_ids: Vec<String>,
 }
 
 #[derive(CandidType, Serialize, Deserialize)]
 pub struct ExecuteInheritanceResponse {
     #[serde(rename = "txId")]
     pub tx_id: String,
+    #[serde(rename = "ckbtcDeposits")]
+    pub ckbtc_deposits: Vec<ckbtc::CkbtcDeposit>,
+    /// Commitment carried in the transaction's OP_RETURN output, if the vault adds one.
+    pub memo: Option<memo::InheritanceMemo>,
 }
 
-#[derive(Debug, Error)]
+/// Returned to callers as a Candid variant so clients can match on the case and its payload.
+#[derive(Debug, Error, CandidType, Deserialize)]
 enum BitcoinWalletError {
     #[error("vault {0} not registered")]
     VaultNotFound(VaultId),
@@ -111,16 +252,76 @@ enum BitcoinWalletError {
     VaultAlreadyExists(VaultId),
     #[error("invalid heir configuration")]
     InvalidHeirs,
+    #[error("vesting tranches must have positive shares summing to 10000 bps")]
+    InvalidSchedule,
+    #[error("invalid transaction policy: {0}")]
+    InvalidPolicy(String),
+    #[error("vault is locked until height {lock_time}; chain tip is {tip_height}")]
+    LockTimeNotReached { lock_time: u32, tip_height: u32 },
+    #[error("vault {0} vesting schedule has started paying out")]
+    VestingStarted(VaultId),
+    #[error("vault {0} has no configured heirs")]
+    HeirsNotConfigured(VaultId),
+    #[error("vault {vault_id} has {pending} deposits awaiting confirmation")]
+    AwaitingConfirmations { vault_id: VaultId, pending: u64 },
     #[error("no spendable UTXOs for vault {0}")]
     NoUtxos(VaultId),
-    #[error("insufficient funds after accounting for fees")]
-    InsufficientFunds,
+    #[error("payout of {amount} sats to {address} is below the dust threshold")]
+    DustPayout { address: String, amount: u64 },
+    #[error("heir address {address} is invalid: {reason}")]
+    InvalidHeirAddress { address: String, reason: String },
+    #[error("insufficient funds: {available} sats available, {required} required including fees")]
+    InsufficientFunds { available: u64, required: u64 },
+    #[error("insufficient cycles: {needed} needed, {available} available")]
+    InsufficientCycles { needed: u128, available: u128 },
     #[error("fee estimation unavailable")]
     FeeEstimationUnavailable,
     #[error("cryptographic failure: {0}")]
     Crypto(String),
     #[error("bitcoin network error: {0}")]
     Network(String),
+    #[error("vault {0} uses a script type without descriptor support")]
+    UnsupportedDescriptor(VaultId),
+    #[error("key id {key_id} is not allowed on {network:?}")]
+    UnknownKeyId { key_id: String, network: Network },
+    #[error("vault {0} already uses the requested key id")]
+    KeyUnchanged(VaultId),
+    #[error("vault {0} is already spending")]
+    SpendInProgress(VaultId),
+    #[error("ckBTC minter not configured")]
+    MinterNotConfigured,
+    #[error("ckBTC minter error: {0}")]
+    Minter(String),
+    #[error("ICRC-1 ledger error: {0}")]
+    Ledger(String),
+    #[error("vault {0} already has an execution scheduled")]
+    ExecutionPending(VaultId),
+    #[error("vault {0} has no scheduled execution")]
+    NoPendingExecution(VaultId),
+    #[error("grace period for vault {0} has elapsed")]
+    GracePeriodElapsed(VaultId),
+    #[error("tranche due at {due_at} falls inside the grace period, which ends at {earliest}")]
+    TrancheTooEarly { due_at: u64, earliest: u64 },
+    #[error("vault {0} has no vesting schedule left to cancel")]
+    NoVestingSchedule(VaultId),
+    #[error("a grace period is configured; use schedule_inheritance")]
+    GracePeriodRequired,
+    #[error("guardian manager not configured")]
+    GuardianMgrNotConfigured,
+    #[error("guardian manager error: {0}")]
+    Guardian(String),
+    #[error("guardian threshold not met for vault {vault_id}: {submitted} shares submitted")]
+    GuardianThresholdNotMet { vault_id: VaultId, submitted: u64 },
+    #[error("only {bound} guardians of vault {vault_id} can veto, {threshold} votes are needed")]
+    VetoQuorumUnreachable { vault_id: VaultId, bound: u64, threshold: u64 },
+    #[error("vault {0} already has an owner")]
+    OwnerAlreadySet(VaultId),
+    #[error("vault manager not configured")]
+    ManagerNotConfigured,
+    #[error("the vault manager role is assigned with set_vault_manager")]
+    ManagerRoleReserved,
+    #[error("canister is paused")]
+    Paused,
     #[error("unauthorized caller: {0}")]
     Unauthorized(Principal),
 }
@@ -131,371 +332,1759 @@ impl From<BitcoinWalletError> for String {
     }
 }
 
-#[init]
-fn init() {
-    STATE.with(|state| state.replace(VaultWalletState::default()));
+impl BitcoinWalletError {
+    /// Errors a timer retry cannot fix; the payout stops until the vault manager steps in.
+    fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            Self::InvalidPolicy(_) | Self::InvalidHeirAddress { .. } | Self::DustPayout { .. }
+        )
+    }
 }
 
-#[pre_upgrade]
-fn pre_upgrade() {
-    STATE.with(|state| {
-        storage::stable_save((state.borrow().clone(),)).expect("failed to persist wallet state");
+#[init]
+fn init(args: Option<InitArgs>) {
+    let args = args.unwrap_or_default();
+    let keys = keys::KeyConfig::new(args.network.unwrap_or(Network::Testnet), args.key_ids);
+    mutate_state(|state| {
+        state.keys = keys;
+        state.ckbtc_minter = args.ckbtc_minter;
+        state.guardian_mgr = args.guardian_mgr;
+        state.grace_period_secs = args.grace_period_secs.unwrap_or_default();
     });
+    if let Some(manager) = args.vault_manager {
+        mutate_state(|state| access::rotate_vault_manager(state, manager, api::msg_caller(), api::time()));
+        audit::record(None, AuditAction::ManagerChanged, AuditOutcome::Success, Some(manager.to_text()));
+    }
 }
 
+/// State lives in stable structures, so there is nothing to serialize before an upgrade.
+/// Canisters still carrying a `stable_save` snapshot are migrated once, here. Upgrades only
+/// take `key_ids` from their arguments, adding them to the allowlist so vaults can migrate
+/// onto new key ids; the rest of the configuration has its own admin methods.
 #[post_upgrade]
-fn post_upgrade() {
-    let restored: Result<(VaultWalletState,), _> = storage::stable_restore();
-    let state = restored.map(|r| r.0).unwrap_or_default();
-    STATE.with(|s| s.replace(state));
+fn post_upgrade(args: Option<InitArgs>) {
+    if memory::holds_legacy_snapshot() {
+        restore_legacy_snapshot();
+    }
+    if let Some(key_ids) = args.and_then(|args| args.key_ids) {
+        mutate_state(|state| state.keys.extend(key_ids));
+    }
+    memory::reindex_schedules();
+    timer::rearm();
 }
 
-#[update]
-fn set_vault_manager(manager: Principal) -> Result<(), String> {
-    let caller = api::msg_caller();
-    // Only allow controller to set the manager
-    if !api::is_controller(&caller) {
-        return Err(BitcoinWalletError::Unauthorized(caller).to_string());
+fn restore_legacy_snapshot() {
+    let snapshot = upgrade::decode_snapshot(&ic_cdk::stable::stable_bytes())
+        .unwrap_or_else(|err| ic_cdk::trap(format!("failed to restore wallet state: {err}")));
+    mutate_state(|state| *state = snapshot.state);
+    for (vault_id, wallet) in snapshot.wallets {
+        memory::insert_wallet(vault_id, wallet);
     }
-    mutate_state(|state| {
-        state.vault_manager = Some(manager);
-    });
-    Ok(())
 }
 
-#[update]
-async fn generate_vault_address(
-    args: GenerateVaultAddressArgs,
-) -> Result<BitcoinAddressResponse, String> {
+#[update(guard = "access::guard_admin")]
+fn set_vault_manager(manager: Principal) -> Result<(), BitcoinWalletError> {
     let caller = api::msg_caller();
-    with_state(|state| {
-        if let Some(manager) = state.vault_manager {
-            if manager != caller {
-                return Err(BitcoinWalletError::Unauthorized(caller));
-            }
-        }
-        Ok(())
-    })
-    .map_err(String::from)?;
+    mutate_state(|state| access::rotate_vault_manager(state, manager, caller, api::time()));
+    audit::record(None, AuditAction::ManagerChanged, AuditOutcome::Success, Some(manager.to_text()));
+    Ok(())
+}
 
-    if let Some(existing) = with_state(|state| state.wallets.get(&args.vault_id).cloned()) {
-        return Ok(BitcoinAddressResponse {
-            address: existing.address,
-            key_id: existing.key_id,
-        });
-    }
+#[query(guard = "access::guard_auditor")]
+fn manager_rotations() -> Vec<ManagerRotation> {
+    memory::manager_rotations()
+}
 
-    let derivation_path = vec![args.vault_id.to_be_bytes().to_vec()];
-    let key_id = EcdsaKeyId {
-        curve: EcdsaCurve::Secp256k1,
-        name: args.key_id.clone(),
-    };
+#[update(guard = "access::guard_admin")]
+fn set_ckbtc_minter(minter: Principal) -> Result<(), BitcoinWalletError> {
+    mutate_state(|state| state.ckbtc_minter = Some(minter));
+    Ok(())
+}
 
-    let pubkey_response = ecdsa_public_key(&EcdsaPublicKeyArgs {
-        canister_id: Some(api::canister_self()),
-        derivation_path: derivation_path.clone(),
-        key_id: key_id.clone(),
-    })
-    .await
-    .map_err(|err| BitcoinWalletError::Crypto(format!("ecdsa_public_key failed: {err:?}")))?;
+#[query]
+fn ckbtc_minter() -> Option<Principal> {
+    with_state(|state| state.ckbtc_minter)
+}
 
-    let public_key = PublicKey::from_slice(&pubkey_response.public_key)
-        .map_err(|err| BitcoinWalletError::Crypto(err.to_string()))?;
-    let segwit_address = Address::p2wpkh(&public_key, BTC_NETWORK)
-        .map_err(|err| BitcoinWalletError::Crypto(err.to_string()))?;
+#[update(guard = "access::guard_admin")]
+fn set_guardian_mgr(guardian_mgr: Principal) -> Result<(), BitcoinWalletError> {
+    mutate_state(|state| state.guardian_mgr = Some(guardian_mgr));
+    Ok(())
+}
 
-    let wallet = VaultWallet {
-        key_id: args.key_id.clone(),
-        derivation_path,
-        address: segwit_address.to_string(),
-        script_pub_key: segwit_address.script_pubkey().to_bytes(),
-        public_key: pubkey_response.public_key.clone(),
-        network: BTC_CANISTER_NETWORK,
-    };
+#[query]
+fn guardian_mgr() -> Option<Principal> {
+    with_state(|state| state.guardian_mgr)
+}
 
-    mutate_state(|state| -> Result<(), BitcoinWalletError> {
-        if state.wallets.contains_key(&args.vault_id) {
-            return Err(BitcoinWalletError::VaultAlreadyExists(args.vault_id));
-        }
-        state.wallets.insert(args.vault_id, wallet.clone());
-        Ok(())
-    })
-    .map_err(String::from)?;
+#[update(guard = "access::guard_admin")]
+fn set_grace_period(seconds: u64) -> Result<(), BitcoinWalletError> {
+    mutate_state(|state| state.grace_period_secs = seconds);
+    Ok(())
+}
 
-    Ok(BitcoinAddressResponse {
-        address: wallet.address,
-        key_id: args.key_id,
-    })
+#[query]
+fn grace_period() -> u64 {
+    with_state(|state| state.grace_period_secs)
 }
 
-#[update]
-async fn execute_inheritance(
-    args: ExecuteInheritanceArgs,
-) -> Result<ExecuteInheritanceResponse, String> {
-    let caller = api::msg_caller();
-    with_state(|state| {
-        if let Some(manager) = state.vault_manager {
-            if manager != caller {
-                return Err(BitcoinWalletError::Unauthorized(caller));
-            }
-        }
-        Ok(())
-    })
-    .map_err(String::from)?;
+#[query]
+fn key_config() -> keys::KeyConfigView {
+    with_state(|state| state.keys.view())
+}
 
-    ensure_valid_heirs(&args.heirs)?;
-    let wallet = with_state(|state| {
-        state
-            .wallets
-            .get(&args.vault_id)
-            .cloned()
-            .ok_or(BitcoinWalletError::VaultNotFound(args.vault_id))
-    })?;
+#[query(guard = "access::guard_auditor")]
+fn audit_log(query: AuditQuery) -> AuditPage {
+    audit::page(&query)
+}
 
-    if wallet.key_id != args.key_id {
-        return Err(BitcoinWalletError::Crypto("mismatched key id".into()).into());
-    }
+#[update(guard = "access::guard_admin")]
+fn grant_role(principal: Principal, role: Role) -> Result<(), BitcoinWalletError> {
+    access::ensure_assignable(role)?;
+    mutate_state(|state| access::grant_role(state, principal, role));
+    Ok(())
+}
 
-    let utxo_response = bitcoin_get_utxos(&GetUtxosRequest {
-        network: wallet.network,
-        address: wallet.address.clone(),
-        filter: Some(UtxosFilter::MinConfirmations(MIN_CONFIRMATIONS)),
-    })
-    .await
-    .map_err(|err| BitcoinWalletError::Network(format!("bitcoin_get_utxos failed: {err:?}")))?;
+#[update(guard = "access::guard_admin")]
+fn revoke_role(principal: Principal, role: Role) -> Result<(), BitcoinWalletError> {
+    access::ensure_assignable(role)?;
+    mutate_state(|state| access::revoke_role(state, principal, role));
+    Ok(())
+}
 
-    if utxo_response.utxos.is_empty() {
-        return Err(BitcoinWalletError::NoUtxos(args.vault_id).into());
-    }
+#[query(guard = "access::guard_auditor")]
+fn list_roles() -> Vec<RoleAssignment> {
+    with_state(access::role_assignments)
+}
 
-    let managed_utxos = normalize_utxos(&utxo_response.utxos)?;
-    let total_value: u64 = managed_utxos.iter().map(|u| u.value).sum();
+#[update(guard = "access::guard_pauser")]
+fn pause() {
+    mutate_state(|state| access::set_paused(state, true));
+}
+
+#[update(guard = "access::guard_admin")]
+fn unpause() {
+    mutate_state(|state| access::set_paused(state, false));
+    timer::rearm();
+}
 
-    let fee_rate = fetch_fee_rate(wallet.network).await?;
-    let estimated_fee = estimate_fee_sat(fee_rate, managed_utxos.len(), args.heirs.len())
-        .ok_or(BitcoinWalletError::FeeEstimationUnavailable)?;
+#[query]
+fn is_paused() -> bool {
+    with_state(access::is_paused)
+}
 
-    if total_value <= estimated_fee {
-        return Err(BitcoinWalletError::InsufficientFunds.into());
-    }
+#[update(guard = "access::guard_vault_manager")]
+async fn generate_vault_address(
+    args: GenerateVaultAddressArgs,
+) -> Result<BitcoinAddressResponse, BitcoinWalletError> {
+    let result = derive_vault_address(&args).await;
+    audit::record_result(args.vault_id, AuditAction::AddressGenerated, &result, |response| {
+        response.address.clone()
+    });
+    result
+}
 
-    let distributable = total_value - estimated_fee;
-    let payouts = allocate_payouts(distributable, &args.heirs)?;
-    let outputs = build_outputs(&args.heirs, &payouts)?;
+/// Pays the vault out immediately. With `waitForConfirmations` set and deposits still short of
+/// the vault's confirmation threshold, the call fails with `AwaitingConfirmations` and the
+/// execution is handed to the timer, which broadcasts it once they confirm. Whether it was
+/// handed over is recorded as `ExecutionDeferred` and visible through `pending_execution`.
+#[update(guard = "access::guard_vault_manager")]
+async fn execute_inheritance(
+    args: ExecuteInheritanceArgs,
+) -> Result<ExecuteInheritanceResponse, BitcoinWalletError> {
+    audit::record(Some(args.vault_id), AuditAction::ExecutionAttempted, AuditOutcome::Success, None);
+    let wait = args.wait_for_confirmations.unwrap_or_default();
+    let result = if with_state(|state| state.grace_period_secs) > 0 {
+        Err(BitcoinWalletError::GracePeriodRequired)
+    } else {
+        broadcast_inheritance(&args, wait).await
+    };
+    match &result {
+        Err(err @ BitcoinWalletError::AwaitingConfirmations { .. }) => defer_execution(&args, err),
+        _ => audit::record_result(args.vault_id, AuditAction::ExecutionBroadcast, &result, |response| {
+            response.tx_id.clone()
+        }),
+    }
+    result
+}
 
-    let unsigned_tx = build_unsigned_transaction(&managed_utxos, outputs)?;
-    let signed_tx = sign_transaction(unsigned_tx, &wallet, &managed_utxos).await?;
+/// Hands an execution held back by `reason` to the timer. The caller still gets `reason`; a
+/// request that cannot be held, e.g. because one is already pending, is only audited.
+fn defer_execution(args: &ExecuteInheritanceArgs, reason: &BitcoinWalletError) {
+    let deferred = hold_execution(args.clone()).map(|mut pending| {
+        pending.defer(reason.to_string(), api::time());
+        memory::insert_pending_execution(args.vault_id, pending);
+        timer::rearm();
+    });
+    audit::record_result(args.vault_id, AuditAction::ExecutionDeferred, &deferred, |()| reason.to_string());
+}
 
-    let mut tx_bytes = Vec::new();
-    signed_tx
-        .consensus_encode(&mut tx_bytes)
-        .map_err(|err| BitcoinWalletError::Crypto(err.to_string()))?;
+/// First phase of an execution: validates the request and holds it for the configured grace
+/// period, during which the owner or a guardian quorum may cancel it. The timer broadcasts
+/// it afterwards. Nothing is scheduled while too few guardians have bound a principal to
+/// reach that quorum.
+#[update(guard = "access::guard_vault_manager")]
+async fn schedule_inheritance(args: ExecuteInheritanceArgs) -> Result<grace::PendingExecution, BitcoinWalletError> {
+    let result = match ensure_schedulable(args.vault_id).await {
+        Ok(()) => hold_execution(args.clone()),
+        Err(err) => Err(err),
+    };
+    audit::record_result(args.vault_id, AuditAction::InheritanceScheduled, &result, |pending| {
+        format!("executes at {}", pending.execute_at)
+    });
+    result
+}
 
-    bitcoin_send_transaction(&SendTransactionRequest {
-        network: wallet.network,
-        transaction: tx_bytes,
-    })
-    .await
-    .map_err(|err| BitcoinWalletError::Network(format!("bitcoin_send_transaction failed: {err:?}")))?;
+async fn ensure_schedulable(vault_id: VaultId) -> Result<(), BitcoinWalletError> {
+    guardians::ensure_threshold_met(vault_id).await?;
+    guardians::guardian_quorum(vault_id).await?.ensure_veto_reachable(vault_id)
+}
 
-    Ok(ExecuteInheritanceResponse {
-        tx_id: signed_tx.txid().to_string(),
-    })
+fn hold_execution(args: ExecuteInheritanceArgs) -> Result<grace::PendingExecution, BitcoinWalletError> {
+    validate_execution(&args)?;
+    if memory::pending_execution(args.vault_id).is_some_and(|pending| pending.is_scheduled()) {
+        return Err(BitcoinWalletError::ExecutionPending(args.vault_id));
+    }
+    let grace_period_secs = with_state(|state| state.grace_period_secs);
+    let vault_id = args.vault_id;
+    let pending = grace::PendingExecution::new(args, api::msg_caller(), api::time(), grace_period_secs);
+    memory::insert_pending_execution(vault_id, pending.clone());
+    timer::rearm();
+    Ok(pending)
 }
 
-#[query]
-fn wallet_view(vault_id: VaultId) -> Option<BitcoinAddressResponse> {
-    with_state(|state| {
-        state.wallets.get(&vault_id).map(|wallet| BitcoinAddressResponse {
-            address: wallet.address.clone(),
-            key_id: wallet.key_id.clone(),
-        })
-    })
+/// Vetoes a scheduled execution. The vault owner cancels outright; each guardian call counts
+/// as one vote and the execution is cancelled once the guardian threshold is reached.
+#[update(guard = "access::guard_not_paused")]
+async fn cancel_inheritance(vault_id: VaultId) -> Result<grace::PendingExecution, BitcoinWalletError> {
+    let caller = api::msg_caller();
+    open_execution(vault_id)?;
+    let guardian_threshold = veto_threshold(vault_id, caller).await?;
+
+    // Re-check after the quorum lookup: the window may have closed in the meantime.
+    let mut pending = open_execution(vault_id)?;
+    let now = api::time();
+    match guardian_threshold {
+        None => pending.cancel(vec![caller], now),
+        Some(threshold) => pending.vote_cancel(caller, threshold, now),
+    }
+    memory::insert_pending_execution(vault_id, pending.clone());
+    if pending.is_scheduled() {
+        let detail = format!("{} of {} votes", pending.cancel_votes.len(), guardian_threshold.unwrap_or_default());
+        audit::record(Some(vault_id), AuditAction::CancelVoteCast, AuditOutcome::Success, Some(detail));
+    } else {
+        audit::record(Some(vault_id), AuditAction::InheritanceCancelled, AuditOutcome::Success, None);
+        timer::rearm();
+    }
+    Ok(pending)
 }
 
-fn ensure_valid_heirs(heirs: &[HeirRecord]) -> Result<(), String> {
-    if heirs.is_empty() {
-        return Err(BitcoinWalletError::InvalidHeirs.into());
+/// How `caller` may veto a payout of `vault_id`: `None` when they own the vault and cancel
+/// outright, or the number of guardian votes that cancels it when they are a guardian.
+async fn veto_threshold(vault_id: VaultId, caller: Principal) -> Result<Option<u64>, BitcoinWalletError> {
+    let owner = memory::wallet(vault_id).and_then(|wallet| wallet.owner);
+    if owner == Some(caller) {
+        return Ok(None);
     }
-    let total: u64 = heirs.iter().map(|h| h.weight_bps).sum();
-    if total != BASIS_POINTS {
-        return Err(BitcoinWalletError::InvalidHeirs.into());
+    let quorum = guardians::guardian_quorum(vault_id).await?;
+    if !quorum.guardians.contains(&caller) {
+        return Err(BitcoinWalletError::Unauthorized(caller));
     }
-    Ok(())
+    Ok(Some(quorum.threshold))
 }
 
-fn normalize_utxos(utxos: &[Utxo]) -> Result<Vec<ManagedUtxo>, BitcoinWalletError> {
-    utxos
-        .iter()
-        .map(|utxo| {
-            let txid =
-                Txid::from_slice(&utxo.outpoint.txid).map_err(|err| BitcoinWalletError::Crypto(err.to_string()))?;
-            Ok(ManagedUtxo {
-                outpoint: OutPoint::new(txid, utxo.outpoint.vout),
-                value: utxo.value,
-            })
-        })
-        .collect()
+fn open_execution(vault_id: VaultId) -> Result<grace::PendingExecution, BitcoinWalletError> {
+    let pending = memory::pending_execution(vault_id)
+        .filter(|pending| pending.is_scheduled())
+        .ok_or(BitcoinWalletError::NoPendingExecution(vault_id))?;
+    if !pending.window_open(api::time()) {
+        return Err(BitcoinWalletError::GracePeriodElapsed(vault_id));
+    }
+    Ok(pending)
 }
 
-async fn fetch_fee_rate(network: Network) -> Result<u64, BitcoinWalletError> {
-    let percentiles = bitcoin_get_current_fee_percentiles(&GetCurrentFeePercentilesRequest { network })
-        .await
-        .map_err(|err| BitcoinWalletError::Network(format!("fee percentiles failed: {err:?}")))?;
-    if percentiles.is_empty() {
-        return Ok(FALLBACK_FEE_MSAT_PER_VBYTE / 1_000);
+/// Visible to the vault owner, so they can veto, and to auditors.
+#[query]
+fn pending_execution(vault_id: VaultId) -> Result<Option<grace::PendingExecution>, BitcoinWalletError> {
+    let caller = api::msg_caller();
+    let is_owner = memory::wallet(vault_id).is_some_and(|wallet| wallet.owner == Some(caller));
+    if !is_owner && !with_state(|state| access::can_audit(state, caller)) {
+        return Err(BitcoinWalletError::Unauthorized(caller));
     }
-    let median_index = percentiles.len() / 2;
-    let msat_per_byte = percentiles[median_index];
-    let sat_per_vbyte = (msat_per_byte + 999) / 1_000;
-    Ok(sat_per_vbyte.max(1))
+    Ok(memory::pending_execution(vault_id))
 }
 
-fn estimate_fee_sat(rate: u64, inputs: usize, outputs: usize) -> Option<u64> {
-    let vbytes = 10 + inputs * 68 + outputs * 31;
-    rate.checked_mul(vbytes as u64)
+/// Broadcasts every scheduled execution whose grace period is over.
+async fn execute_due_inheritances() {
+    let now = api::time();
+    for vault_id in memory::due_executions(now) {
+        // A pause lands between payouts while earlier ones await.
+        if with_state(access::is_paused) {
+            return;
+        }
+        // Re-read: an earlier payout in this run may have awaited while the schedule changed.
+        let Some(mut pending) = memory::pending_execution(vault_id).filter(|pending| pending.is_due(now)) else {
+            continue;
+        };
+        let result = broadcast_inheritance(&pending.request, pending.waits_for_confirmations(now)).await;
+        if let Err(err @ BitcoinWalletError::AwaitingConfirmations { .. }) = &result {
+            pending.defer(err.to_string(), api::time());
+            memory::insert_pending_execution(vault_id, pending);
+            continue;
+        }
+        audit::record_system(vault_id, AuditAction::ExecutionBroadcast, &result, |response| {
+            response.tx_id.clone()
+        });
+        match result {
+            Ok(response) => pending.mark_executed(response.tx_id, response.memo, api::time()),
+            Err(err) if err.is_terminal() => pending.mark_halted(err.to_string(), api::time()),
+            Err(err) => {
+                if pending.mark_failed(err.to_string(), api::time()) {
+                    record_retries_exhausted(vault_id, pending.last_error.clone());
+                }
+            }
+        }
+        memory::insert_pending_execution(vault_id, pending);
+    }
 }
 
-fn allocate_payouts(total: u64, heirs: &[HeirRecord]) -> Result<Vec<u64>, BitcoinWalletError> {
-    let mut allocations = Vec::with_capacity(heirs.len());
-    let mut assigned = 0u64;
-    for (index, heir) in heirs.iter().enumerate() {
-        let amount = if index == heirs.len() - 1 {
-            total
-                .checked_sub(assigned)
-                .ok_or_else(|| BitcoinWalletError::Crypto("payout overflow".to_string()))?
-        } else {
-            (total * heir.weight_bps) / BASIS_POINTS
+/// Asks the minter to credit ckBTC payouts whose deposits are due another attempt. A mint may
+/// credit deposits from other payouts to the same heir, so every pending deposit whose payout
+/// transaction the minter reports as minted settles; deposits still uncredited after
+/// `MAX_MINT_WAIT_NANOS` are dropped.
+async fn retry_pending_mints() {
+    let Some(minter) = with_state(|state| state.ckbtc_minter) else {
+        return;
+    };
+    let now = api::time();
+    for (key, _) in memory::pending_mints() {
+        // Re-read: an earlier notification in this run may have settled or dropped the entry.
+        let Some(mint) = memory::pending_mint(key).filter(|mint| mint.is_due(now)) else {
+            continue;
         };
-        if amount < DUST_THRESHOLD {
-            return Err(BitcoinWalletError::InvalidHeirs);
+        let account = mint.deposit.account.clone();
+        let response = ckbtc::notify_deposit(minter, &account).await;
+        let minted = response.as_deref().unwrap_or_default();
+        for (key, settled) in memory::pending_mints() {
+            if settled.deposit.account != account {
+                continue;
+            }
+            let Some(amount) = ckbtc::minted_from(minted, &settled.tx_id) else {
+                continue;
+            };
+            if memory::remove_pending_mint(key).is_some() {
+                let result: Result<_, String> = Ok(amount);
+                audit::record_system(key.0, AuditAction::CkbtcMinted, &result, |amount| {
+                    format!("{}: {amount} sats minted for {}", settled.tx_id, account.owner)
+                });
+            }
+        }
+        // The notification awaited, so the entry may have settled in the meantime.
+        let Some(mut mint) = memory::pending_mint(key) else {
+            continue;
+        };
+        let reason = response.err().unwrap_or_else(|| "minter has not credited this deposit yet".into());
+        let now = api::time();
+        if mint.expired(now) {
+            memory::remove_pending_mint(key);
+            let attempts = mint.attempts + 1;
+            let result: Result<u64, String> =
+                Err(format!("{}: gave up after {attempts} attempts: {reason}", mint.tx_id));
+            audit::record_system(key.0, AuditAction::CkbtcMinted, &result, u64::to_string);
+        } else {
+            mint.defer(reason, now);
+            memory::update_pending_mint(key, mint);
         }
-        assigned = assigned
-            .checked_add(amount)
-            .ok_or_else(|| BitcoinWalletError::Crypto("payout overflow".to_string()))?;
-        allocations.push(amount);
     }
-    Ok(allocations)
 }
 
-fn build_outputs(heirs: &[HeirRecord], payouts: &[u64]) -> Result<Vec<TxOut>, BitcoinWalletError> {
-    heirs
-        .iter()
-        .zip(payouts.iter())
-        .map(|(heir, amount)| {
-            let address = Address::from_str(&heir.address)
-                .map_err(|err| BitcoinWalletError::Crypto(err.to_string()))?
-                .require_network(BTC_NETWORK)
-                .map_err(|err| BitcoinWalletError::Crypto(err.to_string()))?;
-            Ok(TxOut {
-                value: Amount::from_sat(*amount),
-                script_pubkey: address.script_pubkey(),
-            })
-        })
-        .collect()
+/// ckBTC payouts of `vault_id` the minter has not credited yet.
+#[query(guard = "access::guard_auditor")]
+fn pending_ckbtc_deposits(vault_id: VaultId) -> Vec<ckbtc::PendingMint> {
+    memory::vault_pending_mints(vault_id)
 }
 
-fn build_unsigned_transaction(utxos: &[ManagedUtxo], outputs: Vec<TxOut>) -> Result<Transaction, BitcoinWalletError> {
-    let inputs = utxos
-        .iter()
-        .map(|utxo| TxIn {
-            previous_output: utxo.outpoint,
-            script_sig: ScriptBuf::new(),
-            sequence: Sequence::MAX,
-            witness: Witness::new(),
-        })
-        .collect();
-    Ok(Transaction {
-        version: Version(2),
-        lock_time: LockTime::ZERO,
-        input: inputs,
-        output: outputs,
-    })
+/// Replaces the vault's payout plan with dated tranches, each paid by the canister timer
+/// when due. A schedule can be replaced until its first tranche falls due, and again once it
+/// has been cancelled or halted.
+#[update(guard = "access::guard_vault_manager")]
+fn schedule_vesting(args: vesting::ScheduleVestingArgs) -> Result<vesting::VestingSchedule, BitcoinWalletError> {
+    ensure_valid_heirs(&args.heirs)?;
+    if memory::wallet(args.vault_id).is_none() {
+        return Err(BitcoinWalletError::VaultNotFound(args.vault_id));
+    }
+    let now = api::time();
+    if memory::vesting_schedule(args.vault_id).is_some_and(|schedule| {
+        let stopped = schedule.is_cancelled() || schedule.is_halted();
+        !stopped && (schedule.started() || schedule.due_tranche(now).is_some())
+    }) {
+        return Err(BitcoinWalletError::VestingStarted(args.vault_id));
+    }
+    let earliest = grace::earliest_payout(now, with_state(|state| state.grace_period_secs));
+    let schedule = vesting::VestingSchedule::new(args.tranches, args.heirs, now, earliest)?;
+    memory::insert_vesting_schedule(args.vault_id, schedule.clone());
+    timer::rearm();
+    Ok(schedule)
 }
 
-async fn sign_transaction(
-    unsigned_tx: Transaction,
-    wallet: &VaultWallet,
-    utxos: &[ManagedUtxo],
-) -> Result<Transaction, BitcoinWalletError> {
-    let mut cache = SighashCache::new(&unsigned_tx);
-    let mut signed_tx = unsigned_tx.clone();
-    let public_key = PublicKey::from_slice(&wallet.public_key)
-        .map_err(|err| BitcoinWalletError::Crypto(err.to_string()))?;
-    let script_code = ScriptBuf::new_p2pkh(&public_key.pubkey_hash());
+/// Vetoes the unpaid tranches of a vesting schedule, on the same terms as
+/// `cancel_inheritance`. Only possible while no tranche is due.
+#[update(guard = "access::guard_not_paused")]
+async fn cancel_vesting(vault_id: VaultId) -> Result<vesting::VestingSchedule, BitcoinWalletError> {
+    let caller = api::msg_caller();
+    open_vesting(vault_id)?;
+    let guardian_threshold = veto_threshold(vault_id, caller).await?;
 
-    for (index, utxo) in utxos.iter().enumerate() {
-        let sighash = cache
-            .p2wpkh_signature_hash(
-                index,
-                script_code.as_script(),
-                Amount::from_sat(utxo.value),
-                EcdsaSighashType::All,
-            )
-            .map_err(|err| BitcoinWalletError::Crypto(err.to_string()))?;
-        let digest = sighash.to_byte_array();
-        let signature = sign_digest(wallet, &digest).await?;
-        let mut witness = Witness::new();
-        witness.push(signature);
-        witness.push(public_key.to_bytes());
-        signed_tx
-            .input
-            .get_mut(index)
-            .ok_or(BitcoinWalletError::Crypto("input missing".into()))?
-            .witness = witness;
+    // Re-check after the quorum lookup: a tranche may have fallen due in the meantime.
+    let mut schedule = open_vesting(vault_id)?;
+    let now = api::time();
+    match guardian_threshold {
+        None => schedule.cancel(now),
+        Some(threshold) => schedule.vote_cancel(caller, threshold, now),
+    }
+    memory::insert_vesting_schedule(vault_id, schedule.clone());
+    if schedule.is_cancelled() {
+        audit::record(Some(vault_id), AuditAction::VestingCancelled, AuditOutcome::Success, None);
+        timer::rearm();
+    } else {
+        let detail = format!("{} of {} votes", schedule.cancel_vote_count(), guardian_threshold.unwrap_or_default());
+        audit::record(Some(vault_id), AuditAction::CancelVoteCast, AuditOutcome::Success, Some(detail));
     }
+    Ok(schedule)
+}
 
-    Ok(signed_tx)
+fn open_vesting(vault_id: VaultId) -> Result<vesting::VestingSchedule, BitcoinWalletError> {
+    let schedule = memory::vesting_schedule(vault_id)
+        .filter(|schedule| !schedule.is_cancelled() && schedule.next_run_at().is_some())
+        .ok_or(BitcoinWalletError::NoVestingSchedule(vault_id))?;
+    if !schedule.window_open(api::time()) {
+        return Err(BitcoinWalletError::GracePeriodElapsed(vault_id));
+    }
+    Ok(schedule)
 }
 
-async fn sign_digest(wallet: &VaultWallet, message_hash: &[u8; 32]) -> Result<Vec<u8>, BitcoinWalletError> {
-    let response = sign_with_ecdsa(&SignWithEcdsaArgs {
-        message_hash: message_hash.to_vec(),
-        derivation_path: wallet.derivation_path.clone(),
-        key_id: EcdsaKeyId {
-            curve: EcdsaCurve::Secp256k1,
-            name: wallet.key_id.clone(),
-        },
-    })
-    .await
-    .map_err(|err| BitcoinWalletError::Crypto(format!("sign_with_ecdsa failed: {err:?}")))?;
+#[query(guard = "access::guard_auditor")]
+fn vesting_schedule(vault_id: VaultId) -> Option<vesting::VestingSchedule> {
+    memory::vesting_schedule(vault_id)
+}
 
-    let secp_sig = secp256k1::ecdsa::Signature::from_compact(&response.signature)
+#[update(guard = "access::guard_vault_manager")]
+fn set_transaction_policy(vault_id: VaultId, policy: TransactionPolicy) -> Result<(), BitcoinWalletError> {
+    if memory::wallet(vault_id).is_none() {
+        return Err(BitcoinWalletError::VaultNotFound(vault_id));
+    }
+    policy.validate()?;
+    if let Some(FeeBearer::Heir(_)) = &policy.fee_bearer {
+        let heirs = memory::heir_config(vault_id).ok_or(BitcoinWalletError::HeirsNotConfigured(vault_id))?.heirs;
+        policy.ensure_bearer_among(&heirs)?;
+    }
+    let detail = format!("{policy:?}");
+    memory::insert_transaction_policy(vault_id, policy);
+    audit::record(Some(vault_id), AuditAction::PolicyUpdated, AuditOutcome::Success, Some(detail));
+    Ok(())
+}
+
+#[query]
+fn transaction_policy(vault_id: VaultId) -> TransactionPolicy {
+    memory::transaction_policy(vault_id)
+}
+
+#[update(guard = "access::guard_vault_manager")]
+fn configure_heirs(vault_id: VaultId, heirs: Vec<HeirRecord>) -> Result<(), BitcoinWalletError> {
+    if memory::wallet(vault_id).is_none() {
+        return Err(BitcoinWalletError::VaultNotFound(vault_id));
+    }
+    ensure_valid_heirs(&heirs)?;
+    memory::transaction_policy(vault_id).ensure_bearer_among(&heirs)?;
+    let detail = format!("{} heirs", heirs.len());
+    memory::insert_heir_config(vault_id, HeirConfig { heirs, updated_at: api::time() });
+    audit::record(Some(vault_id), AuditAction::HeirsConfigured, AuditOutcome::Success, Some(detail));
+    Ok(())
+}
+
+#[query(guard = "access::guard_auditor")]
+fn configured_heirs(vault_id: VaultId) -> Vec<HeirRecord> {
+    memory::heir_config(vault_id).map(|config| config.heirs).unwrap_or_default()
+}
+
+/// Fee rates at low, medium and high priority, and what sending the whole vault to its
+/// configured heirs would cost at each. For the vault owner, auditors and the vault manager.
+#[update(guard = "access::guard_vault_reader")]
+async fn estimate_fees(vault_id: VaultId) -> Result<fees::FeeEstimate, BitcoinWalletError> {
+    let wallet = memory::wallet(vault_id).ok_or(BitcoinWalletError::VaultNotFound(vault_id))?;
+    let heirs = memory::heir_config(vault_id).ok_or(BitcoinWalletError::HeirsNotConfigured(vault_id))?.heirs;
+
+    let meter = CyclesMeter::new(vault_id);
+    let backend = Metered::new(&IcBitcoin, &meter);
+    let policy = memory::transaction_policy(vault_id);
+    let (utxos, _) = fetch_utxos(&backend, &wallet, policy.min_confirmations()).await?;
+    let tiers = fetch_fee_tiers(&backend, wallet.network).await?;
+    let balance = utxos.iter().map(|utxo| utxo.value).sum();
+    let memo = policy.inheritance_memo.unwrap_or_default();
+    fees::FeeEstimate::new(vault_id, balance, utxos.len(), heirs.len(), memo, tiers)
+}
+
+/// Cycles `operation` would attach to system API calls for `vault_id` at current prices.
+#[query]
+fn estimate_cycles(vault_id: VaultId, operation: cycles::Operation) -> Result<u128, BitcoinWalletError> {
+    let wallet = memory::wallet(vault_id).ok_or(BitcoinWalletError::VaultNotFound(vault_id))?;
+    cycles::estimate(&IcBitcoin, &IcSigner, &wallet, &operation)
+}
+
+#[query(guard = "access::guard_auditor")]
+fn cycles_spent(vault_id: VaultId) -> u128 {
+    memory::cycles_spent(vault_id)
+}
+
+/// Pays every tranche due now, one per vault; later tranches of the same vault run on the
+/// next timer tick.
+async fn pay_due_tranches() {
+    let now = api::time();
+    for vault_id in memory::due_vesting_schedules(now) {
+        if with_state(access::is_paused) {
+            return;
+        }
+        let Some(mut schedule) = memory::vesting_schedule(vault_id) else {
+            continue;
+        };
+        let Some(index) = schedule.due_tranche(now) else {
+            continue;
+        };
+        let result = pay_tranche(vault_id, &schedule, index).await;
+        audit::record_system(vault_id, AuditAction::TranchePaid, &result, |response| response.tx_id.clone());
+        match result {
+            Ok(response) => schedule.mark_paid(index, response.tx_id, response.memo, api::time()),
+            Err(err) if err.is_terminal() => schedule.mark_halted(index, err.to_string(), api::time()),
+            Err(err) => {
+                if schedule.mark_failed(index, err.to_string(), api::time()) {
+                    record_retries_exhausted(vault_id, schedule.tranches[index].last_error.clone());
+                }
+            }
+        }
+        memory::insert_vesting_schedule(vault_id, schedule);
+    }
+}
+
+fn record_retries_exhausted(vault_id: VaultId, error: Option<String>) {
+    let result: Result<(), String> = Err(error.unwrap_or_default());
+    audit::record_system(vault_id, AuditAction::RetriesExhausted, &result, |_| String::new());
+}
+
+async fn pay_tranche(
+    vault_id: VaultId,
+    schedule: &vesting::VestingSchedule,
+    index: usize,
+) -> Result<ExecuteInheritanceResponse, BitcoinWalletError> {
+    let wallet = memory::wallet(vault_id).ok_or(BitcoinWalletError::VaultNotFound(vault_id))?;
+    let share_bps = schedule.tranches[index].share_bps;
+    let remaining_bps = schedule.remaining_bps();
+    spend_to_heirs(&IcGuardians, vault_id, &wallet, &schedule.heirs, share_bps, remaining_bps, false).await
+}
+
+#[update(guard = "access::guard_vault_manager")]
+fn register_vault_ledger(vault_id: VaultId, ledger: Principal) -> Result<icrc::Account, BitcoinWalletError> {
+    if memory::register_ledger(vault_id, ledger) {
+        audit::record(Some(vault_id), AuditAction::LedgerRegistered, AuditOutcome::Success, Some(ledger.to_text()));
+    }
+    Ok(icrc::vault_account(api::canister_self(), vault_id))
+}
+
+#[query]
+fn vault_ledgers(vault_id: VaultId) -> Vec<Principal> {
+    memory::vault_ledgers(vault_id)
+}
+
+/// Account to deposit a vault's ICRC-1 tokens into, on any of its registered ledgers.
+#[query]
+fn vault_token_account(vault_id: VaultId) -> icrc::Account {
+    icrc::vault_account(api::canister_self(), vault_id)
+}
+
+#[update(guard = "access::guard_auditor")]
+async fn vault_token_balances(vault_id: VaultId) -> Result<Vec<icrc::TokenBalance>, BitcoinWalletError> {
+    let account = icrc::vault_account(api::canister_self(), vault_id);
+    let mut balances = Vec::new();
+    for ledger in memory::vault_ledgers(vault_id) {
+        balances.push(icrc::TokenBalance {
+            ledger,
+            balance: icrc::balance_of(ledger, &account).await?,
+        });
+    }
+    Ok(balances)
+}
+
+/// Empties the vault subaccount on every registered ledger, splitting each balance (net of
+/// one transfer fee per heir) by weight. The split is persisted per ledger, so calling again
+/// after a partial failure pays only the heirs still owed; the heirs passed then are ignored.
+#[update(guard = "access::guard_vault_manager")]
+async fn execute_token_inheritance(
+    args: icrc::ExecuteTokenInheritanceArgs,
+) -> Result<Vec<icrc::LedgerPayout>, BitcoinWalletError> {
+    let result = pay_token_inheritance(&IcGuardians, &IcLedgers, api::canister_self(), &args, api::time()).await;
+    match &result {
+        Ok(payouts) => {
+            for payout in payouts {
+                let outcome = match &payout.error {
+                    Some(err) => AuditOutcome::Failure(err.clone()),
+                    None => AuditOutcome::Success,
+                };
+                let detail = Some(payout.ledger.to_text());
+                audit::record(Some(args.vault_id), AuditAction::TokensTransferred, outcome, detail);
+            }
+        }
+        Err(err) => {
+            let outcome = AuditOutcome::Failure(err.to_string());
+            audit::record(Some(args.vault_id), AuditAction::TokensTransferred, outcome, None);
+        }
+    }
+    result
+}
+
+/// Pays out the vault's tokens on every registered ledger. Like a Bitcoin payout, nothing moves
+/// until `guardian_mgr` confirms the guardian threshold.
+async fn pay_token_inheritance<G: GuardianApi, L: Ledgers>(
+    guardians: &G,
+    ledgers: &L,
+    canister: Principal,
+    args: &icrc::ExecuteTokenInheritanceArgs,
+    now: u64,
+) -> Result<Vec<icrc::LedgerPayout>, BitcoinWalletError> {
+    icrc::ensure_valid_heirs(&args.heirs)?;
+    if memory::wallet(args.vault_id).is_none() {
+        return Err(BitcoinWalletError::VaultNotFound(args.vault_id));
+    }
+    guardians.ensure_threshold_met(args.vault_id).await?;
+    let mut payouts = Vec::new();
+    for ledger in memory::vault_ledgers(args.vault_id) {
+        payouts.push(pay_token_heirs(ledgers, canister, ledger, args.vault_id, &args.heirs, now).await);
+    }
+    Ok(payouts)
+}
+
+async fn pay_token_heirs<L: Ledgers>(
+    ledgers: &L,
+    canister: Principal,
+    ledger: Principal,
+    vault_id: VaultId,
+    heirs: &[icrc::TokenHeir],
+    now: u64,
+) -> icrc::LedgerPayout {
+    let mut payout = icrc::LedgerPayout {
+        ledger,
+        transfers: Vec::new(),
+        error: None,
+    };
+    let mut plan = match memory::token_payout_plan(vault_id, ledger) {
+        Some(plan) => plan,
+        None => match plan_token_payout(ledgers, canister, ledger, vault_id, heirs, now).await {
+            Ok(plan) => plan,
+            Err(err) => {
+                payout.error = Some(err);
+                return payout;
+            }
+        },
+    };
+    memory::insert_token_payout_plan(vault_id, ledger, plan.clone());
+
+    for index in plan.unpaid() {
+        let result = match plan.transfers[index].block_index.clone() {
+            // Found on the ledger while recovering an earlier transfer of this run.
+            Some(block_index) => Ok(block_index),
+            None => transfer_planned(ledgers, canister, ledger, vault_id, &mut plan, index, now).await,
+        };
+        match &result {
+            Ok(block_index) => {
+                plan.mark_paid(index, block_index.clone());
+                memory::insert_token_payout_plan(vault_id, ledger, plan.clone());
+            }
+            Err(err) => {
+                payout.error.get_or_insert_with(|| err.clone());
+            }
+        }
+        payout.transfers.push(icrc::TokenTransfer {
+            account: plan.transfers[index].account.clone(),
+            amount: plan.transfers[index].amount.clone(),
+            block_index: result.as_ref().ok().cloned(),
+            error: result.err(),
+        });
+    }
+    if plan.is_settled() {
+        memory::remove_token_payout_plan(vault_id, ledger);
+    }
+    payout
+}
+
+/// Sends transfer `index` of `plan`. Once the plan's `created_at_time` has left the ledger's
+/// deduplication window the ledger answers `TooOld` and would no longer catch a repeat, so its
+/// blocks are searched for every unpaid transfer's memo before the plan is reissued under a
+/// fresh time and the transfer resubmitted.
+async fn transfer_planned<L: Ledgers>(
+    ledgers: &L,
+    canister: Principal,
+    ledger: Principal,
+    vault_id: VaultId,
+    plan: &mut icrc::TokenPayoutPlan,
+    index: usize,
+    now: u64,
+) -> Result<Nat, String> {
+    match ledgers.transfer(ledger, vault_id, plan, index).await {
+        Err(icrc::TransferFailure::TooOld) => {}
+        result => return result.map_err(|err| err.to_string()),
+    }
+    // Plans made before the first block was recorded are checked against the whole ledger.
+    let start = plan.first_block.clone().unwrap_or_default();
+    let landed = ledgers
+        .transfers_from(ledger, &icrc::vault_account(canister, vault_id), start)
+        .await?;
+    plan.mark_landed(vault_id, &landed);
+    plan.reissue(now);
+    memory::insert_token_payout_plan(vault_id, ledger, plan.clone());
+    match plan.transfers[index].block_index.clone() {
+        Some(block_index) => Ok(block_index),
+        None => ledgers.transfer(ledger, vault_id, plan, index).await.map_err(|err| err.to_string()),
+    }
+}
+
+async fn plan_token_payout<L: Ledgers>(
+    ledgers: &L,
+    canister: Principal,
+    ledger: Principal,
+    vault_id: VaultId,
+    heirs: &[icrc::TokenHeir],
+    now: u64,
+) -> Result<icrc::TokenPayoutPlan, String> {
+    let account = icrc::vault_account(canister, vault_id);
+    let fee = ledgers.fee(ledger).await.map_err(|err| err.to_string())?;
+    let balance = ledgers.balance_of(ledger, &account).await.map_err(|err| err.to_string())?;
+    let first_block = ledgers.log_length(ledger).await.map_err(|err| err.to_string())?;
+    icrc::TokenPayoutPlan::new(balance, fee, heirs, first_block, now)
+}
+
+async fn derive_vault_address(args: &GenerateVaultAddressArgs) -> Result<BitcoinAddressResponse, BitcoinWalletError> {
+    if let Some(existing) = memory::wallet(args.vault_id) {
+        return Ok(BitcoinAddressResponse {
+            address: existing.address,
+            key_id: existing.key_id,
+        });
+    }
+
+    let network = with_state(|state| -> Result<Network, BitcoinWalletError> {
+        state.keys.ensure_allowed(&args.key_id)?;
+        Ok(state.keys.network)
+    })?;
+
+    let wallet = derive_wallet(&IcSigner, args.vault_id, &args.key_id, network, args.owner).await?;
+
+    if memory::wallet(args.vault_id).is_some() {
+        return Err(BitcoinWalletError::VaultAlreadyExists(args.vault_id));
+    }
+    memory::insert_wallet(args.vault_id, wallet.clone());
+
+    Ok(BitcoinAddressResponse {
+        address: wallet.address,
+        key_id: args.key_id.clone(),
+    })
+}
+
+/// Derives the P2WPKH wallet for `vault_id` under `key_id`. Nothing is persisted.
+async fn derive_wallet<S: Signer>(
+    signer: &S,
+    vault_id: VaultId,
+    key_id: &str,
+    network: Network,
+    owner: Option<Principal>,
+) -> Result<VaultWallet, BitcoinWalletError> {
+    let derivation_path = vec![vault_id.to_be_bytes().to_vec()];
+    let sec1_public_key = signer.public_key(key_id, &derivation_path).await?;
+
+    let public_key = PublicKey::from_slice(&sec1_public_key)
+        .map_err(|err| BitcoinWalletError::Crypto(err.to_string()))?;
+    let segwit_address = Address::p2wpkh(&public_key, keys::bitcoin_network(network))
+        .map_err(|err| BitcoinWalletError::Crypto(err.to_string()))?;
+
+    Ok(VaultWallet {
+        key_id: key_id.to_string(),
+        derivation_path,
+        address: segwit_address.to_string(),
+        script_pub_key: segwit_address.script_pubkey().to_bytes(),
+        public_key: sec1_public_key,
+        network,
+        owner,
+        retired_keys: None,
+    })
+}
+
+async fn broadcast_inheritance(
+    args: &ExecuteInheritanceArgs,
+    wait_for_confirmations: bool,
+) -> Result<ExecuteInheritanceResponse, BitcoinWalletError> {
+    let wallet = validate_execution(args)?;
+    let (vault_id, heirs) = (args.vault_id, &args.heirs);
+    spend_to_heirs(&IcGuardians, vault_id, &wallet, heirs, BASIS_POINTS, BASIS_POINTS, wait_for_confirmations).await
+}
+
+fn validate_execution(args: &ExecuteInheritanceArgs) -> Result<VaultWallet, BitcoinWalletError> {
+    ensure_valid_heirs(&args.heirs)?;
+    let wallet = memory::wallet(args.vault_id).ok_or(BitcoinWalletError::VaultNotFound(args.vault_id))?;
+
+    with_state(|state| state.keys.ensure_allowed(&args.key_id))?;
+    if wallet.key_id != args.key_id {
+        return Err(BitcoinWalletError::Crypto("mismatched key id".into()));
+    }
+    Ok(wallet)
+}
+
+/// Pays `share_bps / remaining_bps` of the vault's confirmed balance to `heirs` by weight,
+/// returning the rest to the vault address as change. Heirs bear the network fee. Nothing is
+/// signed unless `guardians` confirm the guardian threshold for the vault.
+async fn spend_to_heirs<G: GuardianApi>(
+    guardians: &G,
+    vault_id: VaultId,
+    wallet: &VaultWallet,
+    heirs: &[HeirRecord],
+    share_bps: u64,
+    remaining_bps: u64,
+    wait_for_confirmations: bool,
+) -> Result<ExecuteInheritanceResponse, BitcoinWalletError> {
+    let _lock = SpendLock::acquire(vault_id)?;
+    guardians.ensure_threshold_met(vault_id).await?;
+    let destinations = resolve_destinations(heirs).await?;
+    let policy = memory::transaction_policy(vault_id);
+    let executed_at = api::time();
+    let memo = policy.inheritance_memo.unwrap_or_default().then(|| memo::commitment(vault_id, heirs, executed_at));
+    let payout = Payout {
+        vault_id,
+        wallet,
+        heirs,
+        destinations: &destinations,
+        share_bps,
+        remaining_bps,
+        policy,
+        wait_for_confirmations,
+        memo,
+    };
+    let meter = CyclesMeter::new(vault_id);
+    let receipt = payout
+        .execute(&Metered::new(&IcBitcoin, &meter), &Metered::new(&IcSigner, &meter))
+        .await?;
+    let (signed_tx, payouts) = (receipt.tx, receipt.payouts);
+    let memo = memo.map(|commitment| memo::InheritanceMemo::new(&commitment, executed_at));
+
+    let now = api::time();
+    metrics::record_execution(now);
+    history::record_deposits(vault_id, &receipt.spent, now);
+    let recipients = heirs
+        .iter()
+        .zip(&destinations)
+        .zip(&payouts)
+        .map(|((heir, address), amount)| history::Recipient {
+            address: address.clone(),
+            amount_sats: *amount,
+            account: heir.account.clone(),
+        })
+        .collect();
+    memory::append_history(
+        vault_id,
+        history::HistoryEntry {
+            kind: history::MovementKind::InheritancePayout,
+            txid: signed_tx.txid().to_string(),
+            vout: None,
+            amount_sats: payouts.iter().sum(),
+            fee_sats: Some(receipt.fee),
+            height: receipt.tip_height,
+            timestamp: now,
+            recipients,
+            memo: memo.clone(),
+        },
+    );
+
+    // The minter only credits confirmed deposits, so the timer notifies it later.
+    let mut ckbtc_deposits = Vec::new();
+    for ((heir, deposit_address), amount) in heirs.iter().zip(destinations).zip(payouts) {
+        let Some(account) = &heir.account else {
+            continue;
+        };
+        let deposit = ckbtc::CkbtcDeposit {
+            account: account.clone(),
+            deposit_address,
+            amount_sats: amount,
+            status: ckbtc::DepositStatus::Pending("awaiting confirmations".into()),
+        };
+        let mint = ckbtc::PendingMint::new(deposit.clone(), signed_tx.txid().to_string(), now);
+        memory::insert_pending_mint(vault_id, mint);
+        ckbtc_deposits.push(deposit);
+    }
+    if !ckbtc_deposits.is_empty() {
+        timer::rearm();
+    }
+
+    Ok(ExecuteInheritanceResponse {
+        tx_id: signed_tx.txid().to_string(),
+        ckbtc_deposits,
+        memo,
+    })
+}
+
+/// A broadcast payout and what went into it.
+struct PayoutReceipt {
+    tx: Transaction,
+    /// Amount paid to each heir, in heir order.
+    payouts: Vec<u64>,
+    fee: u64,
+    spent: Vec<ManagedUtxo>,
+    tip_height: u32,
+}
+
+/// One spend from a vault to its heirs, with destinations already resolved.
+struct Payout<'a> {
+    vault_id: VaultId,
+    wallet: &'a VaultWallet,
+    heirs: &'a [HeirRecord],
+    destinations: &'a [String],
+    share_bps: u64,
+    remaining_bps: u64,
+    policy: TransactionPolicy,
+    /// Fail with `AwaitingConfirmations` rather than leave deposits below the policy's
+    /// confirmation threshold behind.
+    wait_for_confirmations: bool,
+    /// Commitment to publish in an OP_RETURN output.
+    memo: Option<sha256::Hash>,
+}
+
+impl Payout<'_> {
+    /// Builds, signs and broadcasts the transaction, spending only deposits with the
+    /// confirmations the vault's policy requires.
+    async fn execute<B: BitcoinBackend, S: Signer>(
+        &self,
+        backend: &B,
+        signer: &S,
+    ) -> Result<PayoutReceipt, BitcoinWalletError> {
+        let wallet = self.wallet;
+        // Deposits below the policy's threshold are read too, so that they can hold the payout back.
+        let (utxos, tip_height) = fetch_utxos(backend, wallet, MIN_CONFIRMATIONS).await?;
+        let min_confirmations = self.policy.min_confirmations();
+        let (managed_utxos, pending): (Vec<_>, Vec<_>) =
+            utxos.into_iter().partition(|utxo| utxo.confirmations(tip_height) >= min_confirmations);
+        if self.wait_for_confirmations && !pending.is_empty() {
+            return Err(BitcoinWalletError::AwaitingConfirmations {
+                vault_id: self.vault_id,
+                pending: pending.len() as u64,
+            });
+        }
+        if managed_utxos.is_empty() {
+            return Err(BitcoinWalletError::NoUtxos(self.vault_id));
+        }
+        let total_value: u64 = managed_utxos.iter().map(|u| u.value).sum();
+        let (tranche_value, change) = split_tranche(total_value, self.share_bps, self.remaining_bps);
+
+        let fee_rate = fetch_fee_rate(backend, wallet.network).await?;
+        let output_count = self.heirs.len() + usize::from(change > 0);
+        let memo_fee = if self.memo.is_some() { memo::fee_sat(fee_rate) } else { Some(0) };
+        let estimated_fee = estimate_fee_sat(fee_rate, managed_utxos.len(), output_count)
+            .zip(memo_fee)
+            .and_then(|(fee, memo_fee)| fee.checked_add(memo_fee))
+            .ok_or(BitcoinWalletError::FeeEstimationUnavailable)?;
+
+        let required = estimated_fee + DUST_THRESHOLD;
+        if tranche_value < required {
+            return Err(BitcoinWalletError::InsufficientFunds {
+                available: tranche_value,
+                required,
+            });
+        }
+
+        let payouts = allocate_payouts(tranche_value, estimated_fee, self.heirs, &self.policy.fee_bearer())?;
+        let mut outputs = build_outputs(self.destinations, &payouts, keys::bitcoin_network(wallet.network))?;
+        outputs.extend(self.memo.as_ref().map(memo::output));
+        if change > 0 {
+            outputs.push(TxOut {
+                value: Amount::from_sat(change),
+                script_pubkey: ScriptBuf::from(wallet.script_pub_key.clone()),
+            });
+        }
+
+        let needed = cycles::signing_cost(backend, signer, wallet, managed_utxos.len(), outputs.len())?;
+        cycles::ensure_available(needed, backend.cycles_available())?;
+        let unsigned_tx = build_unsigned_transaction(&managed_utxos, outputs, &self.policy, tip_height)?;
+        let signed_tx = sign_transaction(signer, unsigned_tx, wallet, &managed_utxos).await?;
+
+        send_transaction(backend, wallet.network, &signed_tx).await?;
+        Ok(PayoutReceipt {
+            tx: signed_tx,
+            payouts,
+            fee: estimated_fee,
+            spent: managed_utxos,
+            tip_height,
+        })
+    }
+}
+
+/// Splits `total` into the value paid out now and the change kept in the vault. Change too
+/// small to be its own output is paid out with the tranche.
+fn split_tranche(total: u64, share_bps: u64, remaining_bps: u64) -> (u64, u64) {
+    if share_bps >= remaining_bps {
+        return (total, 0);
+    }
+    let tranche = (u128::from(total) * u128::from(share_bps) / u128::from(remaining_bps)) as u64;
+    let change = total - tranche;
+    if change < DUST_THRESHOLD {
+        return (total, 0);
+    }
+    (tranche, change)
+}
+
+/// Payout address per heir: their own Bitcoin address, or the ckBTC minter's deposit address
+/// for their ICRC-1 account.
+async fn resolve_destinations(heirs: &[HeirRecord]) -> Result<Vec<String>, BitcoinWalletError> {
+    let mut destinations = Vec::with_capacity(heirs.len());
+    for heir in heirs {
+        let destination = match &heir.account {
+            Some(account) => {
+                let minter =
+                    with_state(|state| state.ckbtc_minter).ok_or(BitcoinWalletError::MinterNotConfigured)?;
+                ckbtc::deposit_address(minter, account).await?
+            }
+            None => heir.address.clone(),
+        };
+        destinations.push(destination);
+    }
+    Ok(destinations)
+}
+
+/// Moves a vault onto `new_key_id`: derives the new address, retires the old key on the wallet
+/// record and sweeps every retired key's UTXOs onto the new address. Migrating onto the
+/// current key id only sweeps what has reached the retired addresses since.
+#[update(guard = "access::guard_admin")]
+async fn migrate_vault_key(vault_id: VaultId, new_key_id: String) -> Result<KeyMigrationResponse, BitcoinWalletError> {
+    let _lock = SpendLock::acquire(vault_id)?;
+    let result = sweep_to_key(vault_id, &new_key_id).await;
+    audit::record_result(vault_id, AuditAction::KeyMigrated, &result, |response| {
+        format!("{new_key_id}: {} ({} sweeps)", response.address, response.tx_ids.len())
+    });
+    result
+}
+
+async fn sweep_to_key(vault_id: VaultId, new_key_id: &str) -> Result<KeyMigrationResponse, BitcoinWalletError> {
+    let wallet = memory::wallet(vault_id).ok_or(BitcoinWalletError::VaultNotFound(vault_id))?;
+    let rotating = wallet.key_id != new_key_id;
+    if !rotating && wallet.retired_keys().is_empty() {
+        return Err(BitcoinWalletError::KeyUnchanged(vault_id));
+    }
+    with_state(|state| state.keys.ensure_allowed(new_key_id))?;
+
+    let meter = CyclesMeter::new(vault_id);
+    let (backend, signer) = (Metered::new(&IcBitcoin, &meter), Metered::new(&IcSigner, &meter));
+    let current = if rotating {
+        // Persist the retired key before sweeping so a failed sweep can be retried.
+        let mut migrated = derive_wallet(&signer, vault_id, new_key_id, wallet.network, wallet.owner).await?;
+        let mut retired = wallet.retired_keys().to_vec();
+        retired.push(wallet.retire(api::time()));
+        migrated.retired_keys = Some(retired);
+        memory::insert_wallet(vault_id, migrated.clone());
+        migrated
+    } else {
+        wallet
+    };
+
+    let mut tx_ids = Vec::new();
+    for key in current.retired_keys() {
+        let source = current.under_retired(key);
+        if let Some(tx_id) = sweep_wallet(&backend, &signer, vault_id, &source, &current, api::time()).await? {
+            tx_ids.push(tx_id);
+        }
+    }
+
+    let previous_address = current.retired_keys().last().map(|key| key.address.clone()).unwrap_or_default();
+    Ok(KeyMigrationResponse {
+        address: current.address,
+        key_id: current.key_id,
+        previous_address,
+        tx_ids,
+    })
+}
+
+/// Spends every confirmed UTXO at `source` to `destination`'s address in one transaction.
+/// Returns `None` when there was nothing to sweep.
+async fn sweep_wallet<B: BitcoinBackend, S: Signer>(
+    backend: &B,
+    signer: &S,
+    vault_id: VaultId,
+    source: &VaultWallet,
+    destination: &VaultWallet,
+    now: u64,
+) -> Result<Option<String>, BitcoinWalletError> {
+    let (managed_utxos, tip_height) = fetch_utxos(backend, source, MIN_CONFIRMATIONS).await?;
+    if managed_utxos.is_empty() {
+        return Ok(None);
+    }
+    let total_value: u64 = managed_utxos.iter().map(|u| u.value).sum();
+    let fee_rate = fetch_fee_rate(backend, source.network).await?;
+    let estimated_fee =
+        estimate_fee_sat(fee_rate, managed_utxos.len(), 1).ok_or(BitcoinWalletError::FeeEstimationUnavailable)?;
+    let output = sweep_output(total_value, estimated_fee, ScriptBuf::from_bytes(destination.script_pub_key.clone()))?;
+    let swept = output.value.to_sat();
+
+    let needed = cycles::signing_cost(backend, signer, source, managed_utxos.len(), 1)?;
+    cycles::ensure_available(needed, backend.cycles_available())?;
+    let policy = memory::transaction_policy(vault_id);
+    let unsigned_tx = build_unsigned_transaction(&managed_utxos, vec![output], &policy, tip_height)?;
+    let signed_tx = sign_transaction(signer, unsigned_tx, source, &managed_utxos).await?;
+    send_transaction(backend, source.network, &signed_tx).await?;
+
+    history::record_deposits(vault_id, &managed_utxos, now);
+    memory::append_history(
+        vault_id,
+        history::HistoryEntry {
+            kind: history::MovementKind::Withdrawal,
+            txid: signed_tx.txid().to_string(),
+            vout: None,
+            amount_sats: swept,
+            fee_sats: Some(total_value - swept),
+            height: tip_height,
+            timestamp: now,
+            recipients: vec![history::Recipient {
+                address: destination.address.clone(),
+                amount_sats: swept,
+                account: None,
+            }],
+            memo: None,
+        },
+    );
+    Ok(Some(signed_tx.txid().to_string()))
+}
+
+/// Records the principal that owns a vault, for wallets created before owners were tracked.
+/// Admins may reassign an owner; the vault manager may only fill in a missing one.
+#[update(guard = "access::guard_not_paused")]
+fn set_vault_owner(vault_id: VaultId, owner: Principal) -> Result<(), BitcoinWalletError> {
+    let caller = api::msg_caller();
+    let mut wallet = memory::wallet(vault_id).ok_or(BitcoinWalletError::VaultNotFound(vault_id))?;
+    let (is_admin, is_manager) = with_state(|state| {
+        (access::is_admin(state, caller), access::ensure_vault_manager(state, caller).is_ok())
+    });
+    match (is_admin, is_manager, wallet.owner) {
+        (true, _, _) | (false, true, None) => {}
+        (false, true, Some(_)) => return Err(BitcoinWalletError::OwnerAlreadySet(vault_id)),
+        (false, false, _) => return Err(BitcoinWalletError::Unauthorized(caller)),
+    }
+    wallet.owner = Some(owner);
+    memory::insert_wallet(vault_id, wallet);
+    audit::record(Some(vault_id), AuditAction::OwnerChanged, AuditOutcome::Success, Some(owner.to_text()));
+    Ok(())
+}
+
+#[update(guard = "access::guard_not_paused")]
+async fn sign_message(vault_id: VaultId, message: String) -> Result<SignedMessageResponse, BitcoinWalletError> {
+    let caller = api::msg_caller();
+    let wallet = memory::wallet(vault_id).ok_or(BitcoinWalletError::VaultNotFound(vault_id))?;
+    if wallet.owner != Some(caller) {
+        return Err(BitcoinWalletError::Unauthorized(caller));
+    }
+
+    let public_key = PublicKey::from_slice(&wallet.public_key)
+        .map_err(|err| BitcoinWalletError::Crypto(err.to_string()))?;
+    let digest = bip322::p2wpkh_sighash(&public_key, message.as_bytes()).map_err(BitcoinWalletError::Crypto)?;
+    cycles::ensure_available(IcSigner.sign_cost(&wallet.key_id)?, IcBitcoin.cycles_available())?;
+    let meter = CyclesMeter::new(vault_id);
+    let signature = sign_digest(&Metered::new(&IcSigner, &meter), &wallet, &digest).await?;
+
+    Ok(SignedMessageResponse {
+        address: wallet.address,
+        signature: bip322::encode_simple_signature(signature, &public_key),
+    })
+}
+
+#[update(guard = "access::guard_admin")]
+async fn proof_of_reserves(challenge: String) -> Result<ReservesReport, BitcoinWalletError> {
+    let wallets = memory::wallets();
+    let lookups = wallets.iter().map(|(_, wallet)| IcBitcoin.get_utxos_cost(wallet.network)).sum();
+    cycles::ensure_available(lookups, IcBitcoin.cycles_available())?;
+    let meters: Vec<_> = wallets.iter().map(|(vault_id, _)| CyclesMeter::new(*vault_id)).collect();
+
+    let mut vaults = Vec::with_capacity(wallets.len());
+    let mut inputs = Vec::new();
+    let mut signers = Vec::new();
+    for ((vault_id, wallet), meter) in wallets.iter().zip(&meters) {
+        let (managed_utxos, _) = fetch_utxos(&Metered::new(&IcBitcoin, meter), wallet, MIN_CONFIRMATIONS).await?;
+
+        vaults.push(VaultReserve {
+            vault_id: *vault_id,
+            address: wallet.address.clone(),
+            balance_sats: managed_utxos.iter().map(|u| u.value).sum(),
+            utxo_count: managed_utxos.len() as u64,
+        });
+        for utxo in managed_utxos {
+            inputs.push(reserves::ReserveInput {
+                outpoint: utxo.outpoint,
+                prevout: TxOut {
+                    value: Amount::from_sat(utxo.value),
+                    script_pubkey: ScriptBuf::from(wallet.script_pub_key.clone()),
+                },
+            });
+            signers.push((wallet, meter));
+        }
+    }
+
+    let signatures = signers.iter().map(|(wallet, _)| IcSigner.sign_cost(&wallet.key_id)).sum::<Result<u128, _>>()?;
+    cycles::ensure_available(signatures, IcBitcoin.cycles_available())?;
+
+    // Input 0 is the unsignable challenge; reserve input `i` sits at transaction index `i + 1`.
+    let proof_tx = reserves::build_proof_transaction(&challenge, &inputs);
+    let mut cache = SighashCache::new(&proof_tx);
+    let mut witnesses = Vec::with_capacity(inputs.len());
+    for (index, (input, (wallet, meter))) in inputs.iter().zip(signers).enumerate() {
+        let value = input.prevout.value.to_sat();
+        let signer = Metered::new(&IcSigner, meter);
+        witnesses.push(sign_p2wpkh_input(&signer, &mut cache, index + 1, wallet, value).await?);
+    }
+    let psbt = reserves::assemble_psbt(proof_tx, &inputs, witnesses)
+        .map_err(|err| BitcoinWalletError::Crypto(err.to_string()))?;
+
+    Ok(ReservesReport {
+        challenge,
+        total_sats: vaults.iter().map(|v| v.balance_sats).sum(),
+        vaults,
+        psbt: psbt.to_string(),
+        generated_at: api::time(),
+    })
+}
+
+/// Deposits, withdrawals and inheritance payouts of `vault_id`, for its owner, auditors and
+/// heirs paid in ckBTC once they have been paid. Refreshes deposits from the Bitcoin canister first.
+#[update(guard = "access::guard_vault_history_reader")]
+async fn export_vault_history(vault_id: VaultId, format: history::ExportFormat) -> Result<String, BitcoinWalletError> {
+    let wallet = memory::wallet(vault_id).ok_or(BitcoinWalletError::VaultNotFound(vault_id))?;
+
+    let meter = CyclesMeter::new(vault_id);
+    let (utxos, _) = fetch_utxos(&Metered::new(&IcBitcoin, &meter), &wallet, MIN_CONFIRMATIONS).await?;
+    history::record_deposits(vault_id, &utxos, api::time());
+    Ok(history::render(&memory::vault_history(vault_id), &format))
+}
+
+#[query]
+fn http_request(request: metrics::HttpRequest) -> metrics::HttpResponse {
+    metrics::serve(&request, render_metrics)
+}
+
+fn render_metrics() -> String {
+    let scheduled = memory::scheduled_execution_count();
+    let vesting = memory::active_vesting_count();
+
+    let mut encoder = metrics::MetricsEncoder::default();
+    encoder
+        .gauge("bitcoin_wallet_vaults", "Vaults with a derived wallet.", memory::wallet_count())
+        .gauge("bitcoin_wallet_scheduled_executions", "Executions waiting out their grace period.", scheduled)
+        .gauge("bitcoin_wallet_active_vesting_schedules", "Vesting schedules with unpaid tranches.", vesting);
+    metrics::encode_counters(&mut encoder);
+    encoder
+        .gauge("bitcoin_wallet_cycles_balance", "Cycles held by the canister.", api::canister_cycle_balance())
+        .gauge(
+            "bitcoin_wallet_stable_memory_bytes",
+            "Size of stable memory.",
+            ic_cdk::stable::stable_size() * WASM_PAGE_SIZE,
+        );
+    encoder.finish()
+}
+
+#[query]
+fn wallet_view(vault_id: VaultId) -> Option<BitcoinAddressResponse> {
+    memory::wallet(vault_id).map(|wallet| BitcoinAddressResponse {
+        address: wallet.address,
+        key_id: wallet.key_id,
+    })
+}
+
+#[query]
+fn export_descriptor(vault_id: VaultId) -> Result<DescriptorResponse, BitcoinWalletError> {
+    let wallet = memory::wallet(vault_id).ok_or(BitcoinWalletError::VaultNotFound(vault_id))?;
+    let descriptor = descriptor::descriptor_for_script(&wallet.script_pub_key, &wallet.public_key)
+        .ok_or(BitcoinWalletError::UnsupportedDescriptor(vault_id))?;
+    Ok(DescriptorResponse {
+        descriptor,
+        address: wallet.address,
+    })
+}
+
+async fn send_transaction<B: BitcoinBackend>(
+    backend: &B,
+    network: Network,
+    tx: &Transaction,
+) -> Result<(), BitcoinWalletError> {
+    let mut tx_bytes = Vec::new();
+    tx.consensus_encode(&mut tx_bytes)
+        .map_err(|err| BitcoinWalletError::Crypto(err.to_string()))?;
+    backend.send_transaction(network, tx_bytes).await
+}
+
+fn ensure_valid_heirs(heirs: &[HeirRecord]) -> Result<(), BitcoinWalletError> {
+    if heirs.is_empty() {
+        return Err(BitcoinWalletError::InvalidHeirs);
+    }
+    let total: u64 = heirs.iter().map(|h| h.weight_bps).sum();
+    if total != BASIS_POINTS {
+        return Err(BitcoinWalletError::InvalidHeirs);
+    }
+    let single_destination = |heir: &HeirRecord| match &heir.account {
+        Some(account) => heir.address.is_empty() && account.is_valid(),
+        None => !heir.address.is_empty(),
+    };
+    if !heirs.iter().all(single_destination) {
+        return Err(BitcoinWalletError::InvalidHeirs);
+    }
+    Ok(())
+}
+
+/// UTXOs of the vault with at least `min_confirmations`, with the tip height they were read at.
+async fn fetch_utxos<B: BitcoinBackend>(
+    backend: &B,
+    wallet: &VaultWallet,
+    min_confirmations: u32,
+) -> Result<(Vec<ManagedUtxo>, u32), BitcoinWalletError> {
+    let utxo_response = backend
+        .get_utxos(wallet.network, &wallet.address, min_confirmations)
+        .await?;
+    Ok((normalize_utxos(&utxo_response.utxos)?, utxo_response.tip_height))
+}
+
+fn normalize_utxos(utxos: &[Utxo]) -> Result<Vec<ManagedUtxo>, BitcoinWalletError> {
+    utxos
+        .iter()
+        .map(|utxo| {
+            let txid =
+                Txid::from_slice(&utxo.outpoint.txid).map_err(|err| BitcoinWalletError::Crypto(err.to_string()))?;
+            Ok(ManagedUtxo {
+                outpoint: OutPoint::new(txid, utxo.outpoint.vout),
+                value: utxo.value,
+                height: utxo.height,
+            })
+        })
+        .collect()
+}
+
+async fn fetch_fee_tiers<B: BitcoinBackend>(
+    backend: &B,
+    network: Network,
+) -> Result<fees::FeeTiers, BitcoinWalletError> {
+    Ok(fees::FeeTiers::from_percentiles(&backend.fee_percentiles(network).await?))
+}
+
+/// Spends pay the medium tier.
+async fn fetch_fee_rate<B: BitcoinBackend>(backend: &B, network: Network) -> Result<u64, BitcoinWalletError> {
+    Ok(fetch_fee_tiers(backend, network).await?.medium)
+}
+
+fn estimate_fee_sat(rate: u64, inputs: usize, outputs: usize) -> Option<u64> {
+    let vbytes = 10 + inputs * 68 + outputs * 31;
+    rate.checked_mul(vbytes as u64)
+}
+
+/// Splits `total` minus `fee` between the heirs by weight, the last heir taking the rounding
+/// remainder. A proportional fee comes off `total` before the split; a single bearer's fee
+/// comes off their own share after it.
+fn allocate_payouts(
+    total: u64,
+    fee: u64,
+    heirs: &[HeirRecord],
+    bearer: &FeeBearer,
+) -> Result<Vec<u64>, BitcoinWalletError> {
+    let bearer_index = match bearer {
+        FeeBearer::Proportional => None,
+        FeeBearer::Heir(label) => Some(heirs.iter().position(|heir| heir.label() == *label).ok_or_else(|| {
+            BitcoinWalletError::InvalidPolicy(format!("fee bearer {label} is not one of the heirs"))
+        })?),
+        FeeBearer::Residual => Some(heirs.len().saturating_sub(1)),
+    };
+    let distributable = match bearer_index {
+        None => total.checked_sub(fee).ok_or(BitcoinWalletError::InsufficientFunds {
+            available: total,
+            required: fee + DUST_THRESHOLD,
+        })?,
+        Some(_) => total,
+    };
+
+    let mut allocations = Vec::with_capacity(heirs.len());
+    let mut assigned = 0u64;
+    for (index, heir) in heirs.iter().enumerate() {
+        let mut amount = if index == heirs.len() - 1 {
+            distributable
+                .checked_sub(assigned)
+                .ok_or_else(|| BitcoinWalletError::Crypto("payout overflow".to_string()))?
+        } else {
+            (distributable * heir.weight_bps) / BASIS_POINTS
+        };
+        assigned = assigned
+            .checked_add(amount)
+            .ok_or_else(|| BitcoinWalletError::Crypto("payout overflow".to_string()))?;
+        if bearer_index == Some(index) {
+            amount = amount.checked_sub(fee).ok_or(BitcoinWalletError::InsufficientFunds {
+                available: amount,
+                required: fee + DUST_THRESHOLD,
+            })?;
+        }
+        if amount < DUST_THRESHOLD {
+            return Err(BitcoinWalletError::DustPayout {
+                address: heir.label(),
+                amount,
+            });
+        }
+        allocations.push(amount);
+    }
+    Ok(allocations)
+}
+
+fn build_outputs(
+    destinations: &[String],
+    payouts: &[u64],
+    network: BtcNetwork,
+) -> Result<Vec<TxOut>, BitcoinWalletError> {
+    destinations
+        .iter()
+        .zip(payouts.iter())
+        .map(|(destination, amount)| {
+            let invalid = |err: &dyn std::fmt::Display| BitcoinWalletError::InvalidHeirAddress {
+                address: destination.clone(),
+                reason: err.to_string(),
+            };
+            let address = Address::from_str(destination)
+                .map_err(|err| invalid(&err))?
+                .require_network(network)
+                .map_err(|err| invalid(&err))?;
+            Ok(TxOut {
+                value: Amount::from_sat(*amount),
+                script_pubkey: address.script_pubkey(),
+            })
+        })
+        .collect()
+}
+
+fn sweep_output(total: u64, fee: u64, script_pubkey: ScriptBuf) -> Result<TxOut, BitcoinWalletError> {
+    let required = fee + DUST_THRESHOLD;
+    if total < required {
+        return Err(BitcoinWalletError::InsufficientFunds {
+            available: total,
+            required,
+        });
+    }
+    Ok(TxOut {
+        value: Amount::from_sat(total - fee),
+        script_pubkey,
+    })
+}
+
+fn build_unsigned_transaction(
+    utxos: &[ManagedUtxo],
+    outputs: Vec<TxOut>,
+    policy: &TransactionPolicy,
+    tip_height: u32,
+) -> Result<Transaction, BitcoinWalletError> {
+    let lock_time = policy.lock_time(tip_height)?;
+    let sequence = policy.sequence(lock_time);
+    let inputs = utxos
+        .iter()
+        .map(|utxo| TxIn {
+            previous_output: utxo.outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence,
+            witness: Witness::new(),
+        })
+        .collect();
+    Ok(Transaction {
+        version: Version(2),
+        lock_time,
+        input: inputs,
+        output: outputs,
+    })
+}
+
+async fn sign_transaction<S: Signer>(
+    signer: &S,
+    unsigned_tx: Transaction,
+    wallet: &VaultWallet,
+    utxos: &[ManagedUtxo],
+) -> Result<Transaction, BitcoinWalletError> {
+    let mut cache = SighashCache::new(&unsigned_tx);
+    let mut signed_tx = unsigned_tx.clone();
+
+    for (index, utxo) in utxos.iter().enumerate() {
+        let witness = sign_p2wpkh_input(signer, &mut cache, index, wallet, utxo.value).await?;
+        signed_tx
+            .input
+            .get_mut(index)
+            .ok_or(BitcoinWalletError::Crypto("input missing".into()))?
+            .witness = witness;
+    }
+
+    Ok(signed_tx)
+}
+
+async fn sign_p2wpkh_input<S: Signer>(
+    signer: &S,
+    cache: &mut SighashCache<&Transaction>,
+    index: usize,
+    wallet: &VaultWallet,
+    value: u64,
+) -> Result<Witness, BitcoinWalletError> {
+    let public_key = PublicKey::from_slice(&wallet.public_key)
+        .map_err(|err| BitcoinWalletError::Crypto(err.to_string()))?;
+    // BIP-143 derives the script code from the P2WPKH output script itself.
+    let script_pub_key = ScriptBuf::from(wallet.script_pub_key.clone());
+    let sighash = cache
+        .p2wpkh_signature_hash(index, &script_pub_key, Amount::from_sat(value), EcdsaSighashType::All)
+        .map_err(|err| BitcoinWalletError::Crypto(err.to_string()))?;
+    let signature = sign_digest(signer, wallet, &sighash.to_byte_array()).await?;
+    let mut witness = Witness::new();
+    witness.push(signature);
+    witness.push(public_key.to_bytes());
+    Ok(witness)
+}
+
+async fn sign_digest<S: Signer>(
+    signer: &S,
+    wallet: &VaultWallet,
+    message_hash: &[u8; 32],
+) -> Result<Vec<u8>, BitcoinWalletError> {
+    let signature = signer
+        .sign(&wallet.key_id, &wallet.derivation_path, message_hash)
+        .await?;
+
+    let secp_sig = secp256k1::ecdsa::Signature::from_compact(&signature)
         .map_err(|err| BitcoinWalletError::Crypto(err.to_string()))?;
     let mut der = secp_sig.serialize_der().to_vec();
     der.push(EcdsaSighashType::All as u8);
     Ok(der)
 }
 
-fn mutate_state<F, R>(f: F) -> R
-where
-    F: FnOnce(&mut VaultWalletState) -> R,
-{
-    STATE.with(|state| {
-        let mut data = state.borrow_mut();
-        f(&mut data)
-    })
+thread_local! {
+    static SPENDS_IN_FLIGHT: RefCell<BTreeSet<VaultId>> = const { RefCell::new(BTreeSet::new()) };
 }
 
-fn with_state<F, R>(f: F) -> R
-where
-    F: FnOnce(&VaultWalletState) -> R,
-{
-    STATE.with(|state| {
-        let data = state.borrow();
-        f(&data)
-    })
+/// Keeps payouts, tranches and key migrations of the same vault from selecting the same
+/// UTXOs concurrently; released on drop.
+struct SpendLock(VaultId);
+
+impl SpendLock {
+    fn acquire(vault_id: VaultId) -> Result<Self, BitcoinWalletError> {
+        SPENDS_IN_FLIGHT.with(|in_flight| {
+            if !in_flight.borrow_mut().insert(vault_id) {
+                return Err(BitcoinWalletError::SpendInProgress(vault_id));
+            }
+            Ok(SpendLock(vault_id))
+        })
+    }
+}
+
+impl Drop for SpendLock {
+    fn drop(&mut self) {
+        SPENDS_IN_FLIGHT.with(|in_flight| in_flight.borrow_mut().remove(&self.0));
+    }
 }
 
 #[derive(Clone)]
 struct ManagedUtxo {
     outpoint: OutPoint,
     value: u64,
+    height: u32,
+}
+
+impl ManagedUtxo {
+    /// Confirmations at `tip_height`; the block that mined the output counts as the first.
+    fn confirmations(&self, tip_height: u32) -> u32 {
+        (tip_height + 1).saturating_sub(self.height)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use backend::mock::{MockBitcoin, MockGuardians, MockLedgers, MockSigner};
+    use candid::Nat;
+    use futures::executor::block_on;
+
+    fn regtest_heir(signer: &MockSigner, seed: u8, weight_bps: u64) -> HeirRecord {
+        let public_key = block_on(signer.public_key("heir", &[vec![seed]])).unwrap();
+        let address = Address::p2wpkh(&PublicKey::from_slice(&public_key).unwrap(), BtcNetwork::Regtest).unwrap();
+        HeirRecord {
+            address: address.to_string(),
+            weight_bps,
+            account: None,
+        }
+    }
+
+    /// Checks every input's witness against the BIP-143 sighash and the vault's public key.
+    fn assert_signed_by(tx: &Transaction, wallet: &VaultWallet, values: &[u64]) {
+        let secp = secp256k1::Secp256k1::verification_only();
+        let public_key = secp256k1::PublicKey::from_slice(&wallet.public_key).unwrap();
+        let script_pub_key = ScriptBuf::from(wallet.script_pub_key.clone());
+        let mut cache = SighashCache::new(tx);
+        for (index, value) in values.iter().enumerate() {
+            let witness = &tx.input[index].witness;
+            assert_eq!(witness.nth(1), Some(wallet.public_key.as_slice()));
+            let (sighash_type, der) = witness.nth(0).unwrap().split_last().unwrap();
+            assert_eq!(*sighash_type, EcdsaSighashType::All as u8);
+            let sighash = cache
+                .p2wpkh_signature_hash(index, &script_pub_key, Amount::from_sat(*value), EcdsaSighashType::All)
+                .unwrap();
+            let message = secp256k1::Message::from_digest(sighash.to_byte_array());
+            let signature = secp256k1::ecdsa::Signature::from_der(der).unwrap();
+            secp.verify_ecdsa(&message, &signature, &public_key).unwrap();
+        }
+    }
+
+    struct Fixture {
+        signer: MockSigner,
+        backend: MockBitcoin,
+        wallet: VaultWallet,
+        heirs: Vec<HeirRecord>,
+        destinations: Vec<String>,
+    }
+
+    impl Fixture {
+        /// The whole vault paid to the fixture's heirs under `policy`.
+        fn payout(&self, policy: TransactionPolicy, memo: Option<sha256::Hash>) -> Payout<'_> {
+            Payout {
+                vault_id: 5,
+                wallet: &self.wallet,
+                heirs: &self.heirs,
+                destinations: &self.destinations,
+                share_bps: BASIS_POINTS,
+                remaining_bps: BASIS_POINTS,
+                policy,
+                wait_for_confirmations: false,
+                memo,
+            }
+        }
+    }
+
+    fn fixture(utxo_values: &[u64]) -> Fixture {
+        let signer = MockSigner { seed: [7; 32] };
+        let wallet = block_on(derive_wallet(&signer, 5, "dfx_test_key", Network::Regtest, None)).unwrap();
+        let mut backend = MockBitcoin {
+            fee_percentiles: vec![2_000; 100],
+            tip_height: 120,
+            cycles: 1_000_000,
+            ..MockBitcoin::default()
+        };
+        backend.fund(&wallet.address, utxo_values);
+        let heirs = vec![regtest_heir(&signer, 1, 6_000), regtest_heir(&signer, 2, 4_000)];
+        let destinations = heirs.iter().map(|heir| heir.address.clone()).collect();
+        Fixture {
+            signer,
+            backend,
+            wallet,
+            heirs,
+            destinations,
+        }
+    }
+
+    #[test]
+    fn inheritance_sweeps_the_vault_with_valid_signatures() {
+        let fx = fixture(&[60_000, 40_000]);
+        let payout = fx.payout(TransactionPolicy::default(), None);
+        let PayoutReceipt { tx, payouts, .. } = block_on(payout.execute(&fx.backend, &fx.signer)).unwrap();
+
+        // 2 sat/vB over 2 inputs and 2 outputs is a 416 sat fee.
+        assert_eq!(payouts, vec![59_750, 39_834]);
+        let sent = fx.backend.sent.borrow();
+        assert_eq!(sent.len(), 1);
+        let broadcast: Transaction = bitcoin::consensus::deserialize(&sent[0]).unwrap();
+        assert_eq!(broadcast.txid(), tx.txid());
+        let values: Vec<u64> = broadcast.output.iter().map(|out| out.value.to_sat()).collect();
+        assert_eq!(values, payouts);
+        assert_eq!(broadcast.lock_time, bitcoin::absolute::LockTime::from_height(120).unwrap());
+        assert!(broadcast.input.iter().all(|input| input.sequence == bitcoin::Sequence::ENABLE_LOCKTIME_NO_RBF));
+        assert_signed_by(&broadcast, &fx.wallet, &[60_000, 40_000]);
+    }
+
+    #[test]
+    fn tranche_returns_change_to_the_vault() {
+        let fx = fixture(&[100_000]);
+        let payout = Payout {
+            share_bps: 2_500,
+            ..fx.payout(TransactionPolicy::default(), None)
+        };
+        let PayoutReceipt { tx, payouts, .. } = block_on(payout.execute(&fx.backend, &fx.signer)).unwrap();
+
+        assert_eq!(payouts.iter().sum::<u64>(), 25_000 - 2 * (10 + 68 + 3 * 31));
+        let change = tx.output.last().unwrap();
+        assert_eq!(change.value, Amount::from_sat(75_000));
+        assert_eq!(change.script_pubkey.as_bytes(), fx.wallet.script_pub_key.as_slice());
+        assert_signed_by(&tx, &fx.wallet, &[100_000]);
+    }
+
+    #[test]
+    fn payout_is_refused_before_signing_without_cycles() {
+        let mut fx = fixture(&[60_000, 40_000]);
+        fx.backend.cycles = 100;
+        let payout = fx.payout(TransactionPolicy::default(), None);
+        let needed = cycles::signing_cost(&fx.backend, &fx.signer, &fx.wallet, 2, 2).unwrap();
+        assert!(matches!(
+            block_on(payout.execute(&fx.backend, &fx.signer)),
+            Err(BitcoinWalletError::InsufficientCycles { needed: n, available: 100 }) if n == needed
+        ));
+        assert!(fx.backend.sent.borrow().is_empty());
+    }
+
+    #[test]
+    fn empty_vault_is_not_spent() {
+        let fx = fixture(&[]);
+        let payout = fx.payout(TransactionPolicy::default(), None);
+        assert!(matches!(
+            block_on(payout.execute(&fx.backend, &fx.signer)),
+            Err(BitcoinWalletError::NoUtxos(5))
+        ));
+        assert!(fx.backend.sent.borrow().is_empty());
+    }
+
+    #[test]
+    fn memo_is_published_and_paid_for_by_the_heirs() {
+        let fx = fixture(&[100_000]);
+        let commitment = memo::commitment(5, &fx.heirs, 1_000);
+        let payout = fx.payout(TransactionPolicy::default(), Some(commitment));
+        let PayoutReceipt { tx, payouts, fee, .. } = block_on(payout.execute(&fx.backend, &fx.signer)).unwrap();
+
+        assert_eq!(fee, 2 * (10 + 68 + 2 * 31 + memo::OUTPUT_VBYTES));
+        assert_eq!(payouts.iter().sum::<u64>(), 100_000 - fee);
+        assert_eq!(tx.output.len(), 3);
+        assert_eq!(tx.output[2], memo::output(&commitment));
+        assert_signed_by(&tx, &fx.wallet, &[100_000]);
+    }
+
+    #[test]
+    fn utxos_are_read_at_the_requested_confirmations() {
+        let mut fx = fixture(&[60_000, 40_000]);
+        fx.backend.utxos[1].1.height = 118;
+        let (utxos, tip_height) = block_on(fetch_utxos(&fx.backend, &fx.wallet, 6)).unwrap();
+        assert_eq!(tip_height, 120);
+        assert_eq!(utxos.iter().map(|utxo| utxo.value).collect::<Vec<_>>(), [60_000]);
+        let (utxos, _) = block_on(fetch_utxos(&fx.backend, &fx.wallet, 3)).unwrap();
+        assert_eq!(utxos.len(), 2);
+    }
+
+    #[test]
+    fn fresh_deposits_are_left_behind_or_awaited() {
+        let mut fx = fixture(&[60_000, 40_000]);
+        fx.backend.utxos[1].1.height = 118;
+        let policy = TransactionPolicy {
+            min_confirmations: Some(6),
+            ..TransactionPolicy::default()
+        };
+        let mut payout = Payout {
+            wait_for_confirmations: true,
+            ..fx.payout(policy, None)
+        };
+        assert!(matches!(
+            block_on(payout.execute(&fx.backend, &fx.signer)),
+            Err(BitcoinWalletError::AwaitingConfirmations { vault_id: 5, pending: 1 })
+        ));
+        assert!(fx.backend.sent.borrow().is_empty());
+
+        payout.wait_for_confirmations = false;
+        let receipt = block_on(payout.execute(&fx.backend, &fx.signer)).unwrap();
+        assert_eq!(receipt.spent.len(), 1);
+        assert_eq!(receipt.spent[0].confirmations(120), 21);
+        assert_signed_by(&receipt.tx, &fx.wallet, &[60_000]);
+    }
 
     #[test]
     fn allocate_payouts_distributes_full_amount() {
@@ -503,16 +2092,320 @@ mod tests {
             HeirRecord {
                 address: "tb1qtest000000000000000000000000000000000".into(),
                 weight_bps: 6000,
+                account: None,
             },
             HeirRecord {
                 address: "tb1qtest111111111111111111111111111111111".into(),
                 weight_bps: 4000,
+                account: None,
             },
         ];
-        let payouts = allocate_payouts(100_000, &heirs).expect("payouts");
+        let payouts = allocate_payouts(100_000, 0, &heirs, &FeeBearer::Proportional).expect("payouts");
         assert_eq!(payouts.into_iter().sum::<u64>(), 100_000);
     }
 
+    #[test]
+    fn fee_is_taken_from_the_configured_bearer() {
+        let heir = |address: &str, weight_bps| HeirRecord {
+            address: address.into(),
+            weight_bps,
+            account: None,
+        };
+        let heirs = vec![heir("tb1qfirst", 6_000), heir("tb1qsecond", 4_000)];
+        let allocate = |bearer| allocate_payouts(100_000, 416, &heirs, &bearer).unwrap();
+
+        // 99_584 split 60/40 rounds the first share down; the residual heir keeps the extra sat.
+        assert_eq!(allocate(FeeBearer::Proportional), vec![59_750, 39_834]);
+        assert_eq!(allocate(FeeBearer::Heir("tb1qfirst".into())), vec![59_584, 40_000]);
+        assert_eq!(allocate(FeeBearer::Residual), vec![60_000, 39_584]);
+        for bearer in [FeeBearer::Proportional, FeeBearer::Heir("tb1qsecond".into()), FeeBearer::Residual] {
+            assert_eq!(allocate(bearer).iter().sum::<u64>() + 416, 100_000);
+        }
+
+        assert!(matches!(
+            allocate_payouts(100_000, 416, &heirs, &FeeBearer::Heir("tb1qstranger".into())),
+            Err(BitcoinWalletError::InvalidPolicy(_))
+        ));
+        let lopsided = vec![heir("tb1qbig", 9_990), heir("tb1qsmall", 10)];
+        assert!(matches!(
+            allocate_payouts(100_000, 416, &lopsided, &FeeBearer::Residual),
+            Err(BitcoinWalletError::InsufficientFunds { available: 100, required: 962 })
+        ));
+        assert!(matches!(
+            allocate_payouts(100_000, 100, &lopsided, &FeeBearer::Residual),
+            Err(BitcoinWalletError::DustPayout { amount: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn residual_heir_pays_the_broadcast_fee() {
+        let fx = fixture(&[60_000, 40_000]);
+        let policy = TransactionPolicy {
+            fee_bearer: Some(FeeBearer::Residual),
+            ..TransactionPolicy::default()
+        };
+        let payout = fx.payout(policy, None);
+        let PayoutReceipt { tx, payouts, fee, .. } = block_on(payout.execute(&fx.backend, &fx.signer)).unwrap();
+
+        assert_eq!(fee, 416);
+        assert_eq!(payouts, vec![60_000, 39_584]);
+        let values: Vec<u64> = tx.output.iter().map(|out| out.value.to_sat()).collect();
+        assert_eq!(values, payouts);
+        assert_signed_by(&tx, &fx.wallet, &[60_000, 40_000]);
+    }
+
+    #[test]
+    fn dust_payout_names_the_heir() {
+        let heirs = vec![
+            HeirRecord {
+                address: "tb1qbig".into(),
+                weight_bps: 9_900,
+                account: None,
+            },
+            HeirRecord {
+                address: "tb1qsmall".into(),
+                weight_bps: 100,
+                account: None,
+            },
+        ];
+        match allocate_payouts(10_000, 0, &heirs, &FeeBearer::Proportional) {
+            Err(BitcoinWalletError::DustPayout { address, amount }) => {
+                assert_eq!(address, "tb1qsmall");
+                assert_eq!(amount, 100);
+            }
+            _ => panic!("expected a dust payout error"),
+        }
+    }
+
+    #[test]
+    fn heirs_name_exactly_one_destination() {
+        let account = icrc::Account {
+            owner: Principal::from_slice(&[5]),
+            subaccount: None,
+        };
+        let heir = |address: &str, account: Option<icrc::Account>| HeirRecord {
+            address: address.into(),
+            weight_bps: 5_000,
+            account,
+        };
+        assert!(ensure_valid_heirs(&[heir("tb1qheir", None), heir("", Some(account.clone()))]).is_ok());
+        assert!(ensure_valid_heirs(&[heir("tb1qheir", None), heir("tb1qboth", Some(account))]).is_err());
+        assert!(ensure_valid_heirs(&[heir("tb1qheir", None), heir("", None)]).is_err());
+    }
+
+    #[test]
+    fn sweep_output_pays_everything_but_the_fee() {
+        let output = sweep_output(50_000, 1_500, ScriptBuf::new()).expect("sweep");
+        assert_eq!(output.value, Amount::from_sat(48_500));
+        assert!(matches!(
+            sweep_output(1_800, 1_500, ScriptBuf::new()),
+            Err(BitcoinWalletError::InsufficientFunds { available: 1_800, required: 2_046 })
+        ));
+    }
+
+    #[test]
+    fn tranches_keep_the_rest_as_change() {
+        assert_eq!(split_tranche(100_000, 2_500, 10_000), (25_000, 75_000));
+        assert_eq!(split_tranche(75_000, 2_500, 7_500), (25_000, 50_000));
+        assert_eq!(split_tranche(25_000, 2_500, 2_500), (25_000, 0));
+        assert_eq!(split_tranche(10_000, 9_500, 10_000), (10_000, 0));
+    }
+
+    fn token_heir(seed: u8, weight_bps: u64) -> icrc::TokenHeir {
+        icrc::TokenHeir {
+            account: icrc::Account {
+                owner: Principal::from_slice(&[seed]),
+                subaccount: None,
+            },
+            weight_bps,
+        }
+    }
+
+    fn token_vault(vault_id: VaultId, ledger: Principal) -> icrc::ExecuteTokenInheritanceArgs {
+        let fx = fixture(&[]);
+        memory::insert_wallet(vault_id, fx.wallet);
+        memory::register_ledger(vault_id, ledger);
+        icrc::ExecuteTokenInheritanceArgs {
+            vault_id,
+            heirs: vec![token_heir(1, 7_500), token_heir(2, 2_500)],
+        }
+    }
+
+    #[test]
+    fn tokens_stay_put_until_the_guardian_threshold_is_met() {
+        let ledger = Principal::from_slice(&[40]);
+        let args = token_vault(41, ledger);
+        let ledgers = MockLedgers {
+            fee: 10,
+            balances: [(ledger, 1_020)].into(),
+            ..MockLedgers::default()
+        };
+        let guardians = MockGuardians {
+            submitted: 1,
+            threshold_met: false,
+        };
+
+        let result = block_on(pay_token_inheritance(&guardians, &ledgers, Principal::anonymous(), &args, 0));
+        assert!(matches!(
+            result,
+            Err(BitcoinWalletError::GuardianThresholdNotMet { vault_id: 41, submitted: 1 })
+        ));
+        assert!(ledgers.calls.borrow().is_empty());
+        assert!(memory::token_payout_plan(41, ledger).is_none());
+    }
+
+    #[test]
+    fn released_tokens_are_paid_once_per_heir() {
+        let ledger = Principal::from_slice(&[42]);
+        let args = token_vault(43, ledger);
+        let ledgers = MockLedgers {
+            fee: 10,
+            balances: [(ledger, 1_020)].into(),
+            ..MockLedgers::default()
+        };
+        let guardians = MockGuardians {
+            submitted: 2,
+            threshold_met: true,
+        };
+
+        let payouts = block_on(pay_token_inheritance(&guardians, &ledgers, Principal::anonymous(), &args, 0)).unwrap();
+        assert_eq!(payouts.len(), 1);
+        assert!(payouts[0].error.is_none());
+        let amounts: Vec<Nat> = ledgers.transfers.borrow().iter().map(|(_, _, amount)| amount.clone()).collect();
+        assert_eq!(amounts, vec![Nat::from(750u64), Nat::from(250u64)]);
+        assert!(memory::token_payout_plan(43, ledger).is_none());
+    }
+
+    #[test]
+    fn bitcoin_stays_put_until_the_guardian_threshold_is_met() {
+        let fx = fixture(&[60_000]);
+        let guardians = MockGuardians {
+            submitted: 1,
+            threshold_met: false,
+        };
+        let result = block_on(spend_to_heirs(&guardians, 5, &fx.wallet, &fx.heirs, BASIS_POINTS, BASIS_POINTS, false));
+        assert!(matches!(
+            result,
+            Err(BitcoinWalletError::GuardianThresholdNotMet { vault_id: 5, submitted: 1 })
+        ));
+        assert!(SpendLock::acquire(5).is_ok());
+    }
+
+    #[test]
+    fn released_bitcoin_moves_on_to_the_heirs() {
+        let fx = fixture(&[60_000]);
+        let guardians = MockGuardians {
+            submitted: 2,
+            threshold_met: true,
+        };
+        // A ckBTC heir needs the minter, which is the first thing resolved once the guardians
+        // have released the vault.
+        let mut heirs = fx.heirs.clone();
+        heirs[0].account = Some(icrc::Account {
+            owner: Principal::from_slice(&[9]),
+            subaccount: None,
+        });
+        let result = block_on(spend_to_heirs(&guardians, 6, &fx.wallet, &heirs, BASIS_POINTS, BASIS_POINTS, false));
+        assert!(matches!(result, Err(BitcoinWalletError::MinterNotConfigured)));
+    }
+
+    #[test]
+    fn stale_plans_pay_only_transfers_that_never_landed() {
+        let ledger = Principal::from_slice(&[44]);
+        let args = token_vault(45, ledger);
+        let mut plan =
+            icrc::TokenPayoutPlan::new(Nat::from(1_020u64), Nat::from(10u64), &args.heirs, Nat::from(3u64), 0).unwrap();
+        // The first transfer landed before the dedup window closed, but its reply was lost.
+        let landed = icrc::LedgerTransfer {
+            block_index: Nat::from(4u64),
+            memo: Some(icrc::transfer_memo(45, 0).to_vec()),
+        };
+        memory::insert_token_payout_plan(45, ledger, plan.clone());
+        let ledgers = MockLedgers {
+            reject_before: 1,
+            blocks: RefCell::new(vec![landed]),
+            ..MockLedgers::default()
+        };
+        let guardians = MockGuardians {
+            submitted: 2,
+            threshold_met: true,
+        };
+
+        let payouts = block_on(pay_token_inheritance(&guardians, &ledgers, Principal::anonymous(), &args, 5)).unwrap();
+        assert!(payouts[0].error.is_none());
+        let blocks: Vec<_> = payouts[0].transfers.iter().map(|transfer| transfer.block_index.clone()).collect();
+        assert_eq!(blocks, vec![Some(Nat::from(4u64)), Some(Nat::from(5u64))]);
+        let amounts: Vec<Nat> = ledgers.transfers.borrow().iter().map(|(_, _, amount)| amount.clone()).collect();
+        assert_eq!(amounts, vec![Nat::from(250u64)]);
+        assert!(memory::token_payout_plan(45, ledger).is_none());
+
+        // Without a TooOld answer the plan keeps its original creation time.
+        plan.transfers.truncate(1);
+        memory::insert_token_payout_plan(45, ledger, plan);
+        let fresh = MockLedgers::default();
+        block_on(pay_token_inheritance(&guardians, &fresh, Principal::anonymous(), &args, 5)).unwrap();
+        assert!(!fresh.calls.borrow().contains(&"get_transactions"));
+        assert_eq!(fresh.transfers.borrow().len(), 1);
+    }
+
+    #[test]
+    fn retired_keys_spend_under_their_own_key() {
+        let wallet = |key_id: &str, address: &str| VaultWallet {
+            key_id: key_id.into(),
+            derivation_path: vec![4u64.to_be_bytes().to_vec()],
+            address: address.into(),
+            script_pub_key: vec![0, 20, 1],
+            public_key: vec![2; 33],
+            network: Network::Testnet,
+            owner: None,
+            retired_keys: None,
+        };
+        let old = wallet("test_key_1", "tb1qold");
+        let current = VaultWallet {
+            retired_keys: Some(vec![old.retire(10)]),
+            ..wallet("key_1", "tb1qnew")
+        };
+
+        let source = current.under_retired(&current.retired_keys()[0]);
+        assert_eq!((source.key_id.as_str(), source.address.as_str()), ("test_key_1", "tb1qold"));
+        assert!(source.retired_keys().is_empty());
+        assert_eq!(current.retired_keys()[0].retired_at, 10);
+    }
+
+    #[test]
+    fn sweep_moves_every_utxo_to_the_new_key() {
+        let fx = fixture(&[60_000, 40_000]);
+        let new_wallet = block_on(derive_wallet(&fx.signer, 5, "dfx_test_key_2", Network::Regtest, None)).unwrap();
+        assert_ne!(new_wallet.address, fx.wallet.address);
+
+        let tx_id = block_on(sweep_wallet(&fx.backend, &fx.signer, 5, &fx.wallet, &new_wallet, 1_000)).unwrap();
+        let sent = fx.backend.sent.borrow();
+        assert_eq!(sent.len(), 1);
+        let broadcast: Transaction = bitcoin::consensus::deserialize(&sent[0]).unwrap();
+        assert_eq!(tx_id, Some(broadcast.txid().to_string()));
+        // 2 sat/vB over 2 inputs and 1 output is a 354 sat fee.
+        assert_eq!(broadcast.output.len(), 1);
+        assert_eq!(broadcast.output[0].value.to_sat(), 99_646);
+        assert_eq!(broadcast.output[0].script_pubkey.as_bytes(), new_wallet.script_pub_key.as_slice());
+        assert_signed_by(&broadcast, &fx.wallet, &[60_000, 40_000]);
+
+        let withdrawal = memory::vault_history(5).pop().unwrap();
+        assert_eq!(withdrawal.kind, history::MovementKind::Withdrawal);
+        assert_eq!(withdrawal.recipients[0].address, new_wallet.address);
+
+        let empty = block_on(derive_wallet(&fx.signer, 6, "dfx_test_key", Network::Regtest, None)).unwrap();
+        assert_eq!(block_on(sweep_wallet(&fx.backend, &fx.signer, 6, &empty, &new_wallet, 1_000)).unwrap(), None);
+    }
+
+    #[test]
+    fn spend_lock_is_exclusive_per_vault() {
+        let lock = SpendLock::acquire(9).expect("first lock");
+        assert!(matches!(SpendLock::acquire(9), Err(BitcoinWalletError::SpendInProgress(9))));
+        assert!(SpendLock::acquire(10).is_ok());
+        drop(lock);
+        assert!(SpendLock::acquire(9).is_ok());
+    }
+
     #[test]
     fn estimate_fee_scales_with_inputs_outputs() {
         let low = estimate_fee_sat(5, 1, 2).unwrap();
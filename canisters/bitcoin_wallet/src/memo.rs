@@ -0,0 +1,106 @@
+//! OP_RETURN marker linking an inheritance payout to its vault and heir set.
+//!
+//! The output carries `MEMO_TAG` followed by SHA-256 over the vault id (8 bytes, big endian),
+//! then for each heir in order its address, or principal text for ckBTC heirs, prefixed by its
+//! length (4 bytes, big endian) and followed by its weight in basis points (8 bytes, big
+//! endian), and finally the execution time in nanoseconds (8 bytes, big endian). Anyone holding
+//! the will can recompute it.
+
+use crate::{HeirRecord, VaultId};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::script::PushBytesBuf;
+use bitcoin::{Amount, ScriptBuf, TxOut};
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+/// Marks the output as a ThresholdVault memo, version 1.
+pub const MEMO_TAG: &[u8; 4] = b"TVI1";
+
+/// Virtual size of the memo output: value, script length, OP_RETURN, push opcode and payload.
+pub const OUTPUT_VBYTES: u64 = 8 + 1 + 2 + (MEMO_TAG.len() + sha256::Hash::LEN) as u64;
+
+/// A published commitment with the execution time it was computed over, which anyone
+/// recomputing it needs.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub struct InheritanceMemo {
+    /// Hex SHA-256 commitment carried in the OP_RETURN output.
+    pub commitment: String,
+    /// IC time in nanoseconds.
+    #[serde(rename = "executedAt")]
+    pub executed_at: u64,
+}
+
+impl InheritanceMemo {
+    pub fn new(commitment: &sha256::Hash, executed_at: u64) -> Self {
+        Self {
+            commitment: commitment.to_string(),
+            executed_at,
+        }
+    }
+}
+
+pub fn commitment(vault_id: VaultId, heirs: &[HeirRecord], executed_at: u64) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&vault_id.to_be_bytes());
+    for heir in heirs {
+        let label = heir.label();
+        engine.input(&(label.len() as u32).to_be_bytes());
+        engine.input(label.as_bytes());
+        engine.input(&heir.weight_bps.to_be_bytes());
+    }
+    engine.input(&executed_at.to_be_bytes());
+    sha256::Hash::from_engine(engine)
+}
+
+pub fn output(commitment: &sha256::Hash) -> TxOut {
+    let mut payload = PushBytesBuf::from(MEMO_TAG);
+    payload
+        .extend_from_slice(commitment.as_byte_array())
+        .expect("memo payload fits a single push");
+    TxOut {
+        value: Amount::ZERO,
+        script_pubkey: ScriptBuf::new_op_return(&payload),
+    }
+}
+
+/// Fee the memo output adds at `rate` sat/vB.
+pub fn fee_sat(rate: u64) -> Option<u64> {
+    rate.checked_mul(OUTPUT_VBYTES)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::consensus::Encodable;
+
+    fn heir(address: &str, weight_bps: u64) -> HeirRecord {
+        HeirRecord {
+            address: address.into(),
+            weight_bps,
+            account: None,
+        }
+    }
+
+    #[test]
+    fn commitment_binds_vault_heirs_and_time() {
+        let heirs = [heir("bcrt1qa", 6_000), heir("bcrt1qb", 4_000)];
+        let base = commitment(1, &heirs, 5);
+        assert_eq!(base, commitment(1, &heirs, 5));
+        assert_ne!(base, commitment(2, &heirs, 5));
+        assert_ne!(base, commitment(1, &heirs, 6));
+        assert_ne!(base, commitment(1, &[heir("bcrt1qa", 4_000), heir("bcrt1qb", 6_000)], 5));
+    }
+
+    #[test]
+    fn output_is_a_zero_value_op_return() {
+        let commitment = commitment(1, &[heir("bcrt1qa", 10_000)], 5);
+        let output = output(&commitment);
+        assert!(output.script_pubkey.is_op_return());
+        assert_eq!(output.value, Amount::ZERO);
+        assert!(output.script_pubkey.as_bytes().ends_with(commitment.as_byte_array()));
+
+        let mut encoded = Vec::new();
+        output.consensus_encode(&mut encoded).unwrap();
+        assert_eq!(encoded.len() as u64, OUTPUT_VBYTES);
+    }
+}
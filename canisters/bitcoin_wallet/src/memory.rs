@@ -0,0 +1,510 @@
+use crate::audit::AuditEntry;
+use crate::ckbtc::PendingMint;
+use crate::grace::PendingExecution;
+use crate::history::HistoryEntry;
+use crate::icrc::TokenPayoutPlan;
+use crate::policy::TransactionPolicy;
+use crate::upgrade::VersionedState;
+use crate::vesting::VestingSchedule;
+use crate::{HeirConfig, VaultId, VaultWallet, VaultWalletState};
+use candid::Principal;
+use canister_common::access::ManagerRotation;
+use canister_common::{candid_storable, storable};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell, StableLog, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const CONFIG_MEMORY: MemoryId = MemoryId::new(0);
+const WALLETS_MEMORY: MemoryId = MemoryId::new(1);
+const AUDIT_INDEX_MEMORY: MemoryId = MemoryId::new(2);
+const AUDIT_DATA_MEMORY: MemoryId = MemoryId::new(3);
+const AUDIT_BY_VAULT_MEMORY: MemoryId = MemoryId::new(4);
+const VAULT_LEDGERS_MEMORY: MemoryId = MemoryId::new(5);
+const VESTING_MEMORY: MemoryId = MemoryId::new(6);
+const PENDING_EXECUTIONS_MEMORY: MemoryId = MemoryId::new(7);
+const POLICIES_MEMORY: MemoryId = MemoryId::new(8);
+const CYCLES_SPENT_MEMORY: MemoryId = MemoryId::new(9);
+const HISTORY_MEMORY: MemoryId = MemoryId::new(10);
+const HEIRS_MEMORY: MemoryId = MemoryId::new(11);
+const EXECUTIONS_DUE_MEMORY: MemoryId = MemoryId::new(12);
+const TRANCHES_DUE_MEMORY: MemoryId = MemoryId::new(13);
+const ROTATIONS_INDEX_MEMORY: MemoryId = MemoryId::new(14);
+const ROTATIONS_DATA_MEMORY: MemoryId = MemoryId::new(15);
+const PENDING_MINTS_MEMORY: MemoryId = MemoryId::new(16);
+const TOKEN_PAYOUTS_MEMORY: MemoryId = MemoryId::new(17);
+
+/// `(next run time, vault)` for every schedule that still has something to run, so the timer
+/// finds due work without decoding finished schedules.
+type DueIndex = StableBTreeMap<(u64, VaultId), (), Memory>;
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    static CONFIG: RefCell<StableCell<VaultWalletState, Memory>> = RefCell::new(StableCell::init(
+        MEMORY_MANAGER.with(|m| m.borrow().get(CONFIG_MEMORY)),
+        VaultWalletState::default(),
+    ));
+
+    static WALLETS: RefCell<StableBTreeMap<VaultId, VaultWallet, Memory>> =
+        RefCell::new(StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(WALLETS_MEMORY))));
+
+    static AUDIT_LOG: RefCell<StableLog<AuditEntry, Memory, Memory>> = RefCell::new(StableLog::init(
+        MEMORY_MANAGER.with(|m| m.borrow().get(AUDIT_INDEX_MEMORY)),
+        MEMORY_MANAGER.with(|m| m.borrow().get(AUDIT_DATA_MEMORY)),
+    ));
+
+    static AUDIT_BY_VAULT: RefCell<StableBTreeMap<(VaultId, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(AUDIT_BY_VAULT_MEMORY))));
+
+    static VAULT_LEDGERS: RefCell<StableBTreeMap<(VaultId, Principal), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(VAULT_LEDGERS_MEMORY))));
+
+    static VESTING: RefCell<StableBTreeMap<VaultId, VestingSchedule, Memory>> =
+        RefCell::new(StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(VESTING_MEMORY))));
+
+    static PENDING_EXECUTIONS: RefCell<StableBTreeMap<VaultId, PendingExecution, Memory>> =
+        RefCell::new(StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(PENDING_EXECUTIONS_MEMORY))));
+
+    static POLICIES: RefCell<StableBTreeMap<VaultId, TransactionPolicy, Memory>> =
+        RefCell::new(StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(POLICIES_MEMORY))));
+
+    static CYCLES_SPENT: RefCell<StableBTreeMap<VaultId, u128, Memory>> =
+        RefCell::new(StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(CYCLES_SPENT_MEMORY))));
+
+    static HISTORY: RefCell<StableBTreeMap<(VaultId, u64), HistoryEntry, Memory>> =
+        RefCell::new(StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(HISTORY_MEMORY))));
+
+    static HEIRS: RefCell<StableBTreeMap<VaultId, HeirConfig, Memory>> =
+        RefCell::new(StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(HEIRS_MEMORY))));
+
+    static EXECUTIONS_DUE: RefCell<DueIndex> =
+        RefCell::new(StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(EXECUTIONS_DUE_MEMORY))));
+
+    static TRANCHES_DUE: RefCell<DueIndex> =
+        RefCell::new(StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TRANCHES_DUE_MEMORY))));
+
+    static ROTATIONS: RefCell<StableLog<ManagerRotation, Memory, Memory>> = RefCell::new(StableLog::init(
+        MEMORY_MANAGER.with(|m| m.borrow().get(ROTATIONS_INDEX_MEMORY)),
+        MEMORY_MANAGER.with(|m| m.borrow().get(ROTATIONS_DATA_MEMORY)),
+    ));
+
+    static PENDING_MINTS: RefCell<StableBTreeMap<(VaultId, u64), PendingMint, Memory>> =
+        RefCell::new(StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(PENDING_MINTS_MEMORY))));
+
+    static TOKEN_PAYOUTS: RefCell<StableBTreeMap<(VaultId, Principal), TokenPayoutPlan, Memory>> =
+        RefCell::new(StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TOKEN_PAYOUTS_MEMORY))));
+}
+
+/// Canister-wide configuration (roles, managers, pause flag) kept in a stable cell.
+pub fn with_state<F, R>(f: F) -> R
+where
+    F: FnOnce(&VaultWalletState) -> R,
+{
+    CONFIG.with(|cell| f(cell.borrow().get()))
+}
+
+pub fn mutate_state<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut VaultWalletState) -> R,
+{
+    CONFIG.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let mut state = cell.get().clone();
+        let result = f(&mut state);
+        cell.set(state);
+        result
+    })
+}
+
+pub fn append_rotation(rotation: ManagerRotation) {
+    ROTATIONS.with(|log| log.borrow().append(&rotation).expect("rotation log out of stable memory"));
+}
+
+pub fn manager_rotations() -> Vec<ManagerRotation> {
+    ROTATIONS.with(|log| log.borrow().iter().collect())
+}
+
+pub fn wallet(vault_id: VaultId) -> Option<VaultWallet> {
+    WALLETS.with(|wallets| wallets.borrow().get(&vault_id))
+}
+
+pub fn insert_wallet(vault_id: VaultId, wallet: VaultWallet) {
+    WALLETS.with(|wallets| wallets.borrow_mut().insert(vault_id, wallet));
+}
+
+pub fn wallet_count() -> u64 {
+    WALLETS.with(|wallets| wallets.borrow().len())
+}
+
+pub fn wallets() -> Vec<(VaultId, VaultWallet)> {
+    WALLETS.with(|wallets| {
+        wallets
+            .borrow()
+            .iter()
+            .map(|entry| (*entry.key(), entry.value()))
+            .collect()
+    })
+}
+
+/// Appends to the audit log, indexing the entry under its vault when it has one.
+pub fn append_audit(mut entry: AuditEntry) -> u64 {
+    AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        entry.seq = log.len();
+        log.append(&entry).expect("audit log out of stable memory")
+    });
+    if let Some(vault_id) = entry.vault_id {
+        AUDIT_BY_VAULT.with(|index| index.borrow_mut().insert((vault_id, entry.seq), ()));
+    }
+    entry.seq
+}
+
+pub fn audit_len() -> u64 {
+    AUDIT_LOG.with(|log| log.borrow().len())
+}
+
+/// Entries `[offset, offset + limit)` of the whole log, oldest first.
+pub fn audit_range(offset: u64, limit: u64) -> Vec<AuditEntry> {
+    AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        (offset..offset.saturating_add(limit).min(log.len()))
+            .filter_map(|seq| log.get(seq))
+            .collect()
+    })
+}
+
+/// Number of audit entries recorded for `vault_id`.
+pub fn vault_audit_len(vault_id: VaultId) -> u64 {
+    AUDIT_BY_VAULT.with(|index| index.borrow().keys_range((vault_id, 0)..=(vault_id, u64::MAX)).count() as u64)
+}
+
+/// A page of the entries recorded for `vault_id`, oldest first.
+pub fn vault_audit_range(vault_id: VaultId, offset: u64, limit: u64) -> Vec<AuditEntry> {
+    let seqs: Vec<u64> = AUDIT_BY_VAULT.with(|index| {
+        index
+            .borrow()
+            .keys_range((vault_id, 0)..=(vault_id, u64::MAX))
+            .skip(offset as usize)
+            .take(limit as usize)
+            .map(|(_, seq)| seq)
+            .collect()
+    });
+    AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        seqs.into_iter().filter_map(|seq| log.get(seq)).collect()
+    })
+}
+
+/// Registers an ICRC-1 ledger for `vault_id`; false when it was already registered.
+pub fn register_ledger(vault_id: VaultId, ledger: Principal) -> bool {
+    VAULT_LEDGERS.with(|ledgers| ledgers.borrow_mut().insert((vault_id, ledger), ()).is_none())
+}
+
+pub fn vault_ledgers(vault_id: VaultId) -> Vec<Principal> {
+    VAULT_LEDGERS.with(|ledgers| {
+        ledgers
+            .borrow()
+            .keys_range((vault_id, Principal::management_canister())..)
+            .take_while(|(id, _)| *id == vault_id)
+            .map(|(_, ledger)| ledger)
+            .collect()
+    })
+}
+
+/// Unsettled token payout of `vault_id` on `ledger`.
+pub fn token_payout_plan(vault_id: VaultId, ledger: Principal) -> Option<TokenPayoutPlan> {
+    TOKEN_PAYOUTS.with(|plans| plans.borrow().get(&(vault_id, ledger)))
+}
+
+pub fn insert_token_payout_plan(vault_id: VaultId, ledger: Principal, plan: TokenPayoutPlan) {
+    TOKEN_PAYOUTS.with(|plans| plans.borrow_mut().insert((vault_id, ledger), plan));
+}
+
+pub fn remove_token_payout_plan(vault_id: VaultId, ledger: Principal) {
+    TOKEN_PAYOUTS.with(|plans| plans.borrow_mut().remove(&(vault_id, ledger)));
+}
+
+pub fn vesting_schedule(vault_id: VaultId) -> Option<VestingSchedule> {
+    VESTING.with(|schedules| schedules.borrow().get(&vault_id))
+}
+
+pub fn insert_vesting_schedule(vault_id: VaultId, schedule: VestingSchedule) {
+    let next = schedule.next_run_at();
+    let previous = VESTING.with(|schedules| schedules.borrow_mut().insert(vault_id, schedule));
+    TRANCHES_DUE.with(|index| reindex(&mut index.borrow_mut(), vault_id, previous.and_then(|s| s.next_run_at()), next));
+}
+
+/// Vaults whose next tranche is due at `now`, earliest first.
+pub fn due_vesting_schedules(now: u64) -> Vec<VaultId> {
+    TRANCHES_DUE.with(|index| due(&index.borrow(), now))
+}
+
+pub fn next_tranche_at() -> Option<u64> {
+    TRANCHES_DUE.with(|index| index.borrow().first_key_value().map(|((at, _), ())| at))
+}
+
+/// Schedules with unpaid tranches.
+pub fn active_vesting_count() -> u64 {
+    TRANCHES_DUE.with(|index| index.borrow().len())
+}
+
+/// Latest scheduled execution of `vault_id`, including cancelled and executed ones.
+pub fn pending_execution(vault_id: VaultId) -> Option<PendingExecution> {
+    PENDING_EXECUTIONS.with(|executions| executions.borrow().get(&vault_id))
+}
+
+pub fn insert_pending_execution(vault_id: VaultId, execution: PendingExecution) {
+    let next = execution.next_run_at();
+    let previous = PENDING_EXECUTIONS.with(|executions| executions.borrow_mut().insert(vault_id, execution));
+    EXECUTIONS_DUE.with(|index| reindex(&mut index.borrow_mut(), vault_id, previous.and_then(|e| e.next_run_at()), next));
+}
+
+/// Vaults whose scheduled execution is due at `now`, earliest first.
+pub fn due_executions(now: u64) -> Vec<VaultId> {
+    EXECUTIONS_DUE.with(|index| due(&index.borrow(), now))
+}
+
+pub fn next_execution_at() -> Option<u64> {
+    EXECUTIONS_DUE.with(|index| index.borrow().first_key_value().map(|((at, _), ())| at))
+}
+
+/// Executions still waiting to run.
+pub fn scheduled_execution_count() -> u64 {
+    EXECUTIONS_DUE.with(|index| index.borrow().len())
+}
+
+/// Rebuilds both due indexes from the schedules themselves. Called on upgrade, which covers
+/// schedules written before the indexes existed.
+pub fn reindex_schedules() {
+    EXECUTIONS_DUE.with(|index| {
+        let mut index = index.borrow_mut();
+        index.clear_new();
+        PENDING_EXECUTIONS.with(|executions| {
+            for entry in executions.borrow().iter() {
+                reindex(&mut index, *entry.key(), None, entry.value().next_run_at());
+            }
+        });
+    });
+    TRANCHES_DUE.with(|index| {
+        let mut index = index.borrow_mut();
+        index.clear_new();
+        VESTING.with(|schedules| {
+            for entry in schedules.borrow().iter() {
+                reindex(&mut index, *entry.key(), None, entry.value().next_run_at());
+            }
+        });
+    });
+}
+
+fn reindex(index: &mut DueIndex, vault_id: VaultId, previous: Option<u64>, next: Option<u64>) {
+    if let Some(at) = previous {
+        index.remove(&(at, vault_id));
+    }
+    if let Some(at) = next {
+        index.insert((at, vault_id), ());
+    }
+}
+
+fn due(index: &DueIndex, now: u64) -> Vec<VaultId> {
+    index.keys_range(..=(now, VaultId::MAX)).map(|(_, vault_id)| vault_id).collect()
+}
+
+/// Policy for spends from `vault_id`, or the default when none was stored.
+pub fn transaction_policy(vault_id: VaultId) -> TransactionPolicy {
+    POLICIES.with(|policies| policies.borrow().get(&vault_id)).unwrap_or_default()
+}
+
+pub fn insert_transaction_policy(vault_id: VaultId, policy: TransactionPolicy) {
+    POLICIES.with(|policies| policies.borrow_mut().insert(vault_id, policy));
+}
+
+/// Cycles attached to system API calls on behalf of `vault_id` since it was created.
+pub fn cycles_spent(vault_id: VaultId) -> u128 {
+    CYCLES_SPENT.with(|spent| spent.borrow().get(&vault_id)).unwrap_or_default()
+}
+
+pub fn add_cycles_spent(vault_id: VaultId, cycles: u128) {
+    CYCLES_SPENT.with(|spent| {
+        let mut spent = spent.borrow_mut();
+        let total = spent.get(&vault_id).unwrap_or_default().saturating_add(cycles);
+        spent.insert(vault_id, total);
+    });
+}
+
+/// Appends to the history of `vault_id`, keeping entries in recording order.
+pub fn append_history(vault_id: VaultId, entry: HistoryEntry) {
+    HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        let next = history
+            .range((vault_id, 0)..=(vault_id, u64::MAX))
+            .last()
+            .map_or(0, |entry| entry.key().1 + 1);
+        history.insert((vault_id, next), entry);
+    });
+}
+
+pub fn vault_history(vault_id: VaultId) -> Vec<HistoryEntry> {
+    HISTORY.with(|history| {
+        history
+            .borrow()
+            .range((vault_id, 0)..=(vault_id, u64::MAX))
+            .map(|entry| entry.value())
+            .collect()
+    })
+}
+
+pub fn heir_config(vault_id: VaultId) -> Option<HeirConfig> {
+    HEIRS.with(|heirs| heirs.borrow().get(&vault_id))
+}
+
+pub fn insert_heir_config(vault_id: VaultId, config: HeirConfig) {
+    HEIRS.with(|heirs| heirs.borrow_mut().insert(vault_id, config));
+}
+
+pub fn insert_pending_mint(vault_id: VaultId, mint: PendingMint) {
+    PENDING_MINTS.with(|mints| {
+        let mut mints = mints.borrow_mut();
+        let next = mints
+            .range((vault_id, 0)..=(vault_id, u64::MAX))
+            .last()
+            .map_or(0, |entry| entry.key().1 + 1);
+        mints.insert((vault_id, next), mint);
+    });
+}
+
+pub fn update_pending_mint(key: (VaultId, u64), mint: PendingMint) {
+    PENDING_MINTS.with(|mints| mints.borrow_mut().insert(key, mint));
+}
+
+pub fn pending_mint(key: (VaultId, u64)) -> Option<PendingMint> {
+    PENDING_MINTS.with(|mints| mints.borrow().get(&key))
+}
+
+pub fn remove_pending_mint(key: (VaultId, u64)) -> Option<PendingMint> {
+    PENDING_MINTS.with(|mints| mints.borrow_mut().remove(&key))
+}
+
+/// Deposits still waiting for the minter, across all vaults. Entries leave the map once
+/// minted or abandoned, so it stays small enough to scan.
+pub fn pending_mints() -> Vec<((VaultId, u64), PendingMint)> {
+    PENDING_MINTS.with(|mints| mints.borrow().iter().map(|entry| (*entry.key(), entry.value())).collect())
+}
+
+pub fn vault_pending_mints(vault_id: VaultId) -> Vec<PendingMint> {
+    PENDING_MINTS.with(|mints| {
+        mints
+            .borrow()
+            .range((vault_id, 0)..=(vault_id, u64::MAX))
+            .map(|entry| entry.value())
+            .collect()
+    })
+}
+
+pub fn next_mint_attempt_at() -> Option<u64> {
+    PENDING_MINTS.with(|mints| mints.borrow().iter().map(|entry| entry.value().next_attempt_at).min())
+}
+
+/// True when stable memory still holds a snapshot written by `stable_save` instead of the
+/// memory manager layout. Must be checked before any stable structure is touched.
+pub fn holds_legacy_snapshot() -> bool {
+    if ic_cdk::stable::stable_size() == 0 {
+        return false;
+    }
+    let mut magic = [0u8; 3];
+    ic_cdk::stable::stable_read(0, &mut magic);
+    &magic != b"MGR"
+}
+
+impl Storable for VaultWalletState {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.clone().into_bytes())
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        storable::encode(&VersionedState::V1(self), "wallet config")
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        match storable::decode(&bytes, "wallet config") {
+            VersionedState::V1(state) => state,
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+candid_storable!(VaultWallet, "vault wallet");
+
+candid_storable!(VestingSchedule, "vesting schedule");
+
+candid_storable!(PendingExecution, "pending execution");
+
+candid_storable!(TransactionPolicy, "transaction policy");
+
+candid_storable!(HistoryEntry, "history entry");
+
+candid_storable!(TokenPayoutPlan, "token payout plan");
+
+candid_storable!(PendingMint, "pending mint");
+
+candid_storable!(HeirConfig, "heir config");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_cdk::bitcoin_canister::Network;
+
+    #[test]
+    fn wallet_records_survive_the_stable_map() {
+        let record = VaultWallet {
+            key_id: "test_key_1".into(),
+            derivation_path: vec![3u64.to_be_bytes().to_vec()],
+            address: "tb1qexample".into(),
+            script_pub_key: vec![0, 20, 1],
+            public_key: vec![2; 33],
+            network: Network::Testnet,
+            owner: None,
+            retired_keys: None,
+        };
+        insert_wallet(3, record);
+        assert_eq!(wallet(3).unwrap().address, "tb1qexample");
+        assert_eq!(wallets().len(), 1);
+
+        mutate_state(|state| state.vault_manager = Some(candid::Principal::anonymous()));
+        let encoded = with_state(|state| state.to_bytes().into_owned());
+        let decoded = VaultWalletState::from_bytes(Cow::Owned(encoded));
+        assert_eq!(decoded.vault_manager, Some(candid::Principal::anonymous()));
+    }
+
+    #[test]
+    fn due_index_follows_the_schedule() {
+        let request = crate::ExecuteInheritanceArgs {
+            vault_id: 8,
+            key_id: "test_key_1".into(),
+            heirs: vec![],
+            guardian_submissions: 0,
+            wait_for_confirmations: None,
+        };
+        let mut pending = PendingExecution::new(request, Principal::anonymous(), 100, 0);
+        insert_pending_execution(8, pending.clone());
+        assert_eq!(next_execution_at(), Some(100));
+        assert_eq!(due_executions(99), Vec::<VaultId>::new());
+        assert_eq!(due_executions(100), vec![8]);
+
+        pending.mark_failed("no fee estimate".into(), 100);
+        insert_pending_execution(8, pending.clone());
+        assert_eq!(due_executions(100), Vec::<VaultId>::new());
+        assert_eq!(scheduled_execution_count(), 1);
+
+        pending.cancel(vec![Principal::anonymous()], 200);
+        insert_pending_execution(8, pending);
+        assert_eq!(next_execution_at(), None);
+        assert_eq!(scheduled_execution_count(), 0);
+        assert!(pending_execution(8).is_some());
+    }
+}
@@ -0,0 +1,82 @@
+//! Wallet metrics for the shared `/metrics` endpoint.
+//!
+//! Call counters live on the heap and restart from zero after an upgrade, which Prometheus
+//! treats as an ordinary counter reset.
+
+pub use canister_common::metrics::{serve, HttpRequest, HttpResponse, MetricsEncoder};
+use ic_cdk::call::Error as CallError;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
+thread_local! {
+    static SIGN_CALLS: Cell<u64> = const { Cell::new(0) };
+    static SIGN_FAILURES: Cell<u64> = const { Cell::new(0) };
+    static BITCOIN_API_ERRORS: RefCell<BTreeMap<(&'static str, &'static str), u64>> =
+        const { RefCell::new(BTreeMap::new()) };
+    static LAST_EXECUTION_AT: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+pub fn record_signature(ok: bool) {
+    SIGN_CALLS.with(|calls| calls.set(calls.get() + 1));
+    if !ok {
+        SIGN_FAILURES.with(|failures| failures.set(failures.get() + 1));
+    }
+}
+
+pub fn record_bitcoin_error(method: &'static str, err: &CallError) {
+    let kind = match err {
+        CallError::InsufficientLiquidCycleBalance(_) => "insufficient_cycles",
+        CallError::CallPerformFailed(_) => "call_perform_failed",
+        CallError::CallRejected(_) => "rejected",
+        CallError::CandidDecodeFailed(_) => "decode_failed",
+    };
+    BITCOIN_API_ERRORS.with(|errors| *errors.borrow_mut().entry((method, kind)).or_default() += 1);
+}
+
+pub fn record_execution(at: u64) {
+    LAST_EXECUTION_AT.with(|last| last.set(Some(at)));
+}
+
+/// Appends the call counters kept by this module.
+pub fn encode_counters(encoder: &mut MetricsEncoder) {
+    encoder.counter(
+        "bitcoin_wallet_sign_with_ecdsa_calls_total",
+        "Threshold ECDSA signing calls.",
+        SIGN_CALLS.with(Cell::get),
+    );
+    encoder.counter(
+        "bitcoin_wallet_sign_with_ecdsa_failures_total",
+        "Threshold ECDSA signing calls that failed.",
+        SIGN_FAILURES.with(Cell::get),
+    );
+    encoder.family("bitcoin_wallet_bitcoin_api_errors_total", "counter", "Failed Bitcoin canister calls.");
+    BITCOIN_API_ERRORS.with(|errors| {
+        for ((method, kind), count) in errors.borrow().iter() {
+            encoder.sample("bitcoin_wallet_bitcoin_api_errors_total", &[("method", method), ("kind", kind)], count);
+        }
+    });
+    if let Some(at) = LAST_EXECUTION_AT.with(Cell::get) {
+        encoder.gauge(
+            "bitcoin_wallet_last_execution_timestamp_seconds",
+            "When the last inheritance or tranche was broadcast.",
+            at / 1_000_000_000,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signing_calls_and_failures_are_counted() {
+        record_signature(true);
+        record_signature(false);
+        let mut encoder = MetricsEncoder::default();
+        encode_counters(&mut encoder);
+        let body = encoder.finish();
+        assert!(body.contains("# TYPE bitcoin_wallet_sign_with_ecdsa_calls_total counter\n"));
+        assert!(body.contains("bitcoin_wallet_sign_with_ecdsa_calls_total 2\n"));
+        assert!(body.contains("bitcoin_wallet_sign_with_ecdsa_failures_total 1\n"));
+    }
+}
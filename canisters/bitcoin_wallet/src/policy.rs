@@ -0,0 +1,199 @@
+use crate::{BitcoinWalletError, HeirRecord, MIN_CONFIRMATIONS};
+use bitcoin::absolute::{LockTime, LOCK_TIME_THRESHOLD};
+use bitcoin::Sequence;
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+/// Which heirs a payout's network fee is taken from.
+#[derive(Clone, Debug, Default, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub enum FeeBearer {
+    /// Every heir pays in proportion to their weight.
+    #[default]
+    Proportional,
+    /// The heir with this address, or principal for ckBTC heirs, pays the whole fee.
+    Heir(String),
+    /// The last heir, who also receives rounding remainders, pays the whole fee.
+    Residual,
+}
+
+/// How transactions spending a vault are built. Vaults without a stored policy use the
+/// default: anti-fee-sniping on, no fixed locktime, not replaceable, one confirmation, fees
+/// shared proportionally.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub struct TransactionPolicy {
+    /// Sets the locktime to the current tip height so a reorg cannot profitably re-mine the spend.
+    #[serde(rename = "antiFeeSniping")]
+    pub anti_fee_sniping: bool,
+    /// Block height before which the vault cannot be spent. Spends attempted earlier fail
+    /// with `LockTimeNotReached`; timer-driven payouts retry until it passes.
+    #[serde(rename = "lockTime")]
+    pub lock_time: Option<u32>,
+    /// Signals BIP-125 replaceability so a stuck payout can be fee-bumped.
+    pub replaceable: bool,
+    /// Confirmations a deposit needs before payouts spend it. Deposits still in the mempool
+    /// are invisible to the Bitcoin canister, so the lowest setting is one.
+    #[serde(rename = "minConfirmations")]
+    pub min_confirmations: Option<u32>,
+    /// Adds an OP_RETURN output committing to the vault, heir set and execution time to
+    /// inheritance payouts.
+    #[serde(rename = "inheritanceMemo")]
+    pub inheritance_memo: Option<bool>,
+    #[serde(rename = "feeBearer")]
+    pub fee_bearer: Option<FeeBearer>,
+}
+
+impl Default for TransactionPolicy {
+    fn default() -> Self {
+        Self {
+            anti_fee_sniping: true,
+            lock_time: None,
+            replaceable: false,
+            min_confirmations: None,
+            inheritance_memo: None,
+            fee_bearer: None,
+        }
+    }
+}
+
+impl TransactionPolicy {
+    /// Only block-height locktimes are supported; the Bitcoin canister reports no median time past.
+    pub fn validate(&self) -> Result<(), BitcoinWalletError> {
+        if self.lock_time.is_some_and(|height| height == 0 || height >= LOCK_TIME_THRESHOLD) {
+            return Err(BitcoinWalletError::InvalidPolicy("locktime must be a block height".into()));
+        }
+        if self.min_confirmations == Some(0) {
+            return Err(BitcoinWalletError::InvalidPolicy("at least one confirmation is required".into()));
+        }
+        if matches!(&self.fee_bearer, Some(FeeBearer::Heir(label)) if label.trim().is_empty()) {
+            return Err(BitcoinWalletError::InvalidPolicy("fee bearer must name an heir".into()));
+        }
+        Ok(())
+    }
+
+    /// A fee bearer named by label must be one of `heirs`.
+    pub fn ensure_bearer_among(&self, heirs: &[HeirRecord]) -> Result<(), BitcoinWalletError> {
+        match &self.fee_bearer {
+            Some(FeeBearer::Heir(label)) if !heirs.iter().any(|heir| heir.label() == *label) => {
+                Err(BitcoinWalletError::InvalidPolicy(format!("fee bearer {label} is not one of the heirs")))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn fee_bearer(&self) -> FeeBearer {
+        self.fee_bearer.clone().unwrap_or_default()
+    }
+
+    pub fn min_confirmations(&self) -> u32 {
+        self.min_confirmations.unwrap_or(MIN_CONFIRMATIONS)
+    }
+
+    /// Locktime for a transaction built while the chain tip is at `tip_height`. A transaction
+    /// locked at height `h` is final from block `h + 1` on, so `h` must not exceed the tip.
+    pub fn lock_time(&self, tip_height: u32) -> Result<LockTime, BitcoinWalletError> {
+        let height = match self.lock_time {
+            Some(lock_time) if lock_time > tip_height => {
+                return Err(BitcoinWalletError::LockTimeNotReached { lock_time, tip_height })
+            }
+            Some(lock_time) => lock_time,
+            None if self.anti_fee_sniping => tip_height,
+            None => return Ok(LockTime::ZERO),
+        };
+        LockTime::from_height(height).map_err(|err| BitcoinWalletError::InvalidPolicy(err.to_string()))
+    }
+
+    /// Input sequence: a final sequence would disable the locktime.
+    pub fn sequence(&self, lock_time: LockTime) -> Sequence {
+        if self.replaceable {
+            Sequence::ENABLE_RBF_NO_LOCKTIME
+        } else if lock_time == LockTime::ZERO {
+            Sequence::MAX
+        } else {
+            Sequence::ENABLE_LOCKTIME_NO_RBF
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locktime_follows_the_tip_unless_fixed() {
+        let policy = TransactionPolicy::default();
+        let lock_time = policy.lock_time(850_000).unwrap();
+        assert_eq!(lock_time, LockTime::from_height(850_000).unwrap());
+        assert_eq!(policy.sequence(lock_time), Sequence::ENABLE_LOCKTIME_NO_RBF);
+
+        let plain = TransactionPolicy {
+            anti_fee_sniping: false,
+            ..TransactionPolicy::default()
+        };
+        assert_eq!(plain.lock_time(850_000).unwrap(), LockTime::ZERO);
+        assert_eq!(plain.sequence(LockTime::ZERO), Sequence::MAX);
+
+        let fixed = TransactionPolicy {
+            lock_time: Some(849_000),
+            replaceable: true,
+            ..TransactionPolicy::default()
+        };
+        assert_eq!(fixed.lock_time(850_000).unwrap(), LockTime::from_height(849_000).unwrap());
+        assert_eq!(fixed.sequence(LockTime::ZERO), Sequence::ENABLE_RBF_NO_LOCKTIME);
+    }
+
+    #[test]
+    fn future_locktime_holds_the_spend() {
+        let policy = TransactionPolicy {
+            lock_time: Some(900_000),
+            ..TransactionPolicy::default()
+        };
+        assert!(policy.validate().is_ok());
+        assert!(matches!(
+            policy.lock_time(899_999),
+            Err(BitcoinWalletError::LockTimeNotReached {
+                lock_time: 900_000,
+                tip_height: 899_999
+            })
+        ));
+        assert!(policy.lock_time(900_000).is_ok());
+
+        let timestamp = TransactionPolicy {
+            lock_time: Some(LOCK_TIME_THRESHOLD),
+            ..TransactionPolicy::default()
+        };
+        assert!(timestamp.validate().is_err());
+
+        let unconfirmed = TransactionPolicy {
+            min_confirmations: Some(0),
+            ..TransactionPolicy::default()
+        };
+        assert!(unconfirmed.validate().is_err());
+        assert_eq!(TransactionPolicy::default().min_confirmations(), MIN_CONFIRMATIONS);
+
+        let unnamed = TransactionPolicy {
+            fee_bearer: Some(FeeBearer::Heir(" ".into())),
+            ..TransactionPolicy::default()
+        };
+        assert!(unnamed.validate().is_err());
+        assert_eq!(TransactionPolicy::default().fee_bearer(), FeeBearer::Proportional);
+    }
+
+    #[test]
+    fn fee_bearer_must_be_an_heir() {
+        let heirs = [HeirRecord {
+            address: "bcrt1qa".into(),
+            weight_bps: 10_000,
+            account: None,
+        }];
+        let policy = |label: &str| TransactionPolicy {
+            fee_bearer: Some(FeeBearer::Heir(label.into())),
+            ..TransactionPolicy::default()
+        };
+        assert!(policy("bcrt1qa").ensure_bearer_among(&heirs).is_ok());
+        assert!(matches!(
+            policy("bcrt1qb").ensure_bearer_among(&heirs),
+            Err(BitcoinWalletError::InvalidPolicy(_))
+        ));
+        assert!(TransactionPolicy::default().ensure_bearer_among(&[]).is_ok());
+    }
+}
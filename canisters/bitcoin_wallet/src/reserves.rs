@@ -0,0 +1,134 @@
+use bitcoin::blockdata::opcodes::all::OP_RETURN;
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::psbt::Psbt;
+use bitcoin::script::Builder;
+use bitcoin::{
+    absolute::LockTime, transaction::Version, Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+    Witness,
+};
+
+const CHALLENGE_PREFIX: &str = "Proof-of-Reserves: ";
+
+/// A vault UTXO claimed by the proof, together with the output it spends.
+pub struct ReserveInput {
+    pub outpoint: OutPoint,
+    pub prevout: TxOut,
+}
+
+/// The BIP-127 commitment input: it spends an output that cannot exist, which makes the
+/// proof transaction unbroadcastable while every signature still commits to the challenge.
+pub fn challenge_input(challenge: &str) -> TxIn {
+    let digest = sha256d::Hash::hash(format!("{CHALLENGE_PREFIX}{challenge}").as_bytes());
+    TxIn {
+        previous_output: OutPoint::new(Txid::from_raw_hash(digest), 0),
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::MAX,
+        witness: Witness::new(),
+    }
+}
+
+/// Builds the unsigned proof transaction: the commitment input first, then every reserve
+/// UTXO, paying the claimed total to a single unspendable output.
+pub fn build_proof_transaction(challenge: &str, inputs: &[ReserveInput]) -> Transaction {
+    let total: u64 = inputs.iter().map(|input| input.prevout.value.to_sat()).sum();
+    let mut tx_inputs = vec![challenge_input(challenge)];
+    tx_inputs.extend(inputs.iter().map(|input| TxIn {
+        previous_output: input.outpoint,
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::MAX,
+        witness: Witness::new(),
+    }));
+    Transaction {
+        version: Version(2),
+        lock_time: LockTime::ZERO,
+        input: tx_inputs,
+        output: vec![TxOut {
+            value: Amount::from_sat(total),
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        }],
+    }
+}
+
+/// Wraps the proof transaction in a PSBT, attaching the spent outputs and the finalized
+/// witnesses of the reserve inputs (index `i` of `witnesses` belongs to `inputs[i]`).
+pub fn assemble_psbt(
+    tx: Transaction,
+    inputs: &[ReserveInput],
+    witnesses: Vec<Witness>,
+) -> Result<Psbt, bitcoin::psbt::Error> {
+    let mut psbt = Psbt::from_unsigned_tx(tx)?;
+    psbt.inputs[0].witness_utxo = Some(TxOut {
+        value: Amount::ZERO,
+        script_pubkey: ScriptBuf::new(),
+    });
+    for ((slot, input), witness) in psbt.inputs.iter_mut().skip(1).zip(inputs).zip(witnesses) {
+        slot.witness_utxo = Some(input.prevout.clone());
+        slot.final_script_witness = Some(witness);
+    }
+    Ok(psbt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::key::PublicKey;
+    use bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+    use bitcoin::sighash::{EcdsaSighashType, SighashCache};
+
+    fn reserve_input(public_key: &PublicKey, vout: u32, value: u64) -> ReserveInput {
+        ReserveInput {
+            outpoint: OutPoint::new(Txid::all_zeros(), vout),
+            prevout: TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash().unwrap()),
+            },
+        }
+    }
+
+    #[test]
+    fn proof_transaction_commits_to_challenge_and_total() {
+        let secp = Secp256k1::new();
+        let public_key = PublicKey::new(SecretKey::from_slice(&[7u8; 32]).unwrap().public_key(&secp));
+        let inputs = vec![reserve_input(&public_key, 0, 40_000), reserve_input(&public_key, 1, 60_000)];
+
+        let tx = build_proof_transaction("audit 2026-Q3", &inputs);
+        assert_eq!(tx.input.len(), 3);
+        assert_eq!(tx.input[0].previous_output, challenge_input("audit 2026-Q3").previous_output);
+        assert_ne!(tx.input[0].previous_output, challenge_input("audit 2026-Q4").previous_output);
+        assert_eq!(tx.output[0].value, Amount::from_sat(100_000));
+        assert!(tx.output[0].script_pubkey.is_op_return());
+    }
+
+    #[test]
+    fn assembled_psbt_carries_verifiable_witnesses() {
+        let secp = Secp256k1::new();
+        let secret = SecretKey::from_slice(&[9u8; 32]).unwrap();
+        let public_key = PublicKey::new(secret.public_key(&secp));
+        let inputs = vec![reserve_input(&public_key, 0, 25_000)];
+        let tx = build_proof_transaction("challenge", &inputs);
+
+        let digest = SighashCache::new(&tx)
+            .p2wpkh_signature_hash(1, &inputs[0].prevout.script_pubkey, Amount::from_sat(25_000), EcdsaSighashType::All)
+            .unwrap();
+        let signature = secp.sign_ecdsa(&Message::from_digest(digest.to_byte_array()), &secret);
+        let mut der = signature.serialize_der().to_vec();
+        der.push(EcdsaSighashType::All as u8);
+        let mut witness = Witness::new();
+        witness.push(der);
+        witness.push(public_key.to_bytes());
+
+        let psbt = assemble_psbt(tx, &inputs, vec![witness]).unwrap();
+        let decoded = Psbt::deserialize(&psbt.serialize()).unwrap();
+        assert_eq!(decoded.inputs[1].witness_utxo.as_ref().unwrap().value, Amount::from_sat(25_000));
+
+        let stored = decoded.inputs[1].final_script_witness.as_ref().unwrap();
+        let (_, der) = stored.nth(0).unwrap().split_last().unwrap();
+        let signature = bitcoin::secp256k1::ecdsa::Signature::from_der(der).unwrap();
+        let recomputed = SighashCache::new(&decoded.unsigned_tx)
+            .p2wpkh_signature_hash(1, &inputs[0].prevout.script_pubkey, Amount::from_sat(25_000), EcdsaSighashType::All)
+            .unwrap();
+        assert!(secp
+            .verify_ecdsa(&Message::from_digest(recomputed.to_byte_array()), &signature, &public_key.inner)
+            .is_ok());
+    }
+}
@@ -0,0 +1,59 @@
+//! The canister's single global timer, pointed at whichever vesting tranche, grace-period
+//! execution or ckBTC mint attempt is due first.
+//! It is cleared by upgrades, so `post_upgrade` re-arms it from the persisted schedules.
+
+use crate::{access, memory};
+use ic_cdk::api;
+use std::cell::Cell;
+
+/// How often a paused canister checks back; `unpause` re-arms the timer straight away.
+const PAUSED_POLL_NANOS: u64 = 60 * 60 * 1_000_000_000;
+
+thread_local! {
+    static RUNNING: Cell<bool> = const { Cell::new(false) };
+}
+
+pub fn rearm() {
+    let next = [memory::next_tranche_at(), memory::next_execution_at(), memory::next_mint_attempt_at()]
+        .into_iter()
+        .flatten()
+        .min();
+    // Zero deactivates the timer; a due time in the past fires as soon as possible.
+    api::global_timer_set(next.map_or(0, |at| at.max(1)));
+}
+
+/// Held while due payouts run, so a timer fired mid-run does not pay anything twice.
+/// Released on drop, including when a trap cancels the run.
+struct RunGuard;
+
+impl RunGuard {
+    fn acquire() -> Option<Self> {
+        (!RUNNING.replace(true)).then_some(RunGuard)
+    }
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        RUNNING.set(false);
+    }
+}
+
+async fn run() {
+    if memory::with_state(access::is_paused) {
+        api::global_timer_set(api::time().saturating_add(PAUSED_POLL_NANOS));
+        return;
+    }
+    let Some(guard) = RunGuard::acquire() else {
+        return;
+    };
+    crate::execute_due_inheritances().await;
+    crate::pay_due_tranches().await;
+    crate::retry_pending_mints().await;
+    drop(guard);
+    rearm();
+}
+
+#[unsafe(export_name = "canister_global_timer")]
+extern "C" fn canister_global_timer() {
+    ic_cdk::futures::in_executor_context(|| ic_cdk::futures::spawn(run()));
+}
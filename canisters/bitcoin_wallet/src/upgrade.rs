@@ -0,0 +1,123 @@
+use crate::access;
+use crate::{VaultId, VaultWallet, VaultWalletState};
+use candid::{de::IDLDeserialize, CandidType, Principal};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Versioned envelope for the wallet configuration. The newest variant wraps the live state
+/// type; a schema change freezes its current shape as `StateVn` and adds a variant plus a
+/// migration.
+#[derive(CandidType, Deserialize)]
+pub enum VersionedState {
+    V1(VaultWalletState),
+}
+
+/// Unversioned heap state the first release wrote with `stable_save` before each upgrade.
+#[derive(CandidType, Deserialize)]
+pub struct BaselineState {
+    wallets: BTreeMap<VaultId, VaultWallet>,
+    vault_manager: Option<Principal>,
+}
+
+/// A `stable_save` snapshot split into what now lives in the config cell and the wallet map.
+pub struct LegacySnapshot {
+    pub state: VaultWalletState,
+    pub wallets: BTreeMap<VaultId, VaultWallet>,
+}
+
+/// Before roles existed the single `vault_manager` was the only authority, so it keeps the
+/// vault manager role. Everything the first release lacked starts at its default: the key
+/// allowlist of the network it was built for, no ckBTC minter or guardian manager, and no
+/// grace period until an admin sets one.
+pub fn migrate_baseline(legacy: BaselineState) -> LegacySnapshot {
+    let mut state = VaultWalletState {
+        vault_manager: legacy.vault_manager,
+        ..VaultWalletState::default()
+    };
+    if let Some(manager) = legacy.vault_manager {
+        access::grant_role(&mut state, manager, access::Role::VaultManager);
+    }
+    LegacySnapshot {
+        state,
+        wallets: legacy.wallets,
+    }
+}
+
+/// Decodes a `stable_save` snapshot and migrates it forward. Anything that does not decode is
+/// an error: the caller must abort the upgrade rather than start empty.
+pub fn decode_snapshot(bytes: &[u8]) -> Result<LegacySnapshot, String> {
+    let mut de = IDLDeserialize::new(bytes).map_err(|err| err.to_string())?;
+    let legacy = de
+        .get_value::<BaselineState>()
+        .map_err(|err| format!("stable state is not a first-release snapshot: {err}"))?;
+    Ok(migrate_baseline(legacy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_cdk::bitcoin_canister::Network;
+
+    /// Wallet record as persisted by the first release, before vault owners were tracked.
+    #[derive(CandidType)]
+    struct WalletV0 {
+        key_id: String,
+        derivation_path: Vec<Vec<u8>>,
+        address: String,
+        script_pub_key: Vec<u8>,
+        public_key: Vec<u8>,
+        network: Network,
+    }
+
+    #[derive(CandidType)]
+    struct StateV0 {
+        wallets: BTreeMap<VaultId, WalletV0>,
+        vault_manager: Option<Principal>,
+    }
+
+    /// Mimics `stable_bytes()`, which returns whole pages padded with zeros.
+    fn stable_image<T: candid::utils::ArgumentEncoder>(args: T) -> Vec<u8> {
+        let mut bytes = candid::encode_args(args).unwrap();
+        bytes.resize(bytes.len() + 512, 0);
+        bytes
+    }
+
+    fn manager() -> Principal {
+        Principal::from_slice(&[7])
+    }
+
+    #[test]
+    fn first_release_snapshot_migrates_forward() {
+        let mut wallets = BTreeMap::new();
+        wallets.insert(
+            42,
+            WalletV0 {
+                key_id: "test_key_1".into(),
+                derivation_path: vec![42u64.to_be_bytes().to_vec()],
+                address: "tb1qexample".into(),
+                script_pub_key: vec![0, 20],
+                public_key: vec![2; 33],
+                network: Network::Testnet,
+            },
+        );
+        let bytes = stable_image((StateV0 {
+            wallets,
+            vault_manager: Some(manager()),
+        },));
+
+        let snapshot = decode_snapshot(&bytes).expect("legacy state decodes");
+        assert_eq!(snapshot.wallets[&42].address, "tb1qexample");
+        assert_eq!(snapshot.wallets[&42].owner, None);
+        assert!(access::ensure_vault_manager(&snapshot.state, manager()).is_ok());
+        assert_eq!(snapshot.state.keys.network, Network::Testnet);
+        assert!(snapshot.state.keys.ensure_allowed("test_key_1").is_ok());
+        assert_eq!(snapshot.state.grace_period_secs, 0);
+    }
+
+    #[test]
+    fn undecodable_state_is_an_error() {
+        let bytes = stable_image((String::from("not a wallet state"),));
+        assert!(decode_snapshot(&bytes).is_err());
+        assert!(decode_snapshot(&[0u8; 64]).is_err());
+    }
+}
@@ -0,0 +1,355 @@
+use crate::memo::InheritanceMemo;
+use crate::{BitcoinWalletError, HeirRecord, VaultId, BASIS_POINTS};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+/// Delay before a payout that failed (fees unavailable, nothing confirmed yet) is first
+/// retried; it doubles with each further failure.
+pub const RETRY_DELAY_NANOS: u64 = 60 * 60 * 1_000_000_000;
+/// Failed attempts after which a payout stops retrying. By then it has kept failing for about
+/// five days and needs someone to look at it; it can be scheduled again once fixed.
+pub const MAX_RETRY_ATTEMPTS: u32 = 8;
+
+/// Delay after failed attempt number `attempts`, counting from one.
+pub fn retry_delay(attempts: u32) -> u64 {
+    RETRY_DELAY_NANOS << attempts.saturating_sub(1).min(MAX_RETRY_ATTEMPTS)
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct TrancheSpec {
+    /// IC time in nanoseconds.
+    #[serde(rename = "dueAt")]
+    pub due_at: u64,
+    /// Part of the vault, in basis points of the whole schedule.
+    #[serde(rename = "shareBps")]
+    pub share_bps: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ScheduleVestingArgs {
+    #[serde(rename = "vaultId")]
+    pub vault_id: VaultId,
+    pub heirs: Vec<HeirRecord>,
+    pub tranches: Vec<TrancheSpec>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub enum TrancheStatus {
+    Pending,
+    Paid {
+        #[serde(rename = "txId")]
+        tx_id: String,
+        #[serde(rename = "paidAt")]
+        paid_at: u64,
+        /// Commitment published in the tranche's OP_RETURN output.
+        memo: Option<InheritanceMemo>,
+    },
+    /// Stopped on an error retrying cannot fix; the tranches behind it are held back too.
+    Failed {
+        error: String,
+        #[serde(rename = "failedAt")]
+        failed_at: u64,
+    },
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct Tranche {
+    #[serde(rename = "dueAt")]
+    pub due_at: u64,
+    #[serde(rename = "shareBps")]
+    pub share_bps: u64,
+    pub status: TrancheStatus,
+    /// Set after a failed attempt; the tranche is retried from then on.
+    #[serde(rename = "retryAt")]
+    pub retry_at: Option<u64>,
+    #[serde(rename = "lastError")]
+    pub last_error: Option<String>,
+    /// Failed attempts so far; `None` until the first one.
+    pub attempts: Option<u32>,
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct VestingSchedule {
+    pub heirs: Vec<HeirRecord>,
+    /// Ordered by due time.
+    pub tranches: Vec<Tranche>,
+    #[serde(rename = "createdAt")]
+    pub created_at: u64,
+    /// Guardians who voted to cancel the tranches not yet paid; `None` on schedules stored
+    /// before they could be cancelled.
+    #[serde(rename = "cancelVotes")]
+    pub cancel_votes: Option<Vec<Principal>>,
+    #[serde(rename = "cancelledAt")]
+    pub cancelled_at: Option<u64>,
+}
+
+impl VestingSchedule {
+    /// Tranches due before `earliest_due_at` are refused, so every tranche leaves the owner and
+    /// guardians the grace period to cancel it.
+    pub fn new(
+        mut specs: Vec<TrancheSpec>,
+        heirs: Vec<HeirRecord>,
+        created_at: u64,
+        earliest_due_at: u64,
+    ) -> Result<Self, BitcoinWalletError> {
+        let total: u64 = specs.iter().map(|spec| spec.share_bps).sum();
+        if specs.is_empty() || total != BASIS_POINTS || specs.iter().any(|spec| spec.share_bps == 0) {
+            return Err(BitcoinWalletError::InvalidSchedule);
+        }
+        specs.sort_by_key(|spec| spec.due_at);
+        if specs[0].due_at < earliest_due_at {
+            return Err(BitcoinWalletError::TrancheTooEarly {
+                due_at: specs[0].due_at,
+                earliest: earliest_due_at,
+            });
+        }
+        let tranches = specs
+            .into_iter()
+            .map(|spec| Tranche {
+                due_at: spec.due_at,
+                share_bps: spec.share_bps,
+                status: TrancheStatus::Pending,
+                retry_at: None,
+                last_error: None,
+                attempts: None,
+            })
+            .collect();
+        Ok(Self {
+            heirs,
+            tranches,
+            created_at,
+            cancel_votes: None,
+            cancelled_at: None,
+        })
+    }
+
+    pub fn started(&self) -> bool {
+        self.tranches.iter().any(|tranche| tranche.status != TrancheStatus::Pending)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled_at.is_some()
+    }
+
+    /// Whether a tranche failed in a way retrying cannot fix, halting the schedule.
+    pub fn is_halted(&self) -> bool {
+        self.tranches.iter().any(|tranche| matches!(tranche.status, TrancheStatus::Failed { .. }))
+    }
+
+    /// The unpaid tranches can be cancelled until the next one falls due.
+    pub fn window_open(&self, now: u64) -> bool {
+        !self.is_cancelled() && self.pending().next().is_some_and(|(_, tranche)| now < tranche.due_at)
+    }
+
+    pub fn cancel(&mut self, now: u64) {
+        self.cancelled_at = Some(now);
+    }
+
+    /// Counts `guardian`'s vote once and cancels when `threshold` guardians have voted.
+    pub fn vote_cancel(&mut self, guardian: Principal, threshold: u64, now: u64) {
+        let votes = self.cancel_votes.get_or_insert_with(Vec::new);
+        if !votes.contains(&guardian) {
+            votes.push(guardian);
+        }
+        if votes.len() as u64 >= threshold.max(1) {
+            self.cancel(now);
+        }
+    }
+
+    pub fn cancel_vote_count(&self) -> usize {
+        self.cancel_votes.as_ref().map_or(0, Vec::len)
+    }
+
+    /// When the next tranche should run. Tranches are paid strictly in order, so a failed
+    /// one holds back those behind it until its retry, or for good once it has halted.
+    pub fn next_run_at(&self) -> Option<u64> {
+        if self.is_cancelled() || self.is_halted() {
+            return None;
+        }
+        let (_, tranche) = self.pending().next()?;
+        Some(tranche.retry_at.unwrap_or(tranche.due_at))
+    }
+
+    pub fn due_tranche(&self, now: u64) -> Option<usize> {
+        let (index, _) = self.pending().next()?;
+        (self.next_run_at()? <= now).then_some(index)
+    }
+
+    /// Basis points still to be paid out, including the tranche about to run. Each tranche
+    /// pays `share_bps / remaining_bps` of what the vault holds at that moment, so the last
+    /// one empties it.
+    pub fn remaining_bps(&self) -> u64 {
+        self.pending().map(|(_, tranche)| tranche.share_bps).sum()
+    }
+
+    pub fn mark_paid(&mut self, index: usize, tx_id: String, memo: Option<InheritanceMemo>, paid_at: u64) {
+        let tranche = &mut self.tranches[index];
+        tranche.status = TrancheStatus::Paid { tx_id, paid_at, memo };
+        tranche.retry_at = None;
+        tranche.last_error = None;
+    }
+
+    /// Records a failed attempt and backs off. After `MAX_RETRY_ATTEMPTS` the tranche halts
+    /// instead, holding back the schedule, and this returns true.
+    pub fn mark_failed(&mut self, index: usize, error: String, now: u64) -> bool {
+        let tranche = &mut self.tranches[index];
+        let attempts = tranche.attempts.unwrap_or_default() + 1;
+        tranche.attempts = Some(attempts);
+        if attempts >= MAX_RETRY_ATTEMPTS {
+            self.mark_halted(index, format!("gave up after {attempts} attempts: {error}"), now);
+            return true;
+        }
+        tranche.retry_at = Some(now + retry_delay(attempts));
+        tranche.last_error = Some(error);
+        false
+    }
+
+    pub fn mark_halted(&mut self, index: usize, error: String, now: u64) {
+        let tranche = &mut self.tranches[index];
+        tranche.status = TrancheStatus::Failed {
+            error: error.clone(),
+            failed_at: now,
+        };
+        tranche.retry_at = None;
+        tranche.last_error = Some(error);
+    }
+
+    fn pending(&self) -> impl Iterator<Item = (usize, &Tranche)> {
+        self.tranches
+            .iter()
+            .enumerate()
+            .filter(|(_, tranche)| tranche.status == TrancheStatus::Pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::{Decode, Encode};
+
+    fn spec(due_at: u64, share_bps: u64) -> TrancheSpec {
+        TrancheSpec { due_at, share_bps }
+    }
+
+    #[test]
+    fn shares_must_cover_the_whole_vault() {
+        assert!(VestingSchedule::new(vec![], vec![], 0, 0).is_err());
+        assert!(VestingSchedule::new(vec![spec(1, 5_000), spec(2, 4_000)], vec![], 0, 0).is_err());
+        assert!(VestingSchedule::new(vec![spec(1, 10_000), spec(2, 0)], vec![], 0, 0).is_err());
+        assert!(VestingSchedule::new(vec![spec(2, 2_500), spec(1, 7_500)], vec![], 0, 0).is_ok());
+    }
+
+    #[test]
+    fn tranches_leave_the_grace_period_to_cancel() {
+        assert!(matches!(
+            VestingSchedule::new(vec![spec(200, 5_000), spec(99, 5_000)], vec![], 0, 100),
+            Err(BitcoinWalletError::TrancheTooEarly { due_at: 99, earliest: 100 })
+        ));
+        assert!(VestingSchedule::new(vec![spec(200, 5_000), spec(100, 5_000)], vec![], 0, 100).is_ok());
+    }
+
+    #[test]
+    fn guardian_quorum_cancels_between_tranches() {
+        let mut schedule = VestingSchedule::new(vec![spec(100, 5_000), spec(200, 5_000)], vec![], 0, 0).unwrap();
+        assert!(schedule.window_open(99));
+        assert!(!schedule.window_open(100));
+
+        schedule.mark_paid(0, "tx".into(), None, 100);
+        assert!(schedule.window_open(150));
+        let guardian = Principal::from_slice(&[1]);
+        schedule.vote_cancel(guardian, 2, 150);
+        schedule.vote_cancel(guardian, 2, 151);
+        assert!(!schedule.is_cancelled());
+        schedule.vote_cancel(Principal::from_slice(&[2]), 2, 152);
+        assert_eq!(schedule.cancelled_at, Some(152));
+        assert_eq!(schedule.next_run_at(), None);
+        assert!(!schedule.window_open(152));
+    }
+
+    #[test]
+    fn schedules_stored_before_cancellation_still_decode() {
+        #[derive(CandidType, Serialize)]
+        struct StoredSchedule {
+            heirs: Vec<HeirRecord>,
+            tranches: Vec<Tranche>,
+            #[serde(rename = "createdAt")]
+            created_at: u64,
+        }
+        let stored = StoredSchedule {
+            heirs: vec![],
+            tranches: VestingSchedule::new(vec![spec(100, 10_000)], vec![], 0, 0).unwrap().tranches,
+            created_at: 7,
+        };
+        let bytes = Encode!(&stored).unwrap();
+        let schedule = Decode!(&bytes, VestingSchedule).unwrap();
+        assert_eq!(schedule.created_at, 7);
+        assert_eq!(schedule.cancel_vote_count(), 0);
+        assert!(!schedule.is_cancelled());
+    }
+
+    #[test]
+    fn halted_tranche_stops_the_schedule() {
+        let mut schedule = VestingSchedule::new(vec![spec(100, 5_000), spec(200, 5_000)], vec![], 0, 0).unwrap();
+        schedule.mark_halted(0, "dust".into(), 100);
+        assert!(schedule.is_halted());
+        assert_eq!(schedule.next_run_at(), None);
+        assert_eq!(schedule.due_tranche(300), None);
+        assert_eq!(schedule.tranches[0].retry_at, None);
+    }
+
+    #[test]
+    fn failing_tranches_back_off_and_then_halt() {
+        let mut schedule = VestingSchedule::new(vec![spec(100, 10_000)], vec![], 0, 0).unwrap();
+        assert!(!schedule.mark_failed(0, "no utxos".into(), 100));
+        assert!(!schedule.mark_failed(0, "no utxos".into(), 200));
+        assert_eq!(schedule.next_run_at(), Some(200 + 2 * RETRY_DELAY_NANOS));
+        for attempt in 3..MAX_RETRY_ATTEMPTS {
+            assert!(!schedule.mark_failed(0, "no utxos".into(), 300));
+            assert_eq!(schedule.tranches[0].retry_at, Some(300 + retry_delay(attempt)));
+        }
+        assert!(schedule.mark_failed(0, "no utxos".into(), 400));
+        assert!(schedule.is_halted());
+        assert_eq!(schedule.next_run_at(), None);
+        assert_eq!(
+            schedule.tranches[0].last_error.as_deref(),
+            Some("gave up after 8 attempts: no utxos")
+        );
+    }
+
+    #[test]
+    fn tranches_paid_before_memos_still_decode() {
+        #[derive(CandidType, Serialize)]
+        enum StoredStatus {
+            Paid {
+                #[serde(rename = "txId")]
+                tx_id: String,
+                #[serde(rename = "paidAt")]
+                paid_at: u64,
+            },
+        }
+        let bytes = Encode!(&StoredStatus::Paid { tx_id: "tx".into(), paid_at: 9 }).unwrap();
+        assert_eq!(
+            Decode!(&bytes, TrancheStatus).unwrap(),
+            TrancheStatus::Paid { tx_id: "tx".into(), paid_at: 9, memo: None }
+        );
+    }
+
+    #[test]
+    fn tranches_run_in_order_with_retries() {
+        let mut schedule =
+            VestingSchedule::new(vec![spec(300, 2_500), spec(100, 2_500), spec(200, 5_000)], vec![], 0, 0).unwrap();
+        assert_eq!(schedule.next_run_at(), Some(100));
+        assert_eq!(schedule.due_tranche(99), None);
+        assert_eq!(schedule.due_tranche(100), Some(0));
+        assert_eq!(schedule.remaining_bps(), 10_000);
+
+        schedule.mark_failed(0, "no utxos".into(), 100);
+        assert_eq!(schedule.due_tranche(250), None);
+        assert_eq!(schedule.next_run_at(), Some(100 + RETRY_DELAY_NANOS));
+
+        schedule.mark_paid(0, "tx".into(), None, 150);
+        assert!(schedule.started());
+        assert_eq!(schedule.due_tranche(250), Some(1));
+        assert_eq!(schedule.remaining_bps(), 7_500);
+    }
+}
@@ -0,0 +1,340 @@
+//! Local stand-in for the ckBTC minter and ledger. It hands out deterministic regtest deposit
+//! addresses per ICRC-1 account and "mints" by crediting an in-memory balance for every new
+//! UTXO the Bitcoin canister reports. It also answers the ICRC-1 transfer calls the wallet
+//! makes when paying token heirs, and `mint` seeds balances for tests. It keeps no block
+//! history: `get_transactions` reports the log length and nothing else. Deposit scripts are
+//! anyone-can-spend: never deploy this outside a local replica.
+
+use bitcoin::blockdata::opcodes::all::{OP_DROP, OP_PUSHNUM_1};
+use bitcoin::script::{Builder, PushBytesBuf};
+use bitcoin::{Address, Network as BtcNetwork};
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::bitcoin_canister::{bitcoin_get_utxos, GetUtxosRequest, Network, Outpoint, Utxo, UtxosFilter};
+use ic_cdk_macros::{init, query, update};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[cfg(target_arch = "wasm32")]
+mod wasm_rand_shim {
+    use getrandom::Error;
+
+    getrandom::register_custom_getrandom!(unavailable);
+
+    fn unavailable(_dest: &mut [u8]) -> Result<(), Error> {
+        Err(Error::UNSUPPORTED)
+    }
+}
+
+const DEFAULT_MIN_CONFIRMATIONS: u32 = 1;
+const TRANSFER_FEE: u64 = 10;
+
+thread_local! {
+    static STATE: RefCell<StubState> = RefCell::new(StubState::default());
+}
+
+#[derive(Default)]
+struct StubState {
+    min_confirmations: u32,
+    balances: BTreeMap<(Principal, Vec<u8>), u64>,
+    seen: BTreeSet<(Vec<u8>, u32)>,
+    next_block_index: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct InitArgs {
+    pub min_confirmations: Option<u32>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct AccountArg {
+    pub owner: Option<Principal>,
+    pub subaccount: Option<serde_bytes::ByteBuf>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<serde_bytes::ByteBuf>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum UtxoStatus {
+    Minted {
+        block_index: u64,
+        minted_amount: u64,
+        utxo: Utxo,
+    },
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct NoNewUtxos {
+    pub current_confirmations: Option<u32>,
+    pub required_confirmations: u32,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum UpdateBalanceError {
+    GenericError { error_code: u64, error_message: String },
+    NoNewUtxos(NoNewUtxos),
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct TransferArg {
+    pub from_subaccount: Option<serde_bytes::ByteBuf>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<serde_bytes::ByteBuf>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    InsufficientFunds { balance: Nat },
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct GetTransactionsRequest {
+    pub start: Nat,
+    pub length: Nat,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Transaction {
+    pub kind: String,
+    pub timestamp: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct TransactionRange {
+    pub transactions: Vec<Transaction>,
+}
+
+candid::define_function!(pub ArchiveCallback : (GetTransactionsRequest) -> (TransactionRange) query);
+
+#[derive(CandidType, Deserialize)]
+pub struct ArchivedRange {
+    pub start: Nat,
+    pub length: Nat,
+    pub callback: ArchiveCallback,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct GetTransactionsResponse {
+    pub log_length: Nat,
+    pub first_index: Nat,
+    pub transactions: Vec<Transaction>,
+    pub archived_transactions: Vec<ArchivedRange>,
+}
+
+#[init]
+fn init(args: Option<InitArgs>) {
+    let min_confirmations = args
+        .and_then(|args| args.min_confirmations)
+        .unwrap_or(DEFAULT_MIN_CONFIRMATIONS);
+    STATE.with(|state| state.borrow_mut().min_confirmations = min_confirmations);
+}
+
+#[update]
+fn get_btc_address(arg: AccountArg) -> String {
+    let (owner, subaccount) = resolve(arg);
+    deposit_address(owner, &subaccount).to_string()
+}
+
+#[update]
+async fn update_balance(arg: AccountArg) -> Result<Vec<UtxoStatus>, UpdateBalanceError> {
+    let (owner, subaccount) = resolve(arg);
+    let required_confirmations = STATE.with(|state| state.borrow().min_confirmations);
+    let response = bitcoin_get_utxos(&GetUtxosRequest {
+        network: Network::Regtest,
+        address: deposit_address(owner, &subaccount).to_string(),
+        filter: Some(UtxosFilter::MinConfirmations(required_confirmations)),
+    })
+    .await
+    .map_err(|err| UpdateBalanceError::GenericError {
+        error_code: 1,
+        error_message: format!("bitcoin_get_utxos failed: {err:?}"),
+    })?;
+
+    let minted = STATE.with(|state| credit_new_utxos(&mut state.borrow_mut(), owner, subaccount, response.utxos));
+    if minted.is_empty() {
+        return Err(UpdateBalanceError::NoNewUtxos(NoNewUtxos {
+            current_confirmations: None,
+            required_confirmations,
+        }));
+    }
+    Ok(minted)
+}
+
+#[query]
+fn icrc1_balance_of(account: Account) -> Nat {
+    let key = (account.owner, subaccount_bytes(account.subaccount));
+    STATE.with(|state| Nat::from(state.borrow().balances.get(&key).copied().unwrap_or_default()))
+}
+
+#[query]
+fn icrc1_fee() -> Nat {
+    Nat::from(TRANSFER_FEE)
+}
+
+#[update]
+fn icrc1_transfer(arg: TransferArg) -> Result<Nat, TransferError> {
+    let from = (ic_cdk::api::msg_caller(), subaccount_bytes(arg.from_subaccount));
+    let to = (arg.to.owner, subaccount_bytes(arg.to.subaccount));
+    let amount = to_u64(&arg.amount)?;
+    if arg.fee.is_some_and(|fee| fee != TRANSFER_FEE) {
+        return Err(TransferError::BadFee {
+            expected_fee: Nat::from(TRANSFER_FEE),
+        });
+    }
+    STATE.with(|state| transfer(&mut state.borrow_mut(), from, to, amount)).map(Nat::from)
+}
+
+/// Block indices start at 1, so the log holds `next_block_index + 1` blocks.
+#[query]
+fn get_transactions(_request: GetTransactionsRequest) -> GetTransactionsResponse {
+    let log_length = Nat::from(STATE.with(|state| state.borrow().next_block_index) + 1);
+    GetTransactionsResponse {
+        first_index: log_length.clone(),
+        log_length,
+        transactions: Vec::new(),
+        archived_transactions: Vec::new(),
+    }
+}
+
+/// Credits `amount` to `account` out of thin air.
+#[update]
+fn mint(account: Account, amount: u64) -> Nat {
+    let key = (account.owner, subaccount_bytes(account.subaccount));
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        *state.balances.entry(key).or_default() += amount;
+        state.next_block_index += 1;
+        Nat::from(state.next_block_index)
+    })
+}
+
+fn to_u64(amount: &Nat) -> Result<u64, TransferError> {
+    u64::try_from(amount.0.clone()).map_err(|_| TransferError::GenericError {
+        error_code: Nat::from(1u64),
+        message: "amount exceeds u64".to_string(),
+    })
+}
+
+fn transfer(
+    state: &mut StubState,
+    from: (Principal, Vec<u8>),
+    to: (Principal, Vec<u8>),
+    amount: u64,
+) -> Result<u64, TransferError> {
+    let balance = state.balances.get(&from).copied().unwrap_or_default();
+    let debit = amount.saturating_add(TRANSFER_FEE);
+    if balance < debit {
+        return Err(TransferError::InsufficientFunds {
+            balance: Nat::from(balance),
+        });
+    }
+    state.balances.insert(from, balance - debit);
+    *state.balances.entry(to).or_default() += amount;
+    state.next_block_index += 1;
+    Ok(state.next_block_index)
+}
+
+fn resolve(arg: AccountArg) -> (Principal, Vec<u8>) {
+    (
+        arg.owner.unwrap_or_else(ic_cdk::api::msg_caller),
+        subaccount_bytes(arg.subaccount),
+    )
+}
+
+fn subaccount_bytes(subaccount: Option<serde_bytes::ByteBuf>) -> Vec<u8> {
+    subaccount.map(|bytes| bytes.into_vec()).unwrap_or_else(|| vec![0; 32])
+}
+
+fn deposit_address(owner: Principal, subaccount: &[u8]) -> Address {
+    let mut hasher = Sha256::new();
+    hasher.update(owner.as_slice());
+    hasher.update(subaccount);
+    let tag = PushBytesBuf::from(<[u8; 32]>::from(hasher.finalize()));
+    let script = Builder::new()
+        .push_slice(tag)
+        .push_opcode(OP_DROP)
+        .push_opcode(OP_PUSHNUM_1)
+        .into_script();
+    Address::p2wsh(&script, BtcNetwork::Regtest)
+}
+
+fn credit_new_utxos(state: &mut StubState, owner: Principal, subaccount: Vec<u8>, utxos: Vec<Utxo>) -> Vec<UtxoStatus> {
+    let mut minted = Vec::new();
+    for utxo in utxos {
+        let Outpoint { txid, vout } = utxo.outpoint.clone();
+        if !state.seen.insert((txid, vout)) {
+            continue;
+        }
+        *state.balances.entry((owner, subaccount.clone())).or_default() += utxo.value;
+        state.next_block_index += 1;
+        minted.push(UtxoStatus::Minted {
+            block_index: state.next_block_index,
+            minted_amount: utxo.value,
+            utxo,
+        });
+    }
+    minted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utxo(vout: u32, value: u64) -> Utxo {
+        Utxo {
+            outpoint: Outpoint {
+                txid: vec![7; 32],
+                vout,
+            },
+            value,
+            height: 1,
+        }
+    }
+
+    #[test]
+    fn deposit_addresses_are_per_account() {
+        let owner = Principal::from_slice(&[1]);
+        let first = deposit_address(owner, &[0; 32]);
+        assert_eq!(first, deposit_address(owner, &[0; 32]));
+        assert_ne!(first, deposit_address(owner, &[1; 32]));
+        assert!(first.to_string().starts_with("bcrt1q"));
+    }
+
+    #[test]
+    fn utxos_are_credited_once() {
+        let mut state = StubState::default();
+        let owner = Principal::from_slice(&[1]);
+        let minted = credit_new_utxos(&mut state, owner, vec![0; 32], vec![utxo(0, 5_000), utxo(1, 7_000)]);
+        assert_eq!(minted.len(), 2);
+        let again = credit_new_utxos(&mut state, owner, vec![0; 32], vec![utxo(0, 5_000)]);
+        assert!(again.is_empty());
+        assert_eq!(state.balances[&(owner, vec![0; 32])], 12_000);
+    }
+
+    #[test]
+    fn transfers_debit_amount_plus_fee() {
+        let mut state = StubState::default();
+        let from = (Principal::from_slice(&[1]), vec![0; 32]);
+        let to = (Principal::from_slice(&[2]), vec![0; 32]);
+        state.balances.insert(from.clone(), 1_000);
+        assert!(transfer(&mut state, from.clone(), to.clone(), 500).is_ok());
+        assert_eq!(state.balances[&from], 1_000 - 500 - TRANSFER_FEE);
+        assert_eq!(state.balances[&to], 500);
+        assert_eq!(
+            transfer(&mut state, from.clone(), to, 490),
+            Err(TransferError::InsufficientFunds {
+                balance: Nat::from(490u64)
+            })
+        );
+    }
+}
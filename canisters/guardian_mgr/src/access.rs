@@ -0,0 +1,71 @@
+use crate::{with_state, GuardianError, GuardianManagerState};
+use candid::{CandidType, Principal};
+pub use canister_common::access::*;
+use ic_cdk::api;
+use serde::Deserialize;
+
+#[derive(Default, CandidType, Deserialize)]
+pub struct InitArgs {
+    pub vault_manager: Option<Principal>,
+}
+
+impl AccessState for GuardianManagerState {
+    fn access(&self) -> &AccessControl {
+        &self.access
+    }
+
+    fn access_mut(&mut self) -> &mut AccessControl {
+        &mut self.access
+    }
+
+    fn vault_manager_mut(&mut self) -> &mut Option<Principal> {
+        &mut self.vault_manager
+    }
+
+    fn log_rotation(&mut self, rotation: ManagerRotation) {
+        crate::memory::append_rotation(rotation);
+    }
+}
+
+impl From<AccessError> for GuardianError {
+    fn from(value: AccessError) -> Self {
+        match value {
+            AccessError::ManagerNotConfigured => GuardianError::ManagerNotConfigured,
+            AccessError::ManagerRoleReserved => GuardianError::ManagerRoleReserved,
+            AccessError::Paused => GuardianError::Paused,
+            AccessError::Unauthorized(caller) => GuardianError::Unauthorized(caller),
+        }
+    }
+}
+
+fn reject(err: AccessError) -> String {
+    GuardianError::from(err).into()
+}
+
+pub fn guard_admin() -> Result<(), String> {
+    let caller = api::msg_caller();
+    with_state(|state| ensure_admin(state, caller)).map_err(reject)
+}
+
+pub fn guard_auditor() -> Result<(), String> {
+    let caller = api::msg_caller();
+    with_state(|state| ensure_auditor(state, caller)).map_err(reject)
+}
+
+pub fn guard_pauser() -> Result<(), String> {
+    let caller = api::msg_caller();
+    with_state(|state| ensure_pauser(state, caller)).map_err(reject)
+}
+
+pub fn guard_vault_manager() -> Result<(), String> {
+    let caller = api::msg_caller();
+    with_state(|state| {
+        ensure_not_paused(state)?;
+        ensure_vault_manager(state, caller)
+    })
+    .map_err(reject)
+}
+
+pub fn guard_not_paused() -> Result<(), String> {
+    with_state(ensure_not_paused).map_err(reject)
+}
@@ -1,16 +1,22 @@
 use candid::{CandidType, Principal};
 use ic_cdk::api::{self, time};
 use ic_cdk::management_canister::{raw_rand, VetKDCurve, VetKDDeriveKeyArgs, VetKDKeyId, VetKDPublicKeyArgs};
-use ic_cdk::storage;
-use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query, update};
+use ic_cdk_macros::{init, post_upgrade, query, update};
 use ic_vetkeys::{DerivedKeyMaterial, DerivedPublicKey, EncryptedVetKey, TransportSecretKey, VetKey};
 use rand_chacha::{rand_core::SeedableRng, ChaCha20Rng};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::cell::RefCell;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::BTreeSet;
 use thiserror::Error;
 
+mod access;
+mod memory;
+mod metrics;
+mod upgrade;
+
+use access::{InitArgs, ManagerRotation, Role, RoleAssignment};
+use memory::{mutate_state, with_state};
+
 type VaultId = u64;
 
 const MIN_GUARDIANS: usize = 3;
@@ -21,6 +27,7 @@ const SHARE_ENCRYPTION_DOMAIN: &str = "thresholdvault.share";
 const RNG_DOMAIN: &[u8] = b"thresholdvault.guardian.rng";
 const VETKEY_NAME: &str = "key_1";
 const MAX_SHARE_BYTES: usize = 4096;
+const WASM_PAGE_SIZE: u64 = 65_536;
 
 #[cfg(target_arch = "wasm32")]
 mod wasm_rand_shim {
@@ -33,14 +40,10 @@ mod wasm_rand_shim {
     }
 }
 
-thread_local! {
-    static STATE: RefCell<GuardianManagerState> = RefCell::new(GuardianManagerState::default());
-}
-
 #[derive(Clone, Default, CandidType, Deserialize, Serialize)]
 struct GuardianManagerState {
-    vaults: BTreeMap<VaultId, VaultGuardianSet>,
     vault_manager: Option<Principal>,
+    access: access::AccessControl,
 }
 
 #[derive(Clone, CandidType, Deserialize, Serialize)]
@@ -130,6 +133,13 @@ pub struct GuardianSubmissionResult {
     pub threshold_met: bool,
 }
 
+/// Guardians that have bound a principal, and how many of them form a quorum.
+#[derive(CandidType, Deserialize, Serialize)]
+pub struct GuardianQuorum {
+    pub threshold: u64,
+    pub guardians: Vec<Principal>,
+}
+
 #[derive(CandidType, Deserialize, Serialize)]
 pub struct ShareSubmissionReceipt {
     #[serde(rename = "vaultId")]
@@ -140,10 +150,13 @@ pub struct ShareSubmissionReceipt {
     pub remaining_required: i64,
 }
 
-#[derive(Debug, Error)]
+/// Returned to callers as a Candid variant so clients can match on the case and its payload.
+#[derive(Debug, Error, CandidType, Deserialize)]
 enum GuardianError {
     #[error("vault {0} not found")]
     VaultNotFound(VaultId),
+    #[error("vault {0} already has registered guardians")]
+    VaultAlreadyRegistered(VaultId),
     #[error("guardian entry not found for provided hash")]
     GuardianNotFound,
     #[error("guardian already accepted invitation under different principal")]
@@ -152,10 +165,16 @@ enum GuardianError {
     GuardianNotAccepted,
     #[error("guardian share already submitted")]
     ShareAlreadySubmitted,
+    #[error("vault manager not configured")]
+    ManagerNotConfigured,
+    #[error("the vault manager role is assigned with set_vault_manager")]
+    ManagerRoleReserved,
+    #[error("canister is paused")]
+    Paused,
     #[error("caller {0} is not authorized")]
     Unauthorized(Principal),
-    #[error("share payload exceeds {MAX_SHARE_BYTES} bytes")]
-    ShareTooLarge,
+    #[error("share payload of {size} bytes exceeds {max} bytes")]
+    ShareTooLarge { size: u64, max: u64 },
     #[error("{0}")]
     Validation(String),
     #[error("cryptographic material unavailable: {0}")]
@@ -177,52 +196,82 @@ impl From<GuardianError> for String {
 }
 
 #[init]
-fn init() {
-    STATE.with(|state| state.replace(GuardianManagerState::default()));
-}
-
-#[pre_upgrade]
-fn pre_upgrade() {
-    STATE.with(|state| {
-        storage::stable_save((state.borrow().clone(),)).expect("failed to persist guardian state");
-    });
+fn init(args: Option<InitArgs>) {
+    if let Some(manager) = args.unwrap_or_default().vault_manager {
+        mutate_state(|state| access::rotate_vault_manager(state, manager, api::msg_caller(), time()));
+    }
 }
 
+/// State lives in stable structures, so there is nothing to serialize before an upgrade.
+/// Canisters still carrying a `stable_save` snapshot are migrated once, here.
 #[post_upgrade]
 fn post_upgrade() {
-    let restored: Result<(GuardianManagerState,), _> = storage::stable_restore();
-    let state = restored.map(|r| r.0).unwrap_or_default();
-    STATE.with(|s| s.replace(state));
+    if memory::holds_legacy_snapshot() {
+        restore_legacy_snapshot();
+    }
 }
 
-#[update]
-fn set_vault_manager(manager: Principal) -> Result<(), String> {
-    let caller = api::msg_caller();
-    if !api::is_controller(&caller) {
-        return Err(GuardianError::Unauthorized(caller).to_string());
+fn restore_legacy_snapshot() {
+    let snapshot = upgrade::decode_snapshot(&ic_cdk::stable::stable_bytes())
+        .unwrap_or_else(|err| ic_cdk::trap(format!("failed to restore guardian state: {err}")));
+    mutate_state(|state| *state = snapshot.state);
+    for (vault_id, vault) in snapshot.vaults {
+        memory::insert_vault(vault_id, vault);
     }
-    mutate_state(|state| {
-        state.vault_manager = Some(manager);
-    });
-    Ok(())
 }
 
-#[update]
-async fn register_guardians(args: RegisterGuardiansArgs) -> Vec<GuardianRecord> {
+#[update(guard = "access::guard_admin")]
+fn set_vault_manager(manager: Principal) -> Result<(), GuardianError> {
     let caller = api::msg_caller();
-    with_state(|state| {
-        if let Some(manager) = state.vault_manager {
-            if manager != caller {
-                ic_cdk::trap(&GuardianError::Unauthorized(caller).to_string());
-            }
-        }
-    });
+    mutate_state(|state| access::rotate_vault_manager(state, manager, caller, time()));
+    Ok(())
+}
 
-    if caller != args.owner {
-        ic_cdk::trap(&GuardianError::Unauthorized(caller).to_string());
-    }
-    if let Err(err) = validate_invites(&args.invites, args.threshold) {
-        ic_cdk::trap(&err.to_string());
+#[query(guard = "access::guard_auditor")]
+fn manager_rotations() -> Vec<ManagerRotation> {
+    memory::manager_rotations()
+}
+
+#[update(guard = "access::guard_admin")]
+fn grant_role(principal: Principal, role: Role) -> Result<(), GuardianError> {
+    access::ensure_assignable(role)?;
+    mutate_state(|state| access::grant_role(state, principal, role));
+    Ok(())
+}
+
+#[update(guard = "access::guard_admin")]
+fn revoke_role(principal: Principal, role: Role) -> Result<(), GuardianError> {
+    access::ensure_assignable(role)?;
+    mutate_state(|state| access::revoke_role(state, principal, role));
+    Ok(())
+}
+
+#[query(guard = "access::guard_auditor")]
+fn list_roles() -> Vec<RoleAssignment> {
+    with_state(access::role_assignments)
+}
+
+#[update(guard = "access::guard_pauser")]
+fn pause() {
+    mutate_state(|state| access::set_paused(state, true));
+}
+
+#[update(guard = "access::guard_admin")]
+fn unpause() {
+    mutate_state(|state| access::set_paused(state, false));
+}
+
+#[query]
+fn is_paused() -> bool {
+    with_state(access::is_paused)
+}
+
+// The vault manager registers guardians on behalf of `args.owner`.
+#[update(guard = "access::guard_vault_manager")]
+async fn register_guardians(args: RegisterGuardiansArgs) -> Result<Vec<GuardianRecord>, GuardianError> {
+    validate_invites(&args.invites, args.threshold)?;
+    if memory::vault(args.vault_id).is_some() {
+        return Err(GuardianError::VaultAlreadyRegistered(args.vault_id));
     }
 
     let timestamp = time();
@@ -241,35 +290,25 @@ async fn register_guardians(args: RegisterGuardiansArgs) -> Vec<GuardianRecord>
         .collect();
     let response: Vec<GuardianRecord> = guardians.iter().map(guardian_record).collect();
 
-    STATE.with(|state| {
-        let mut data = state.borrow_mut();
-        if data.vaults.contains_key(&args.vault_id) {
-            ic_cdk::trap("VAULT_ALREADY_REGISTERED");
-        }
-        data.vaults.insert(
-            args.vault_id,
-            VaultGuardianSet {
-                owner: args.owner,
-                threshold: args.threshold,
-                key_id: args.key_id.clone(),
-                guardians,
-                created_at: timestamp,
-                updated_at: timestamp,
-            },
-        );
-    });
+    memory::insert_vault(
+        args.vault_id,
+        VaultGuardianSet {
+            owner: args.owner,
+            threshold: args.threshold,
+            key_id: args.key_id.clone(),
+            guardians,
+            created_at: timestamp,
+            updated_at: timestamp,
+        },
+    );
 
-    response
+    Ok(response)
 }
 
-#[update]
-async fn accept_invitation(args: AcceptGuardianArgs) -> Result<GuardianRecord, String> {
+#[update(guard = "access::guard_not_paused")]
+async fn accept_invitation(args: AcceptGuardianArgs) -> Result<GuardianRecord, GuardianError> {
     let caller = api::msg_caller();
-    mutate_state(|state| -> Result<GuardianRecord, GuardianError> {
-        let vault = state
-            .vaults
-            .get_mut(&args.vault_id)
-            .ok_or(GuardianError::VaultNotFound(args.vault_id))?;
+    memory::mutate_vault(args.vault_id, GuardianError::VaultNotFound(args.vault_id), |vault| {
         let guardian = vault
             .guardians
             .iter_mut()
@@ -286,28 +325,30 @@ async fn accept_invitation(args: AcceptGuardianArgs) -> Result<GuardianRecord, S
         vault.updated_at = guardian.updated_at;
         Ok(guardian_record(guardian))
     })
-    .map_err(String::from)
 }
 
-#[update]
-async fn submit_guardian_share(args: SubmitShareArgs) -> Result<ShareSubmissionReceipt, String> {
+#[update(guard = "access::guard_not_paused")]
+async fn submit_guardian_share(args: SubmitShareArgs) -> Result<ShareSubmissionReceipt, GuardianError> {
     let caller = api::msg_caller();
     if args.share_payload.is_empty() {
-        return Err(GuardianError::validation("share payload required").into());
+        return Err(GuardianError::validation("share payload required"));
     }
     if args.share_payload.len() > MAX_SHARE_BYTES {
-        return Err(GuardianError::ShareTooLarge.into());
+        return Err(GuardianError::ShareTooLarge {
+            size: args.share_payload.len() as u64,
+            max: MAX_SHARE_BYTES as u64,
+        });
     }
 
     let snapshot = snapshot_guardian(args.vault_id, &args.email_hash)?;
     if snapshot.guardian.principal_id != Some(caller) {
-        return Err(GuardianError::Unauthorized(caller).into());
+        return Err(GuardianError::Unauthorized(caller));
     }
     if snapshot.guardian.status != GuardianStatus::Accepted {
-        return Err(GuardianError::GuardianNotAccepted.into());
+        return Err(GuardianError::GuardianNotAccepted);
     }
     if snapshot.guardian.encrypted_share.is_some() {
-        return Err(GuardianError::ShareAlreadySubmitted.into());
+        return Err(GuardianError::ShareAlreadySubmitted);
     }
 
     let ciphertext =
@@ -315,11 +356,7 @@ async fn submit_guardian_share(args: SubmitShareArgs) -> Result<ShareSubmissionR
             .await?;
     let now = time();
 
-    mutate_state(|state| -> Result<ShareSubmissionReceipt, GuardianError> {
-        let vault = state
-            .vaults
-            .get_mut(&snapshot.vault_id)
-            .ok_or(GuardianError::VaultNotFound(snapshot.vault_id))?;
+    memory::mutate_vault(snapshot.vault_id, GuardianError::VaultNotFound(snapshot.vault_id), |vault| {
         let guardian = vault
             .guardians
             .get_mut(snapshot.guardian_index)
@@ -345,74 +382,68 @@ async fn submit_guardian_share(args: SubmitShareArgs) -> Result<ShareSubmissionR
             remaining_required: remaining,
         })
     })
-    .map_err(String::from)
 }
 
-#[update]
+#[update(guard = "access::guard_not_paused")]
 async fn guardian_threshold_status(vault_id: VaultId) -> GuardianSubmissionResult {
-    with_state(|state| threshold_summary(state, vault_id))
+    threshold_summary(vault_id)
 }
 
 #[query]
 fn list_guardians(vault_id: VaultId) -> Vec<GuardianRecord> {
-    with_state(|state| {
-        let vault = state
-            .vaults
-            .get(&vault_id)
-            .unwrap_or_else(|| ic_cdk::trap(&GuardianError::VaultNotFound(vault_id).to_string()));
-        vault.guardians.iter().map(guardian_record).collect()
+    let vault = memory::vault(vault_id)
+        .unwrap_or_else(|| ic_cdk::trap(GuardianError::VaultNotFound(vault_id).to_string()));
+    vault.guardians.iter().map(guardian_record).collect()
+}
+
+#[query(guard = "access::guard_auditor")]
+fn guardian_quorum(vault_id: VaultId) -> Option<GuardianQuorum> {
+    memory::vault(vault_id).map(|vault| GuardianQuorum {
+        threshold: vault.threshold,
+        guardians: vault.guardians.iter().filter_map(|g| g.principal_id).collect(),
     })
 }
 
 #[query]
 fn guardian_by_hash(args: AcceptGuardianArgs) -> Option<GuardianRecord> {
-    with_state(|state| {
-        state
-            .vaults
-            .get(&args.vault_id)
-            .and_then(|vault| {
-                vault
-                    .guardians
-                    .iter()
-                    .find(|g| g.email_hash == args.email_hash)
-                    .map(guardian_record)
-            })
+    memory::vault(args.vault_id).and_then(|vault| {
+        vault
+            .guardians
+            .iter()
+            .find(|g| g.email_hash == args.email_hash)
+            .map(guardian_record)
     })
 }
 
 #[query]
 fn list_guardian_vaults() -> Vec<VaultId> {
     let caller = api::msg_caller();
-    with_state(|state| {
-        state
-            .vaults
-            .iter()
-            .filter(|(_, vault)| {
-                vault.guardians.iter().any(|g| g.principal_id == Some(caller))
-            })
-            .map(|(id, _)| *id)
-            .collect()
-    })
-}
-
-fn mutate_state<F, R>(f: F) -> R
-where
-    F: FnOnce(&mut GuardianManagerState) -> R,
-{
-    STATE.with(|state| {
-        let mut data = state.borrow_mut();
-        f(&mut data)
-    })
+    memory::vaults()
+        .into_iter()
+        .filter(|(_, vault)| {
+            vault.guardians.iter().any(|g| g.principal_id == Some(caller))
+        })
+        .map(|(id, _)| id)
+        .collect()
 }
 
-fn with_state<F, R>(f: F) -> R
-where
-    F: FnOnce(&GuardianManagerState) -> R,
-{
-    STATE.with(|state| {
-        let data = state.borrow();
-        f(&data)
-    })
+#[query]
+fn http_request(request: metrics::HttpRequest) -> metrics::HttpResponse {
+    metrics::serve(&request, render_metrics)
+}
+
+fn render_metrics() -> String {
+    let vaults: Vec<VaultGuardianSet> = memory::vaults().into_iter().map(|(_, vault)| vault).collect();
+    let mut encoder = metrics::MetricsEncoder::default();
+    metrics::encode_vaults(&mut encoder, &vaults);
+    encoder
+        .gauge("guardian_mgr_cycles_balance", "Cycles held by the canister.", api::canister_cycle_balance())
+        .gauge(
+            "guardian_mgr_stable_memory_bytes",
+            "Size of stable memory.",
+            ic_cdk::stable::stable_size() * WASM_PAGE_SIZE,
+        );
+    encoder.finish()
 }
 
 fn guardian_record(entry: &GuardianEntry) -> GuardianRecord {
@@ -462,11 +493,9 @@ fn validate_invites(invites: &[GuardianRegistration], threshold: u64) -> Result<
     Ok(())
 }
 
-fn threshold_summary(state: &GuardianManagerState, vault_id: VaultId) -> GuardianSubmissionResult {
-    let vault = state
-        .vaults
-        .get(&vault_id)
-        .unwrap_or_else(|| ic_cdk::trap(&GuardianError::VaultNotFound(vault_id).to_string()));
+fn threshold_summary(vault_id: VaultId) -> GuardianSubmissionResult {
+    let vault = memory::vault(vault_id)
+        .unwrap_or_else(|| ic_cdk::trap(GuardianError::VaultNotFound(vault_id).to_string()));
     let submitted = vault
         .guardians
         .iter()
@@ -479,23 +508,18 @@ fn threshold_summary(state: &GuardianManagerState, vault_id: VaultId) -> Guardia
 }
 
 fn snapshot_guardian(vault_id: VaultId, email_hash: &[u8]) -> Result<GuardianSnapshot, GuardianError> {
-    with_state(|state| {
-        let vault = state
-            .vaults
-            .get(&vault_id)
-            .ok_or(GuardianError::VaultNotFound(vault_id))?;
-        vault
-            .guardians
-            .iter()
-            .enumerate()
-            .find(|(_, entry)| entry.email_hash == email_hash)
-            .map(|(index, entry)| GuardianSnapshot {
-                vault_id,
-                guardian_index: index,
-                guardian: entry.clone(),
-            })
-            .ok_or(GuardianError::GuardianNotFound)
-    })
+    let vault = memory::vault(vault_id).ok_or(GuardianError::VaultNotFound(vault_id))?;
+    vault
+        .guardians
+        .iter()
+        .enumerate()
+        .find(|(_, entry)| entry.email_hash == email_hash)
+        .map(|(index, entry)| GuardianSnapshot {
+            vault_id,
+            guardian_index: index,
+            guardian: entry.clone(),
+        })
+        .ok_or(GuardianError::GuardianNotFound)
 }
 
 fn vetkd_key_id() -> VetKDKeyId {
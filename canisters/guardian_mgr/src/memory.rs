@@ -0,0 +1,160 @@
+use crate::upgrade::VersionedState;
+use crate::{GuardianManagerState, VaultGuardianSet, VaultId};
+use canister_common::access::ManagerRotation;
+use canister_common::{candid_storable, storable};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell, StableLog, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const CONFIG_MEMORY: MemoryId = MemoryId::new(0);
+const VAULTS_MEMORY: MemoryId = MemoryId::new(1);
+const ROTATIONS_INDEX_MEMORY: MemoryId = MemoryId::new(2);
+const ROTATIONS_DATA_MEMORY: MemoryId = MemoryId::new(3);
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    static CONFIG: RefCell<StableCell<GuardianManagerState, Memory>> = RefCell::new(StableCell::init(
+        MEMORY_MANAGER.with(|m| m.borrow().get(CONFIG_MEMORY)),
+        GuardianManagerState::default(),
+    ));
+
+    static VAULTS: RefCell<StableBTreeMap<VaultId, VaultGuardianSet, Memory>> =
+        RefCell::new(StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(VAULTS_MEMORY))));
+
+    static ROTATIONS: RefCell<StableLog<ManagerRotation, Memory, Memory>> = RefCell::new(StableLog::init(
+        MEMORY_MANAGER.with(|m| m.borrow().get(ROTATIONS_INDEX_MEMORY)),
+        MEMORY_MANAGER.with(|m| m.borrow().get(ROTATIONS_DATA_MEMORY)),
+    ));
+}
+
+/// Canister-wide configuration (roles, managers, pause flag) kept in a stable cell.
+pub fn with_state<F, R>(f: F) -> R
+where
+    F: FnOnce(&GuardianManagerState) -> R,
+{
+    CONFIG.with(|cell| f(cell.borrow().get()))
+}
+
+pub fn mutate_state<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut GuardianManagerState) -> R,
+{
+    CONFIG.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let mut state = cell.get().clone();
+        let result = f(&mut state);
+        cell.set(state);
+        result
+    })
+}
+
+pub fn append_rotation(rotation: ManagerRotation) {
+    ROTATIONS.with(|log| log.borrow().append(&rotation).expect("rotation log out of stable memory"));
+}
+
+pub fn manager_rotations() -> Vec<ManagerRotation> {
+    ROTATIONS.with(|log| log.borrow().iter().collect())
+}
+
+pub fn vault(vault_id: VaultId) -> Option<VaultGuardianSet> {
+    VAULTS.with(|vaults| vaults.borrow().get(&vault_id))
+}
+
+pub fn insert_vault(vault_id: VaultId, vault: VaultGuardianSet) {
+    VAULTS.with(|vaults| vaults.borrow_mut().insert(vault_id, vault));
+}
+
+/// Applies `f` to a copy of the vault and writes it back only when `f` succeeds.
+pub fn mutate_vault<F, R, E>(vault_id: VaultId, missing: E, f: F) -> Result<R, E>
+where
+    F: FnOnce(&mut VaultGuardianSet) -> Result<R, E>,
+{
+    let mut vault = vault(vault_id).ok_or(missing)?;
+    let result = f(&mut vault)?;
+    insert_vault(vault_id, vault);
+    Ok(result)
+}
+
+pub fn vaults() -> Vec<(VaultId, VaultGuardianSet)> {
+    VAULTS.with(|vaults| {
+        vaults
+            .borrow()
+            .iter()
+            .map(|entry| (*entry.key(), entry.value()))
+            .collect()
+    })
+}
+
+/// True when stable memory still holds a snapshot written by `stable_save` instead of the
+/// memory manager layout. Must be checked before any stable structure is touched.
+pub fn holds_legacy_snapshot() -> bool {
+    if ic_cdk::stable::stable_size() == 0 {
+        return false;
+    }
+    let mut magic = [0u8; 3];
+    ic_cdk::stable::stable_read(0, &mut magic);
+    &magic != b"MGR"
+}
+
+impl Storable for GuardianManagerState {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.clone().into_bytes())
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        storable::encode(&VersionedState::V1(self), "guardian config")
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        match storable::decode(&bytes, "guardian config") {
+            VersionedState::V1(state) => state,
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+candid_storable!(VaultGuardianSet, "guardian set");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Principal;
+
+    #[test]
+    fn failed_mutation_leaves_vault_untouched() {
+        insert_vault(
+            5,
+            VaultGuardianSet {
+                owner: Principal::from_slice(&[1]),
+                threshold: 2,
+                key_id: "key_1".into(),
+                guardians: vec![],
+                created_at: 1,
+                updated_at: 1,
+            },
+        );
+
+        let result: Result<(), &str> = mutate_vault(5, "missing", |vault| {
+            vault.updated_at = 99;
+            Err("rejected")
+        });
+        assert_eq!(result, Err("rejected"));
+        assert_eq!(vault(5).unwrap().updated_at, 1);
+
+        mutate_vault(5, "missing", |vault| -> Result<(), &str> {
+            vault.updated_at = 2;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(vault(5).unwrap().updated_at, 2);
+        assert_eq!(mutate_vault(6, "missing", |_| Ok(())), Err("missing"));
+        assert_eq!(vaults().len(), 1);
+    }
+}
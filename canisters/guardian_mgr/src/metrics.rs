@@ -0,0 +1,92 @@
+//! Guardian metrics for the shared `/metrics` endpoint.
+
+use crate::{GuardianStatus, VaultGuardianSet};
+pub use canister_common::metrics::{serve, HttpRequest, HttpResponse, MetricsEncoder};
+
+/// Upper bounds of the shares-submitted-per-vault histogram; vaults hold at most five guardians.
+const SUBMISSION_BUCKETS: [u64; 5] = [0, 1, 2, 3, 4];
+
+/// Vault and guardian metrics derived from the registered guardian sets.
+pub fn encode_vaults(encoder: &mut MetricsEncoder, vaults: &[VaultGuardianSet]) {
+    let guardians = || vaults.iter().flat_map(|vault| vault.guardians.iter());
+    let submitted = |vault: &VaultGuardianSet| {
+        vault.guardians.iter().filter(|g| g.encrypted_share.is_some()).count() as u64
+    };
+
+    encoder.gauge("guardian_mgr_vaults", "Vaults with registered guardians.", vaults.len());
+    encoder.family("guardian_mgr_guardians", "gauge", "Guardians by invitation status.");
+    for (status, label) in [
+        (GuardianStatus::Invited, "invited"),
+        (GuardianStatus::Accepted, "accepted"),
+        (GuardianStatus::ShareSubmitted, "share_submitted"),
+    ] {
+        let count = guardians().filter(|g| g.status == status).count();
+        encoder.sample("guardian_mgr_guardians", &[("status", label)], count);
+    }
+    encoder.gauge(
+        "guardian_mgr_share_submissions",
+        "Encrypted shares submitted across all vaults.",
+        vaults.iter().map(submitted).sum::<u64>(),
+    );
+    encoder.gauge(
+        "guardian_mgr_vaults_threshold_met",
+        "Vaults whose submitted shares meet their threshold.",
+        vaults.iter().filter(|vault| submitted(vault) >= vault.threshold).count(),
+    );
+
+    let name = "guardian_mgr_vault_shares_submitted";
+    encoder.family(name, "histogram", "Shares submitted per vault.");
+    for bound in SUBMISSION_BUCKETS {
+        let count = vaults.iter().filter(|vault| submitted(vault) <= bound).count();
+        encoder.sample(&format!("{name}_bucket"), &[("le", &bound.to_string())], count);
+    }
+    encoder.sample(&format!("{name}_bucket"), &[("le", "+Inf")], vaults.len());
+    encoder.sample(&format!("{name}_sum"), &[], vaults.iter().map(submitted).sum::<u64>());
+    encoder.sample(&format!("{name}_count"), &[], vaults.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GuardianEntry;
+    use candid::Principal;
+
+    fn guardian(status: GuardianStatus) -> GuardianEntry {
+        GuardianEntry {
+            email_hash: vec![],
+            alias: String::new(),
+            encrypted_share: (status == GuardianStatus::ShareSubmitted).then(|| vec![1]),
+            status,
+            principal_id: None,
+            submitted_at: None,
+            updated_at: 0,
+        }
+    }
+
+    #[test]
+    fn guardian_metrics_cover_every_status() {
+        let vault = VaultGuardianSet {
+            owner: Principal::anonymous(),
+            threshold: 2,
+            key_id: "key_1".into(),
+            guardians: vec![
+                guardian(GuardianStatus::Invited),
+                guardian(GuardianStatus::Accepted),
+                guardian(GuardianStatus::ShareSubmitted),
+            ],
+            created_at: 0,
+            updated_at: 0,
+        };
+        let mut encoder = MetricsEncoder::default();
+        encode_vaults(&mut encoder, &[vault]);
+        let body = encoder.finish();
+
+        assert!(body.contains("guardian_mgr_vaults 1\n"));
+        assert!(body.contains("guardian_mgr_guardians{status=\"invited\"} 1\n"));
+        assert!(body.contains("guardian_mgr_guardians{status=\"share_submitted\"} 1\n"));
+        assert!(body.contains("guardian_mgr_vaults_threshold_met 0\n"));
+        assert!(body.contains("guardian_mgr_vault_shares_submitted_bucket{le=\"0\"} 0\n"));
+        assert!(body.contains("guardian_mgr_vault_shares_submitted_bucket{le=\"1\"} 1\n"));
+        assert!(body.contains("guardian_mgr_vault_shares_submitted_count 1\n"));
+    }
+}
@@ -0,0 +1,117 @@
+use crate::access;
+use crate::{GuardianManagerState, VaultGuardianSet, VaultId};
+use candid::{de::IDLDeserialize, CandidType, Principal};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Versioned envelope for the guardian configuration. The newest variant wraps the live state
+/// type; a schema change freezes its current shape as `StateVn` and adds a variant plus a
+/// migration.
+#[derive(CandidType, Deserialize)]
+pub enum VersionedState {
+    V1(GuardianManagerState),
+}
+
+/// Unversioned heap state the first release wrote with `stable_save` before each upgrade.
+#[derive(CandidType, Deserialize)]
+pub struct BaselineState {
+    vaults: BTreeMap<VaultId, VaultGuardianSet>,
+    vault_manager: Option<Principal>,
+}
+
+/// A `stable_save` snapshot split into what now lives in the config cell and the vault map.
+pub struct LegacySnapshot {
+    pub state: GuardianManagerState,
+    pub vaults: BTreeMap<VaultId, VaultGuardianSet>,
+}
+
+/// Before roles existed the single `vault_manager` was the only authority, so it keeps the
+/// vault manager role.
+pub fn migrate_baseline(legacy: BaselineState) -> LegacySnapshot {
+    let mut state = GuardianManagerState {
+        vault_manager: legacy.vault_manager,
+        ..GuardianManagerState::default()
+    };
+    if let Some(manager) = legacy.vault_manager {
+        access::grant_role(&mut state, manager, access::Role::VaultManager);
+    }
+    LegacySnapshot {
+        state,
+        vaults: legacy.vaults,
+    }
+}
+
+/// Decodes a `stable_save` snapshot and migrates it forward. Anything that does not decode is
+/// an error: the caller must abort the upgrade rather than start empty.
+pub fn decode_snapshot(bytes: &[u8]) -> Result<LegacySnapshot, String> {
+    let mut de = IDLDeserialize::new(bytes).map_err(|err| err.to_string())?;
+    let legacy = de
+        .get_value::<BaselineState>()
+        .map_err(|err| format!("stable state is not a first-release snapshot: {err}"))?;
+    Ok(migrate_baseline(legacy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GuardianEntry, GuardianStatus};
+
+    /// State as persisted by the first release, before rotations and roles were tracked.
+    #[derive(CandidType)]
+    struct StateV0 {
+        vaults: BTreeMap<VaultId, VaultGuardianSet>,
+        vault_manager: Option<Principal>,
+    }
+
+    /// Mimics `stable_bytes()`, which returns whole pages padded with zeros.
+    fn stable_image<T: candid::utils::ArgumentEncoder>(args: T) -> Vec<u8> {
+        let mut bytes = candid::encode_args(args).unwrap();
+        bytes.resize(bytes.len() + 512, 0);
+        bytes
+    }
+
+    fn manager() -> Principal {
+        Principal::from_slice(&[7])
+    }
+
+    fn guardian_set() -> VaultGuardianSet {
+        VaultGuardianSet {
+            owner: Principal::from_slice(&[1]),
+            threshold: 2,
+            key_id: "key_1".into(),
+            guardians: vec![GuardianEntry {
+                email_hash: vec![0xab; 32],
+                alias: "Alpha".into(),
+                status: GuardianStatus::ShareSubmitted,
+                principal_id: Some(Principal::from_slice(&[2])),
+                encrypted_share: Some(vec![1, 2, 3]),
+                submitted_at: Some(9),
+                updated_at: 9,
+            }],
+            created_at: 1,
+            updated_at: 9,
+        }
+    }
+
+    #[test]
+    fn first_release_snapshot_migrates_forward() {
+        let mut vaults = BTreeMap::new();
+        vaults.insert(42, guardian_set());
+        let bytes = stable_image((StateV0 {
+            vaults,
+            vault_manager: Some(manager()),
+        },));
+
+        let snapshot = decode_snapshot(&bytes).expect("legacy state decodes");
+        let vault = &snapshot.vaults[&42];
+        assert_eq!(vault.guardians[0].encrypted_share, Some(vec![1, 2, 3]));
+        assert!(access::ensure_vault_manager(&snapshot.state, manager()).is_ok());
+    }
+
+    #[test]
+    fn undecodable_state_is_an_error() {
+        let bytes = stable_image((String::from("not a guardian state"),));
+        assert!(decode_snapshot(&bytes).is_err());
+        assert!(decode_snapshot(&[0u8; 64]).is_err());
+    }
+}
@@ -0,0 +1,264 @@
+//! Role-based access control. Each canister keeps an `AccessControl` in its config state,
+//! implements `AccessState` for that state and wraps the checks below in its own guards,
+//! converting `AccessError` into its Candid error type.
+
+use candid::{CandidType, Principal};
+use ic_cdk::api;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Clone, Copy, Debug, CandidType, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Admin,
+    VaultManager,
+    Auditor,
+    Pauser,
+}
+
+#[derive(Clone, Default, CandidType, Deserialize, Serialize)]
+pub struct AccessControl {
+    pub roles: BTreeMap<Principal, BTreeSet<Role>>,
+    pub paused: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct RoleAssignment {
+    pub principal: Principal,
+    pub roles: Vec<Role>,
+}
+
+#[derive(Clone, CandidType, Deserialize, Serialize)]
+pub struct ManagerRotation {
+    pub previous: Option<Principal>,
+    pub next: Principal,
+    #[serde(rename = "changedBy")]
+    pub changed_by: Principal,
+    #[serde(rename = "changedAt")]
+    pub changed_at: u64,
+}
+
+// Rotations only ever grow, so canisters append them to a `StableLog` rather than keeping
+// them in their config cell.
+crate::candid_storable!(ManagerRotation, "manager rotation");
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AccessError {
+    ManagerNotConfigured,
+    ManagerRoleReserved,
+    Paused,
+    Unauthorized(Principal),
+}
+
+/// Canister state carrying the role table, the primary vault manager and its rotation log.
+pub trait AccessState {
+    fn access(&self) -> &AccessControl;
+    fn access_mut(&mut self) -> &mut AccessControl;
+    fn vault_manager_mut(&mut self) -> &mut Option<Principal>;
+    fn log_rotation(&mut self, rotation: ManagerRotation);
+}
+
+pub fn has_role<S: AccessState>(state: &S, principal: Principal, role: Role) -> bool {
+    state
+        .access()
+        .roles
+        .get(&principal)
+        .is_some_and(|roles| roles.contains(&role))
+}
+
+/// Whether `principal` has been granted any role at all.
+pub fn holds_any_role<S: AccessState>(state: &S, principal: Principal) -> bool {
+    state.access().roles.contains_key(&principal)
+}
+
+pub fn is_admin<S: AccessState>(state: &S, caller: Principal) -> bool {
+    api::is_controller(&caller) || has_role(state, caller, Role::Admin)
+}
+
+/// Read access to sensitive data: admins, auditors and vault managers.
+pub fn can_audit<S: AccessState>(state: &S, caller: Principal) -> bool {
+    is_admin(state, caller) || [Role::Auditor, Role::VaultManager].iter().any(|role| has_role(state, caller, *role))
+}
+
+pub fn is_paused<S: AccessState>(state: &S) -> bool {
+    state.access().paused
+}
+
+pub fn ensure_admin<S: AccessState>(state: &S, caller: Principal) -> Result<(), AccessError> {
+    if !is_admin(state, caller) {
+        return Err(AccessError::Unauthorized(caller));
+    }
+    Ok(())
+}
+
+pub fn ensure_auditor<S: AccessState>(state: &S, caller: Principal) -> Result<(), AccessError> {
+    if !can_audit(state, caller) {
+        return Err(AccessError::Unauthorized(caller));
+    }
+    Ok(())
+}
+
+pub fn ensure_pauser<S: AccessState>(state: &S, caller: Principal) -> Result<(), AccessError> {
+    if !is_admin(state, caller) && !has_role(state, caller, Role::Pauser) {
+        return Err(AccessError::Unauthorized(caller));
+    }
+    Ok(())
+}
+
+/// Sensitive methods fail closed: until a vault manager is configured nobody may call them.
+pub fn ensure_vault_manager<S: AccessState>(state: &S, caller: Principal) -> Result<(), AccessError> {
+    let configured = state
+        .access()
+        .roles
+        .values()
+        .any(|roles| roles.contains(&Role::VaultManager));
+    if !configured {
+        return Err(AccessError::ManagerNotConfigured);
+    }
+    if !has_role(state, caller, Role::VaultManager) {
+        return Err(AccessError::Unauthorized(caller));
+    }
+    Ok(())
+}
+
+pub fn ensure_not_paused<S: AccessState>(state: &S) -> Result<(), AccessError> {
+    if is_paused(state) {
+        return Err(AccessError::Paused);
+    }
+    Ok(())
+}
+
+/// The vault manager role only changes hands through `rotate_vault_manager`, which keeps
+/// `vault_manager` and the rotation log in step with the grant.
+pub fn ensure_assignable(role: Role) -> Result<(), AccessError> {
+    if role == Role::VaultManager {
+        return Err(AccessError::ManagerRoleReserved);
+    }
+    Ok(())
+}
+
+pub fn grant_role<S: AccessState>(state: &mut S, principal: Principal, role: Role) {
+    state
+        .access_mut()
+        .roles
+        .entry(principal)
+        .or_default()
+        .insert(role);
+}
+
+pub fn revoke_role<S: AccessState>(state: &mut S, principal: Principal, role: Role) {
+    let roles = &mut state.access_mut().roles;
+    if let Some(held) = roles.get_mut(&principal) {
+        held.remove(&role);
+        if held.is_empty() {
+            roles.remove(&principal);
+        }
+    }
+}
+
+pub fn set_paused<S: AccessState>(state: &mut S, paused: bool) {
+    state.access_mut().paused = paused;
+}
+
+pub fn role_assignments<S: AccessState>(state: &S) -> Vec<RoleAssignment> {
+    state
+        .access()
+        .roles
+        .iter()
+        .map(|(principal, roles)| RoleAssignment {
+            principal: *principal,
+            roles: roles.iter().copied().collect(),
+        })
+        .collect()
+}
+
+/// Replaces the primary vault manager, moving its role grant and logging the rotation.
+pub fn rotate_vault_manager<S: AccessState>(state: &mut S, next: Principal, changed_by: Principal, changed_at: u64) {
+    let previous = state.vault_manager_mut().replace(next);
+    if let Some(previous) = previous {
+        revoke_role(state, previous, Role::VaultManager);
+    }
+    grant_role(state, next, Role::VaultManager);
+    state.log_rotation(ManagerRotation {
+        previous,
+        next,
+        changed_by,
+        changed_at,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct State {
+        vault_manager: Option<Principal>,
+        rotations: Vec<ManagerRotation>,
+        access: AccessControl,
+    }
+
+    impl AccessState for State {
+        fn access(&self) -> &AccessControl {
+            &self.access
+        }
+
+        fn access_mut(&mut self) -> &mut AccessControl {
+            &mut self.access
+        }
+
+        fn vault_manager_mut(&mut self) -> &mut Option<Principal> {
+            &mut self.vault_manager
+        }
+
+        fn log_rotation(&mut self, rotation: ManagerRotation) {
+            self.rotations.push(rotation);
+        }
+    }
+
+    #[test]
+    fn unset_manager_rejects_every_caller() {
+        let state = State::default();
+        let caller = Principal::from_slice(&[1]);
+        assert_eq!(ensure_vault_manager(&state, caller), Err(AccessError::ManagerNotConfigured));
+    }
+
+    #[test]
+    fn rotations_are_logged_in_order() {
+        let mut state = State::default();
+        let first = Principal::from_slice(&[1]);
+        let second = Principal::from_slice(&[2]);
+        let admin = Principal::from_slice(&[9]);
+        rotate_vault_manager(&mut state, first, admin, 10);
+        rotate_vault_manager(&mut state, second, admin, 20);
+
+        assert!(ensure_vault_manager(&state, second).is_ok());
+        assert_eq!(ensure_vault_manager(&state, first), Err(AccessError::Unauthorized(first)));
+        assert_eq!(state.vault_manager, Some(second));
+        let log = state.rotations;
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[1].previous, Some(first));
+        assert_eq!(log[1].next, second);
+    }
+
+    #[test]
+    fn roles_are_granted_and_revoked_independently() {
+        let mut state = State::default();
+        let auditor = Principal::from_slice(&[3]);
+        grant_role(&mut state, auditor, Role::Auditor);
+        grant_role(&mut state, auditor, Role::Pauser);
+        revoke_role(&mut state, auditor, Role::Pauser);
+
+        assert!(has_role(&state, auditor, Role::Auditor));
+        assert!(!has_role(&state, auditor, Role::Pauser));
+        assert!(holds_any_role(&state, auditor));
+        revoke_role(&mut state, auditor, Role::Auditor);
+        assert!(!holds_any_role(&state, auditor));
+        assert_eq!(ensure_vault_manager(&state, auditor), Err(AccessError::ManagerNotConfigured));
+
+        assert!(ensure_assignable(Role::Auditor).is_ok());
+        assert_eq!(ensure_assignable(Role::VaultManager), Err(AccessError::ManagerRoleReserved));
+
+        set_paused(&mut state, true);
+        assert_eq!(ensure_not_paused(&state), Err(AccessError::Paused));
+    }
+}
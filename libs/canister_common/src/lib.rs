@@ -0,0 +1,6 @@
+//! Building blocks shared by the Rust canisters: role-based access control, the
+//! Prometheus metrics endpoint and Candid-encoded stable storage.
+
+pub mod access;
+pub mod metrics;
+pub mod storable;
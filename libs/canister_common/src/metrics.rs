@@ -0,0 +1,109 @@
+//! Prometheus text metrics served from `http_request`. Query responses are not certified, so
+//! scrape them through the canister's `raw` domain.
+
+use candid::CandidType;
+use serde::Deserialize;
+use serde_bytes::ByteBuf;
+use std::fmt::{Display, Write};
+
+#[derive(CandidType, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: ByteBuf,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: ByteBuf,
+}
+
+/// Answers `GET /metrics` with `render()`; every other request gets a 404.
+pub fn serve(request: &HttpRequest, render: impl FnOnce() -> String) -> HttpResponse {
+    let path = request.url.split('?').next().unwrap_or_default();
+    if !request.method.eq_ignore_ascii_case("GET") || path != "/metrics" {
+        return HttpResponse {
+            status_code: 404,
+            headers: vec![("Content-Type".into(), "text/plain".into())],
+            body: ByteBuf::from("not found"),
+        };
+    }
+    HttpResponse {
+        status_code: 200,
+        headers: vec![("Content-Type".into(), "text/plain; version=0.0.4".into())],
+        body: ByteBuf::from(render()),
+    }
+}
+
+#[derive(Default)]
+pub struct MetricsEncoder {
+    out: String,
+}
+
+impl MetricsEncoder {
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) -> &mut Self {
+        let _ = writeln!(self.out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+        self
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) -> &mut Self {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels.iter().map(|(key, value)| format!("{key}=\"{value}\"")).collect();
+            let _ = write!(self.out, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.out, " {value}");
+        self
+    }
+
+    pub fn gauge(&mut self, name: &str, help: &str, value: impl Display) -> &mut Self {
+        self.family(name, "gauge", help).sample(name, &[], value)
+    }
+
+    pub fn counter(&mut self, name: &str, help: &str, value: impl Display) -> &mut Self {
+        self.family(name, "counter", help).sample(name, &[], value)
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, url: &str) -> HttpRequest {
+        HttpRequest {
+            method: method.into(),
+            url: url.into(),
+            headers: vec![],
+            body: ByteBuf::new(),
+        }
+    }
+
+    #[test]
+    fn only_get_metrics_is_served() {
+        let response = serve(&request("GET", "/metrics?format=text"), || {
+            let mut encoder = MetricsEncoder::default();
+            encoder.gauge("vaults", "Registered vaults.", 3);
+            encoder.finish()
+        });
+        assert_eq!(response.status_code, 200);
+        let body = String::from_utf8(response.body.into_vec()).unwrap();
+        assert_eq!(body, "# HELP vaults Registered vaults.\n# TYPE vaults gauge\nvaults 3\n");
+
+        assert_eq!(serve(&request("POST", "/metrics"), String::new).status_code, 404);
+        assert_eq!(serve(&request("GET", "/status"), String::new).status_code, 404);
+    }
+
+    #[test]
+    fn labels_are_rendered_in_order() {
+        let mut encoder = MetricsEncoder::default();
+        encoder.sample("errors_total", &[("method", "bitcoin_get_utxos"), ("kind", "rejected")], 2);
+        assert_eq!(encoder.finish(), "errors_total{method=\"bitcoin_get_utxos\",kind=\"rejected\"} 2\n");
+    }
+}
@@ -0,0 +1,38 @@
+//! Candid encoding for values kept in stable structures.
+
+pub use ic_stable_structures::storable::Bound;
+pub use ic_stable_structures::Storable;
+
+use candid::CandidType;
+use serde::de::DeserializeOwned;
+
+/// Implements an unbounded `Storable` for `$ty` that Candid-encodes the value. `$what` names
+/// it in the panic raised when stable memory holds something that does not decode.
+#[macro_export]
+macro_rules! candid_storable {
+    ($ty:ty, $what:literal) => {
+        impl $crate::storable::Storable for $ty {
+            fn to_bytes(&self) -> ::std::borrow::Cow<'_, [u8]> {
+                ::std::borrow::Cow::Owned($crate::storable::encode(self, $what))
+            }
+
+            fn into_bytes(self) -> ::std::vec::Vec<u8> {
+                $crate::storable::encode(&self, $what)
+            }
+
+            fn from_bytes(bytes: ::std::borrow::Cow<[u8]>) -> Self {
+                $crate::storable::decode(&bytes, $what)
+            }
+
+            const BOUND: $crate::storable::Bound = $crate::storable::Bound::Unbounded;
+        }
+    };
+}
+
+pub fn encode<T: CandidType>(value: &T, what: &str) -> Vec<u8> {
+    candid::encode_one(value).unwrap_or_else(|err| panic!("failed to encode {what}: {err}"))
+}
+
+pub fn decode<T: CandidType + DeserializeOwned>(bytes: &[u8], what: &str) -> T {
+    candid::decode_one(bytes).unwrap_or_else(|err| panic!("failed to decode {what}: {err}"))
+}
@@ -1,15 +1,22 @@
 import { IDL } from "@dfinity/candid";
-import type { HeirRecord } from "@/types/vault";
+import type { Principal } from "@dfinity/principal";
+import type { CanisterResult } from "@/lib/ic/result";
 
 export const idlFactory: IDL.InterfaceFactory = ({ IDL }) => {
   const I = IDL;
+  const Account = I.Record({
+    owner: I.Principal,
+    subaccount: I.Opt(I.Vec(I.Nat8)),
+  });
   const HeirRecordIdl = I.Record({
     address: I.Text,
     weightBps: I.Nat64,
+    account: I.Opt(Account),
   });
   const GenerateVaultAddressArgs = I.Record({
     vaultId: I.Nat64,
     keyId: I.Text,
+    owner: I.Opt(I.Principal),
   });
   const BitcoinAddressResponse = I.Record({
     address: I.Text,
@@ -20,19 +27,66 @@ export const idlFactory: IDL.InterfaceFactory = ({ IDL }) => {
     keyId: I.Text,
     heirs: I.Vec(HeirRecordIdl),
     guardian_submissions: I.Nat64,
+    waitForConfirmations: I.Opt(I.Bool),
   });
   const ExecuteInheritanceResponse = I.Record({
     txId: I.Text,
   });
+  const Network = I.Variant({
+    mainnet: I.Null,
+    testnet: I.Null,
+    regtest: I.Null,
+  });
+  const WalletErrorIdl = I.Variant({
+    VaultNotFound: I.Nat64,
+    VaultAlreadyExists: I.Nat64,
+    InvalidHeirs: I.Null,
+    InvalidSchedule: I.Null,
+    InvalidPolicy: I.Text,
+    LockTimeNotReached: I.Record({ lock_time: I.Nat32, tip_height: I.Nat32 }),
+    VestingStarted: I.Nat64,
+    HeirsNotConfigured: I.Nat64,
+    AwaitingConfirmations: I.Record({ vault_id: I.Nat64, pending: I.Nat64 }),
+    NoUtxos: I.Nat64,
+    DustPayout: I.Record({ address: I.Text, amount: I.Nat64 }),
+    InvalidHeirAddress: I.Record({ address: I.Text, reason: I.Text }),
+    InsufficientFunds: I.Record({ available: I.Nat64, required: I.Nat64 }),
+    InsufficientCycles: I.Record({ needed: I.Nat, available: I.Nat }),
+    FeeEstimationUnavailable: I.Null,
+    Crypto: I.Text,
+    Network: I.Text,
+    UnsupportedDescriptor: I.Nat64,
+    UnknownKeyId: I.Record({ key_id: I.Text, network: Network }),
+    KeyUnchanged: I.Nat64,
+    SpendInProgress: I.Nat64,
+    MinterNotConfigured: I.Null,
+    Minter: I.Text,
+    Ledger: I.Text,
+    ExecutionPending: I.Nat64,
+    NoPendingExecution: I.Nat64,
+    GracePeriodElapsed: I.Nat64,
+    TrancheTooEarly: I.Record({ due_at: I.Nat64, earliest: I.Nat64 }),
+    NoVestingSchedule: I.Nat64,
+    GracePeriodRequired: I.Null,
+    GuardianMgrNotConfigured: I.Null,
+    Guardian: I.Text,
+    GuardianThresholdNotMet: I.Record({ vault_id: I.Nat64, submitted: I.Nat64 }),
+    VetoQuorumUnreachable: I.Record({ vault_id: I.Nat64, bound: I.Nat64, threshold: I.Nat64 }),
+    OwnerAlreadySet: I.Nat64,
+    ManagerNotConfigured: I.Null,
+    ManagerRoleReserved: I.Null,
+    Paused: I.Null,
+    Unauthorized: I.Principal,
+  });
   return I.Service({
     generate_vault_address: I.Func(
       [GenerateVaultAddressArgs],
-      [BitcoinAddressResponse],
+      [I.Variant({ Ok: BitcoinAddressResponse, Err: WalletErrorIdl })],
       [],
     ),
     execute_inheritance: I.Func(
       [ExecuteInheritanceArgs],
-      [ExecuteInheritanceResponse],
+      [I.Variant({ Ok: ExecuteInheritanceResponse, Err: WalletErrorIdl })],
       [],
     ),
     wallet_view: I.Func(
@@ -43,18 +97,33 @@ export const idlFactory: IDL.InterfaceFactory = ({ IDL }) => {
   });
 };
 
+/** One-key object naming the `WalletError` case, e.g. `{ VaultNotFound: 7n }`. */
+export type WalletError = { [kind: string]: unknown };
+
+export type WalletAccount = {
+  owner: Principal;
+  subaccount: [] | [number[]];
+};
+
+export type WalletHeirRecord = {
+  address: string;
+  weightBps: bigint;
+  account: [] | [WalletAccount];
+};
+
 export type BitcoinWalletActor = {
   generate_vault_address: (
-    payload: { vaultId: bigint; keyId: string },
-  ) => Promise<BitcoinAddressResponse>;
+    payload: { vaultId: bigint; keyId: string; owner: [] | [Principal] },
+  ) => Promise<CanisterResult<BitcoinAddressResponse, WalletError>>;
   execute_inheritance: (
     payload: {
       vaultId: bigint;
       keyId: string;
-      heirs: HeirRecord[];
+      heirs: WalletHeirRecord[];
       guardian_submissions: bigint;
+      waitForConfirmations: [] | [boolean];
     },
-  ) => Promise<{ txId: string }>;
+  ) => Promise<CanisterResult<{ txId: string }, WalletError>>;
   wallet_view: (
     vaultId: bigint,
   ) => Promise<BitcoinAddressResponse | null>;
@@ -1,5 +1,6 @@
 import { IDL } from "@dfinity/candid";
 import type { GuardianRecord, GuardianSubmissionResult } from "@/types/vault";
+import type { CanisterResult } from "@/lib/ic/result";
 
 export const idlFactory: IDL.InterfaceFactory = ({ IDL }) => {
   const I = IDL;
@@ -12,7 +13,7 @@ export const idlFactory: IDL.InterfaceFactory = ({ IDL }) => {
     emailHash: I.Vec(I.Nat8),
     alias: I.Text,
     status: GuardianStatusIdl,
-    principal: I.Opt(I.Principal),
+    principalId: I.Opt(I.Principal),
   });
   const GuardianRegistration = I.Record({
     email: I.Text,
@@ -43,20 +44,36 @@ export const idlFactory: IDL.InterfaceFactory = ({ IDL }) => {
     submittedAt: I.Nat64,
     remainingRequired: I.Int64,
   });
+  const GuardianErrorIdl = I.Variant({
+    VaultNotFound: I.Nat64,
+    VaultAlreadyRegistered: I.Nat64,
+    GuardianNotFound: I.Null,
+    PrincipalMismatch: I.Null,
+    GuardianNotAccepted: I.Null,
+    ShareAlreadySubmitted: I.Null,
+    ManagerNotConfigured: I.Null,
+    ManagerRoleReserved: I.Null,
+    Paused: I.Null,
+    Unauthorized: I.Principal,
+    ShareTooLarge: I.Record({ size: I.Nat64, max: I.Nat64 }),
+    Validation: I.Text,
+    CryptoError: I.Text,
+    RandomnessUnavailable: I.Null,
+  });
+  const ResultGuardians = I.Variant({
+    Ok: I.Vec(GuardianRecordIdl),
+    Err: GuardianErrorIdl,
+  });
   const ResultGuardian = I.Variant({
-    ok: GuardianRecordIdl,
-    err: I.Text,
+    Ok: GuardianRecordIdl,
+    Err: GuardianErrorIdl,
   });
   const ResultReceipt = I.Variant({
-    ok: ShareSubmissionReceipt,
-    err: I.Text,
+    Ok: ShareSubmissionReceipt,
+    Err: GuardianErrorIdl,
   });
   return I.Service({
-    register_guardians: I.Func(
-      [RegisterGuardiansArgs],
-      [I.Vec(GuardianRecordIdl)],
-      [],
-    ),
+    register_guardians: I.Func([RegisterGuardiansArgs], [ResultGuardians], []),
     accept_invitation: I.Func([AcceptGuardianArgs], [ResultGuardian], []),
     submit_guardian_share: I.Func([SubmitShareArgs], [ResultReceipt], []),
     guardian_threshold_status: I.Func(
@@ -87,13 +104,13 @@ export type GuardianActor = {
       keyId: string;
       invites: { email: string; alias: string }[];
     },
-  ) => Promise<GuardianRecord[]>;
+  ) => Promise<GuardianResult<GuardianRecord[]>>;
   accept_invitation: (
     payload: { vaultId: bigint; emailHash: number[] },
-  ) => Promise<{ ok?: GuardianRecord; err?: string }>;
+  ) => Promise<GuardianResult<GuardianRecord>>;
   submit_guardian_share: (
     payload: { vaultId: bigint; emailHash: number[]; sharePayload: number[] },
-  ) => Promise<{ ok?: ShareSubmissionReceipt; err?: string }>;
+  ) => Promise<GuardianResult<ShareSubmissionReceipt>>;
   guardian_threshold_status: (
     vaultId: bigint,
   ) => Promise<GuardianSubmissionResult>;
@@ -104,6 +121,24 @@ export type GuardianActor = {
   ) => Promise<GuardianRecord | null>;
 };
 
+export type GuardianError =
+  | { VaultNotFound: bigint }
+  | { VaultAlreadyRegistered: bigint }
+  | { GuardianNotFound: null }
+  | { PrincipalMismatch: null }
+  | { GuardianNotAccepted: null }
+  | { ShareAlreadySubmitted: null }
+  | { ManagerNotConfigured: null }
+  | { ManagerRoleReserved: null }
+  | { Paused: null }
+  | { Unauthorized: import("@dfinity/principal").Principal }
+  | { ShareTooLarge: { size: bigint; max: bigint } }
+  | { Validation: string }
+  | { CryptoError: string }
+  | { RandomnessUnavailable: null };
+
+export type GuardianResult<T> = CanisterResult<T, GuardianError>;
+
 export type ShareSubmissionReceipt = {
   vaultId: bigint;
   submittedAt: bigint;
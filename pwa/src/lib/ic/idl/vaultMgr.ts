@@ -1,9 +1,9 @@
 import { IDL } from "@dfinity/candid";
 import type {
-  ExecuteInheritanceResponse,
   GuardianSubmissionResult,
   HeirRecord,
   HeartbeatConfig,
+  ScheduleInheritanceResponse,
   VaultStatusResponse,
   VaultSummary,
 } from "@/types/vault";
@@ -67,9 +67,8 @@ export const idlFactory: IDL.InterfaceFactory = ({ IDL }) => {
     submitted: I.Nat,
     thresholdMet: I.Bool,
   });
-  const ExecuteInheritanceResponse = I.Record({
-    txId: I.Text,
-    broadcastAt: I.Int,
+  const ScheduleInheritanceResponse = I.Record({
+    executeAt: I.Int,
   });
   return I.Service({
     create_vault: I.Func([CreateVaultRequest], [VaultSummary], []),
@@ -77,11 +76,12 @@ export const idlFactory: IDL.InterfaceFactory = ({ IDL }) => {
     get_vault_status: I.Func([VaultId], [VaultStatusResponse], []),
     request_inheritance: I.Func([VaultId], [VaultSummary], []),
     heartbeat_missed: I.Func([VaultId], [VaultSummary], []),
-    execute_inheritance: I.Func(
+    schedule_inheritance: I.Func(
       [VaultId],
-      [ExecuteInheritanceResponse],
+      [ScheduleInheritanceResponse],
       [],
     ),
+    sync_inheritance: I.Func([VaultId], [VaultSummary], []),
     guardian_threshold_status: I.Func(
       [VaultId],
       [GuardianSubmissionResult],
@@ -110,9 +110,10 @@ export type VaultActor = {
   get_vault_status: (vaultId: bigint) => Promise<VaultStatusResponse>;
   request_inheritance: (vaultId: bigint) => Promise<VaultSummary>;
   heartbeat_missed: (vaultId: bigint) => Promise<VaultSummary>;
-  execute_inheritance: (
+  schedule_inheritance: (
     vaultId: bigint,
-  ) => Promise<ExecuteInheritanceResponse>;
+  ) => Promise<ScheduleInheritanceResponse>;
+  sync_inheritance: (vaultId: bigint) => Promise<VaultSummary>;
   guardian_threshold_status: (
     vaultId: bigint,
   ) => Promise<GuardianSubmissionResult>;
@@ -0,0 +1,36 @@
+import { Principal } from "@dfinity/principal";
+
+/** Candid `variant { Ok : T; Err : E }` as decoded by agent-js. */
+export type CanisterResult<T, E> = { Ok: T } | { Err: E };
+
+function stringify(value: unknown): string {
+  if (value instanceof Principal) {
+    return value.toText();
+  }
+  if (typeof value === "object") {
+    return JSON.stringify(value, (_, inner) =>
+      typeof inner === "bigint"
+        ? inner.toString()
+        : inner instanceof Principal
+          ? inner.toText()
+          : inner,
+    );
+  }
+  return String(value);
+}
+
+/** Renders an error variant as `Kind` or `Kind: payload`. */
+export function describeVariant(error: object): string {
+  const [kind, detail] = Object.entries(error)[0] ?? ["Unknown", null];
+  if (detail === null || detail === undefined) {
+    return kind;
+  }
+  return `${kind}: ${stringify(detail)}`;
+}
+
+export function unwrapResult<T, E extends object>(result: CanisterResult<T, E>): T {
+  if ("Err" in result) {
+    throw new Error(describeVariant(result.Err));
+  }
+  return result.Ok;
+}
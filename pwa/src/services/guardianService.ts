@@ -1,6 +1,7 @@
 'use client';
 
 import { getGuardianActor } from "@/services/icActors";
+import { unwrapResult } from "@/lib/ic/result";
 import type { GuardianRecord, GuardianSubmissionResult, ShareSubmissionReceipt } from "@/types/vault";
 
 export async function registerGuardians(payload: {
@@ -11,7 +12,7 @@ export async function registerGuardians(payload: {
   invites: { email: string; alias: string }[];
 }): Promise<GuardianRecord[]> {
   const actor = await getGuardianActor();
-  return actor.register_guardians(payload);
+  return unwrapResult(await actor.register_guardians(payload));
 }
 
 export async function listGuardians(vaultId: bigint) {
@@ -30,13 +31,7 @@ export async function acceptInvitation(vaultId: bigint, emailHash: Uint8Array):
     vaultId,
     emailHash: Array.from(emailHash),
   });
-  if ('err' in result) {
-    throw new Error(result.err);
-  }
-  if (!result.ok) {
-    throw new Error("Unknown error");
-  }
-  return result.ok;
+  return unwrapResult(result);
 }
 
 export async function submitGuardianShare(
@@ -50,13 +45,7 @@ export async function submitGuardianShare(
     emailHash: Array.from(emailHash),
     sharePayload: Array.from(sharePayload),
   });
-  if ('err' in result) {
-    throw new Error(result.err);
-  }
-  if (!result.ok) {
-    throw new Error("Unknown error");
-  }
-  return result.ok;
+  return unwrapResult(result);
 }
 
 export async function getGuardianByHash(vaultId: bigint, emailHash: Uint8Array): Promise<GuardianRecord | null> {
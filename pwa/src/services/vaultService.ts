@@ -5,6 +5,7 @@ import { getVaultActor } from "@/services/icActors";
 import type {
   CreateVaultPayload,
   GuardianSubmissionResult,
+  ScheduleInheritanceResponse,
   VaultStatusResponse,
   VaultSummary,
 } from "@/types/vault";
@@ -42,11 +43,16 @@ export async function getVaultStatus(
   return actor.get_vault_status(vaultId);
 }
 
-export async function executeInheritance(
+export async function scheduleInheritance(
   vaultId: bigint,
-): Promise<void> {
+): Promise<ScheduleInheritanceResponse> {
   const actor = await getVaultActor();
-  await actor.execute_inheritance(vaultId);
+  return actor.schedule_inheritance(vaultId);
+}
+
+export async function syncInheritance(vaultId: bigint): Promise<VaultSummary> {
+  const actor = await getVaultActor();
+  return actor.sync_inheritance(vaultId);
 }
 
 export async function guardianSubmissionStatus(
@@ -1,6 +1,7 @@
 'use client';
 
 import { getBitcoinWalletActor } from "@/services/icActors";
+import { unwrapResult } from "@/lib/ic/result";
 import type { HeirRecord } from "@/types/vault";
 
 export async function fetchVaultAddress(vaultId: bigint) {
@@ -15,13 +16,16 @@ export async function executeInheritanceTx(payload: {
   guardianSubmissions: bigint;
 }) {
   const actor = await getBitcoinWalletActor();
-  return actor.execute_inheritance({
+  const result = await actor.execute_inheritance({
     vaultId: payload.vaultId,
     keyId: payload.keyId,
     heirs: payload.heirs.map((heir) => ({
       address: heir.address,
       weightBps: BigInt(heir.weightBps),
+      account: [],
     })),
     guardian_submissions: payload.guardianSubmissions,
+    waitForConfirmations: [],
   });
+  return unwrapResult(result);
 }
@@ -51,9 +51,8 @@ export interface CreateVaultPayload {
   heartbeat: HeartbeatConfig;
 }
 
-export interface ExecuteInheritanceResponse {
-  txId: string;
-  broadcastAt: bigint;
+export interface ScheduleInheritanceResponse {
+  executeAt: bigint;
 }
 
 export interface ShareSubmissionReceipt {
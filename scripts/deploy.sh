@@ -10,9 +10,18 @@ if [ "$NETWORK" == "local" ]; then
     dfx ledger fabricate-cycles --all --t 10 --network "$NETWORK"
 fi
 
+# The ckBTC stub stands in for the minter locally; elsewhere the real minter is configured
+# with `set_ckbtc_minter`.
+CANISTERS="vault_mgr heartbeat_tracker guardian_mgr bitcoin_wallet pwa_frontend"
+if [ "$NETWORK" == "local" ]; then
+  CANISTERS="$CANISTERS ckbtc_stub"
+fi
+
 # 1. Create Canisters (Generate IDs)
 echo "🏗️  Creating Canisters..."
-dfx canister create --all --network "$NETWORK"
+for CANISTER in $CANISTERS; do
+  dfx canister create "$CANISTER" --network "$NETWORK"
+done
 
 # 2. Generate Environment Variables for Frontend
 echo "📝 Generating Environment Variables..."
@@ -51,7 +60,9 @@ echo "   Admin: $ADMIN_ID"
 
 # 3. Build Canisters (Backend)
 echo "🔨 Building Canisters..."
-dfx build --network "$NETWORK"
+for CANISTER in $CANISTERS; do
+  dfx build "$CANISTER" --network "$NETWORK"
+done
 
 # 4. Build Frontend (Now has IDs)
 echo "📦 Building PWA Frontend..."
@@ -62,20 +73,26 @@ cd ..
 
 # 5. Install Canisters (Order Matters for Dependencies)
 
+# Rust canisters refuse sensitive calls until a vault manager is configured
+MANAGER_INIT_ARG="(opt record { vault_manager = opt principal \"$VAULT_MGR_ID\" })"
+
 # Install Bitcoin Wallet
 echo "💾 Installing Bitcoin Wallet..."
 if [ "$NETWORK" == "local" ]; then
-  dfx canister install bitcoin_wallet --mode reinstall --yes --network "$NETWORK"
+  dfx canister install ckbtc_stub --argument "(null)" --mode reinstall --yes --network "$NETWORK"
+  CKBTC_STUB_ID=$(dfx canister id ckbtc_stub --network "$NETWORK")
+  WALLET_INIT_ARG="(opt record { vault_manager = opt principal \"$VAULT_MGR_ID\"; network = opt variant { regtest }; ckbtc_minter = opt principal \"$CKBTC_STUB_ID\" })"
+  dfx canister install bitcoin_wallet --argument "$WALLET_INIT_ARG" --mode reinstall --yes --network "$NETWORK"
 else
-  dfx canister install bitcoin_wallet --yes --network "$NETWORK"
+  dfx canister install bitcoin_wallet --argument "$MANAGER_INIT_ARG" --yes --network "$NETWORK"
 fi
 
 # Install Guardian Mgr
 echo "💾 Installing Guardian Manager..."
 if [ "$NETWORK" == "local" ]; then
-  dfx canister install guardian_mgr --mode reinstall --yes --network "$NETWORK"
+  dfx canister install guardian_mgr --argument "$MANAGER_INIT_ARG" --mode reinstall --yes --network "$NETWORK"
 else
-  dfx canister install guardian_mgr --yes --network "$NETWORK"
+  dfx canister install guardian_mgr --argument "$MANAGER_INIT_ARG" --yes --network "$NETWORK"
 fi
 
 # Install Heartbeat Tracker (Depends on VaultMgr ID)
@@ -3,6 +3,14 @@ set -e
 
 NETWORK=${1:-ic}
 
+# Scheduled payouts wait this long for the owner or a guardian quorum to veto them. Locally a
+# few minutes keep the flow testable; override with GRACE_PERIOD_SECS.
+if [ "$NETWORK" == "local" ]; then
+  GRACE_PERIOD_SECS=${GRACE_PERIOD_SECS:-300}
+else
+  GRACE_PERIOD_SECS=${GRACE_PERIOD_SECS:-604800}
+fi
+
 # Get canister IDs
 VAULT_MGR_ID=$(dfx canister id vault_mgr --network "$NETWORK")
 BITCOIN_WALLET_ID=$(dfx canister id bitcoin_wallet --network "$NETWORK")
@@ -20,5 +28,13 @@ dfx canister call bitcoin_wallet set_vault_manager "(principal \"$VAULT_MGR_ID\"
 echo "Setting VaultMgr as manager for GuardianMgr..."
 dfx canister call guardian_mgr set_vault_manager "(principal \"$VAULT_MGR_ID\")" --network "$NETWORK"
 
+# BitcoinWallet reads guardian quorums from GuardianMgr to count cancellation votes
+echo "Linking BitcoinWallet to GuardianMgr..."
+dfx canister call bitcoin_wallet set_guardian_mgr "(principal \"$GUARDIAN_MGR_ID\")" --network "$NETWORK"
+dfx canister call guardian_mgr grant_role "(principal \"$BITCOIN_WALLET_ID\", variant { Auditor })" --network "$NETWORK"
+
+echo "Setting the BitcoinWallet grace period to $GRACE_PERIOD_SECS seconds..."
+dfx canister call bitcoin_wallet set_grace_period "($GRACE_PERIOD_SECS : nat64)" --network "$NETWORK"
+
 echo "Access control initialization complete."
 
@@ -0,0 +1,10 @@
+#!/bin/bash
+set -e
+
+# Runs the vault manager's Motoko tests in the interpreter of the compiler dfx ships with.
+DFX_CACHE=$(dfx cache show)
+
+for TEST in canisters/vault_mgr/test/*.test.mo; do
+  echo "🧪 $TEST"
+  "$DFX_CACHE/moc" --package base "$DFX_CACHE/base" -r "$TEST"
+done